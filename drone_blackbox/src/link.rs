@@ -0,0 +1,85 @@
+use drone_flight::protocol::{Command, MAX_FRAME, Request, Response, SYNC, Status};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::process;
+
+const RETRIES: usize = 3;
+
+/// Request/response exchange over the CDC ACM app port.
+pub struct Link {
+    port: File,
+    seq: u8,
+}
+
+impl Link {
+    pub fn open(tty: &str) -> Result<Link, String> {
+        // Raw mode, reads give up after one second of silence
+        let stty = process::Command::new("stty")
+            .args(["-F", tty, "raw", "-echo", "min", "0", "time", "10"])
+            .status()
+            .map_err(|e| format!("stty: {e}"))?;
+        if !stty.success() {
+            return Err(format!("stty failed on {tty}"));
+        }
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tty)
+            .map_err(|e| format!("{tty}: {e}"))?;
+        Ok(Link { port, seq: 0 })
+    }
+
+    pub fn call(&mut self, cmd: Command, payload: &[u8]) -> Result<Response, String> {
+        let mut last_error = String::new();
+        for _ in 0..RETRIES {
+            match self.exchange(cmd, payload) {
+                Ok(response) if response.status == Status::Ok => return Ok(response),
+                Ok(response) => return Err(format!("{:?}: {:?}", cmd, response.status)),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn exchange(&mut self, cmd: Command, payload: &[u8]) -> Result<Response, String> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut frame = [0; MAX_FRAME];
+        let len = Request::new(cmd as u8, self.seq, payload).encode(&mut frame);
+        self.port
+            .write_all(&frame[..len])
+            .map_err(|e| format!("write: {e}"))?;
+
+        loop {
+            let mut header = [0u8; 5];
+            self.read_exact(&mut header[..1])?;
+            if header[0] != SYNC {
+                continue;
+            }
+            self.read_exact(&mut header[1..])?;
+            let len = 5 + header[4] as usize + 2;
+            if len > MAX_FRAME {
+                continue;
+            }
+            let mut frame = [0u8; MAX_FRAME];
+            frame[..5].copy_from_slice(&header);
+            self.read_exact(&mut frame[5..len])?;
+            match Response::decode(&frame[..len]) {
+                Some(response) if response.seq == self.seq => return Ok(response),
+                Some(_) => continue,
+                None => return Err("corrupt response".into()),
+            }
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.port.read(&mut buf[filled..]) {
+                Ok(0) => return Err("timeout".into()),
+                Ok(n) => filled += n,
+                Err(e) => return Err(format!("read: {e}")),
+            }
+        }
+        Ok(())
+    }
+}
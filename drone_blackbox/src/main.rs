@@ -0,0 +1,96 @@
+//! Downloads the blackbox over the USB app port and converts dumps to CSV.
+
+mod link;
+
+use drone_flight::blackbox::{self, Event, Record};
+use drone_flight::protocol::{Command, MAX_PAYLOAD};
+use link::Link;
+use std::{env, fs, process};
+
+fn usage() -> ! {
+    eprintln!("usage:");
+    eprintln!("  drone_blackbox download <tty> <dump.bin>");
+    eprintln!("  drone_blackbox erase <tty>");
+    eprintln!("  drone_blackbox csv <dump.bin>");
+    process::exit(2)
+}
+
+fn download(tty: &str, path: &str) -> Result<(), String> {
+    let mut link = Link::open(tty)?;
+    let info = link.call(Command::BlackboxInfo, &[])?;
+    let [s0, s1, z0, z1, z2, z3, recording] = *info.payload() else {
+        return Err("malformed info response".into());
+    };
+    let size =
+        u16::from_le_bytes([s0, s1]) as usize * u32::from_le_bytes([z0, z1, z2, z3]) as usize;
+    if recording != 0 {
+        eprintln!("warning: still recording, the last session may be cut short");
+    }
+
+    let mut dump = Vec::with_capacity(size);
+    while dump.len() < size {
+        let len = (size - dump.len()).min(MAX_PAYLOAD);
+        let mut payload = (dump.len() as u32).to_le_bytes().to_vec();
+        payload.push(len as u8);
+        let response = link.call(Command::BlackboxRead, &payload)?;
+        dump.extend_from_slice(response.payload());
+        if dump.len() % (64 * 1024) == 0 {
+            eprintln!("{} / {} KiB", dump.len() / 1024, size / 1024);
+        }
+    }
+
+    fs::write(path, &dump).map_err(|e| format!("{path}: {e}"))
+}
+
+fn erase(tty: &str) -> Result<(), String> {
+    Link::open(tty)?.call(Command::BlackboxErase, &[])?;
+    eprintln!("erase started, takes about half a minute");
+    Ok(())
+}
+
+fn csv(path: &str) -> Result<(), String> {
+    let dump = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut session = 0;
+    let mut fields = None;
+
+    blackbox::decode(&dump, |record| match record {
+        Record::Start {
+            time_ms,
+            rate_hz,
+            fields: f,
+        } => {
+            session += 1;
+            eprintln!("session {session}: t={time_ms} ms, {rate_hz} Hz");
+            if fields.is_none() {
+                let names: Vec<_> = f.iter().map(|(name, _)| name).collect();
+                println!("session,time_ms,event,{}", names.join(","));
+            }
+            fields = Some(f);
+        }
+        Record::Frame { time_ms, data } => {
+            let Some(fields) = fields else { return };
+            let values: Vec<_> = fields.values(data).map(|v| v.to_string()).collect();
+            println!("{session},{time_ms},,{}", values.join(","));
+        }
+        Record::Event { time_ms, event } => {
+            let name = Event::try_from(event).map_or("UNKNOWN", |e| e.name());
+            println!("{session},{time_ms},{name},");
+        }
+    });
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["download", tty, path] => download(tty, path),
+        ["erase", tty] => erase(tty),
+        ["csv", path] => csv(path),
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+}
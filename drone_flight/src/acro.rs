@@ -0,0 +1,26 @@
+use crate::{rc::RcData, switch::SwitchingPolicy};
+
+pub struct Acro;
+
+impl SwitchingPolicy for Acro {
+    type SafetyContext = bool; // self-leveling required, e.g. failsafe descent
+
+    const NAME: &'static str = "ACRO";
+    const ON_TICKS: u64 = 10;
+    const OFF_TICKS: u64 = 10;
+
+    #[inline(always)]
+    fn want_on(rc: &RcData) -> bool {
+        rc.acro_switch() > 0.5
+    }
+
+    #[inline(always)]
+    fn want_off(rc: &RcData) -> bool {
+        rc.acro_switch() < 0.5
+    }
+
+    #[inline(always)]
+    fn force_off(_: &RcData, level: bool) -> bool {
+        level
+    }
+}
@@ -47,4 +47,15 @@ impl AltitudeEstimator {
 
         self.estimated_alt
     }
+
+    /// Estimated climb rate in m/s.
+    pub fn velocity(&self) -> f32 {
+        self.velocity_z
+    }
+}
+
+impl Default for AltitudeEstimator {
+    fn default() -> AltitudeEstimator {
+        AltitudeEstimator::new()
+    }
 }
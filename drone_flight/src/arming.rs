@@ -0,0 +1,58 @@
+use crate::rc::RcData;
+use crate::{
+    consts::{ARM_HOLD_TICKS, DISARM_HOLD_TICKS},
+    switch::SwitchingPolicy,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use portable_atomic::AtomicBool;
+
+pub static DISARMED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+pub static ARMED: AtomicBool = AtomicBool::new(false);
+/// Arm (true) or disarm requests from a ground station, the outcome comes back on `ARM_RESULT`.
+pub static ARM_REQUEST: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+pub static ARM_RESULT: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+pub struct Arming;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ArmingContext {
+    pub rc_valid: bool,
+    /// Failing pre-arm checks, see `prearm::checks`.
+    pub prearm_failures: u16,
+}
+
+impl SwitchingPolicy for Arming {
+    type SafetyContext = ArmingContext;
+
+    const NAME: &'static str = "ARMING";
+    const ON_TICKS: u64 = ARM_HOLD_TICKS;
+    const OFF_TICKS: u64 = DISARM_HOLD_TICKS;
+
+    const OFF_SIGNAL: Option<&'static Signal<CriticalSectionRawMutex, ()>> = Some(&DISARMED);
+    const ACTIVE_FLAG: Option<&'static AtomicBool> = Some(&ARMED);
+
+    #[inline(always)]
+    fn want_on(rc: &RcData) -> bool {
+        rc.throttle() < 0.1 && rc.arm_switch() > 0.5
+    }
+
+    #[inline(always)]
+    fn switch_on(rc: &RcData) -> bool {
+        rc.arm_switch() > 0.5
+    }
+
+    #[inline(always)]
+    fn want_off(rc: &RcData) -> bool {
+        rc.arm_switch() < 0.5
+    }
+
+    #[inline(always)]
+    fn force_off(_: &RcData, ctx: ArmingContext) -> bool {
+        !ctx.rc_valid // Safety trip: lost RC signal
+    }
+
+    #[inline(always)]
+    fn may_on(ctx: &ArmingContext) -> bool {
+        ctx.prearm_failures == 0
+    }
+}
@@ -1,4 +1,4 @@
-use crate::consts::{AHRS_BETA, CYCLE_TIME};
+use crate::consts::CYCLE_TIME;
 use ahrs::{Ahrs, Madgwick};
 use nalgebra::{UnitQuaternion, Vector3};
 
@@ -7,12 +7,16 @@ pub struct Attitude {
 }
 
 impl Attitude {
-    pub fn new() -> Attitude {
+    pub fn new(beta: f32) -> Attitude {
         Attitude {
-            ahrs: Madgwick::new(CYCLE_TIME, AHRS_BETA),
+            ahrs: Madgwick::new(CYCLE_TIME, beta),
         }
     }
 
+    pub fn set_beta(&mut self, beta: f32) {
+        *self.ahrs.beta_mut() = beta;
+    }
+
     pub fn update(
         &mut self,
         gyr: &Vector3<f32>,
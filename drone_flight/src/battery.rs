@@ -0,0 +1,224 @@
+use crate::consts::{
+    BATTERY_CELL_MAX, BATTERY_HYSTERESIS, BATTERY_LPF_HZ, BATTERY_MIN_V, BATTERY_SETTLE_S,
+    BATTERY_WARN_S,
+};
+use crate::filter::Pt1;
+use crate::params::Params;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    #[default]
+    Ok = 0,
+    /// Under `bat_low_cell`, time to land.
+    Low = 1,
+    /// Under `bat_crit_cell`, the failsafe lands.
+    Critical = 2,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BatteryState {
+    /// Pack voltage, low-passed.
+    pub voltage: f32,
+    /// Voltage with the sag of the present current added back.
+    pub compensated: f32,
+    /// Amps, `None` without a current sensor.
+    pub current: Option<f32>,
+    pub mah: f32,
+    /// 0 until counted, and without a battery.
+    pub cells: u8,
+    pub level: Level,
+}
+
+impl BatteryState {
+    pub fn cell_voltage(&self) -> f32 {
+        if self.cells == 0 {
+            return 0.0;
+        }
+        self.compensated / self.cells as f32
+    }
+}
+
+/// Turns raw pack voltage and current readings into the battery state and warnings.
+pub struct BatteryMonitor {
+    cells: u8,
+    low: f32,
+    critical: f32,
+    resistance: f32,
+    sample_hz: f32,
+    voltage_lp: Pt1,
+    current_lp: Pt1,
+    samples: u32,
+    settle_samples: u32,
+    warn_samples: u32,
+    pending: u32,
+    state: BatteryState,
+}
+
+impl BatteryMonitor {
+    pub fn new(params: &Params, sample_hz: f32) -> BatteryMonitor {
+        BatteryMonitor {
+            cells: params.bat_cells as u8,
+            low: params.bat_low_cell,
+            critical: params.bat_crit_cell,
+            resistance: params.bat_resistance,
+            sample_hz,
+            voltage_lp: Pt1::new(BATTERY_LPF_HZ, sample_hz),
+            current_lp: Pt1::new(BATTERY_LPF_HZ, sample_hz),
+            samples: 0,
+            settle_samples: (BATTERY_SETTLE_S * sample_hz) as u32,
+            warn_samples: (BATTERY_WARN_S * sample_hz) as u32,
+            pending: 0,
+            state: BatteryState::default(),
+        }
+    }
+
+    pub fn update(&mut self, voltage: f32, current: Option<f32>) -> &BatteryState {
+        if self.samples == 0 {
+            self.voltage_lp.reset(voltage);
+            self.current_lp.reset(current.unwrap_or(0.0));
+        }
+        self.samples = self.samples.saturating_add(1);
+
+        let state = &mut self.state;
+        state.voltage = self.voltage_lp.apply(voltage);
+        state.current = current.map(|amps| self.current_lp.apply(amps));
+        let amps = state.current.unwrap_or(0.0);
+        // A for one sample, in mAh
+        state.mah += amps * 1000.0 / 3600.0 / self.sample_hz;
+        state.compensated = state.voltage + amps * self.resistance;
+
+        if state.cells == 0 && self.samples >= self.settle_samples && state.voltage > BATTERY_MIN_V
+        {
+            state.cells = match self.cells {
+                0 => libm::ceilf(state.voltage / BATTERY_CELL_MAX) as u8,
+                cells => cells,
+            };
+            log::info!("[BATTERY] {}S, {:.2} V", state.cells, state.voltage);
+        }
+
+        let level = self.level();
+        if level == self.state.level {
+            self.pending = 0;
+        } else {
+            self.pending += 1;
+            if self.pending >= self.warn_samples {
+                self.pending = 0;
+                self.state.level = level;
+                let cell = self.state.cell_voltage();
+                match level {
+                    Level::Ok => log::info!("[BATTERY] ok, {:.2} V/cell", cell),
+                    Level::Low => log::warn!("[BATTERY] low, {:.2} V/cell", cell),
+                    Level::Critical => log::warn!("[BATTERY] critical, {:.2} V/cell", cell),
+                }
+            }
+        }
+        &self.state
+    }
+
+    pub fn state(&self) -> &BatteryState {
+        &self.state
+    }
+
+    /// Level of the present reading, leaving a warning takes `BATTERY_HYSTERESIS` more.
+    fn level(&self) -> Level {
+        if self.state.cells == 0 {
+            return Level::Ok;
+        }
+        let cell = self.state.cell_voltage();
+        let margin = |level| {
+            if self.state.level >= level {
+                BATTERY_HYSTERESIS
+            } else {
+                0.0
+            }
+        };
+        if cell < self.critical + margin(Level::Critical) {
+            Level::Critical
+        } else if cell < self.low + margin(Level::Low) {
+            Level::Low
+        } else {
+            Level::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HZ: f32 = 50.0;
+
+    fn run(monitor: &mut BatteryMonitor, seconds: f32, voltage: f32, current: Option<f32>) {
+        for _ in 0..(seconds * HZ) as usize {
+            monitor.update(voltage, current);
+        }
+    }
+
+    #[test]
+    fn counts_cells_once_settled() {
+        for (voltage, cells) in [(16.4, 4), (12.3, 3), (25.2, 6), (0.4, 0)] {
+            let mut monitor = BatteryMonitor::new(&Params::defaults(), HZ);
+            run(&mut monitor, 0.5, voltage, None);
+            assert_eq!(monitor.state().cells, 0);
+            run(&mut monitor, 1.0, voltage, None);
+            assert_eq!(monitor.state().cells, cells, "{voltage}");
+            assert_eq!(monitor.state().level, Level::Ok);
+        }
+
+        // A storage charged 4S looks like 3S, the param says otherwise
+        let params = Params {
+            bat_cells: 4.0,
+            ..Params::defaults()
+        };
+        let mut monitor = BatteryMonitor::new(&params, HZ);
+        run(&mut monitor, 1.5, 15.2, None);
+        assert_eq!(monitor.state().cells, 4);
+    }
+
+    #[test]
+    fn integrates_consumed_mah() {
+        let mut monitor = BatteryMonitor::new(&Params::defaults(), HZ);
+        run(&mut monitor, 36.0, 16.0, Some(10.0));
+        assert!((monitor.state().mah - 100.0).abs() < 0.5);
+
+        let mut monitor = BatteryMonitor::new(&Params::defaults(), HZ);
+        run(&mut monitor, 36.0, 16.0, None);
+        assert_eq!(monitor.state().mah, 0.0);
+    }
+
+    #[test]
+    fn sag_is_compensated() {
+        let params = Params {
+            bat_resistance: 0.02,
+            ..Params::defaults()
+        };
+        let mut monitor = BatteryMonitor::new(&params, HZ);
+        run(&mut monitor, 3.0, 14.0, Some(30.0));
+        let state = monitor.state();
+        assert!((state.compensated - 14.6).abs() < 0.01);
+        assert_eq!(state.cells, 4);
+        assert_eq!(state.level, Level::Ok);
+    }
+
+    #[test]
+    fn warnings_need_time_and_hysteresis() {
+        let mut monitor = BatteryMonitor::new(&Params::defaults(), HZ);
+        run(&mut monitor, 2.0, 16.0, None);
+
+        // A short dip is not a warning
+        run(&mut monitor, 1.0, 13.6, None);
+        run(&mut monitor, 1.0, 16.0, None);
+        assert_eq!(monitor.state().level, Level::Ok);
+
+        run(&mut monitor, 3.0, 13.6, None);
+        assert_eq!(monitor.state().level, Level::Low);
+        // Recovering just above the threshold isn't enough
+        run(&mut monitor, 5.0, 14.2, None);
+        assert_eq!(monitor.state().level, Level::Low);
+        run(&mut monitor, 5.0, 12.8, None);
+        assert_eq!(monitor.state().level, Level::Critical);
+        run(&mut monitor, 5.0, 15.0, None);
+        assert_eq!(monitor.state().level, Level::Ok);
+    }
+}
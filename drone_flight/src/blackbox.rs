@@ -0,0 +1,757 @@
+// Log layout, a ring of flash sectors:
+// Sector: SECTOR_MAGIC u32, sequence u32, records back to back, erased (0xFF) tail.
+// Record: tag u8, time_ms u32, body
+//   TAG_START: rate_hz u16, field count u8, per field: scale f32, name len u8, name
+//   TAG_FRAME: field count * i16, value * scale
+//   TAG_EVENT: event u8
+// A session starts on a fresh sector at arming and may span several sectors.
+
+use crate::mixer::MAX_MOTORS;
+
+pub const SECTOR_SIZE: usize = 4096;
+const PAGE_SIZE: usize = 256;
+const SECTOR_MAGIC: u32 = 0x3158_4242; // "BBX1"
+const SECTOR_HEADER_SIZE: usize = 8;
+const ERASED: u32 = 0xffff_ffff;
+
+const TAG_START: u8 = 0x01;
+const TAG_FRAME: u8 = 0x02;
+const TAG_EVENT: u8 = 0x03;
+const RECORD_HEADER_SIZE: usize = 5;
+const EVENT_SIZE: usize = RECORD_HEADER_SIZE + 1;
+const START_MAX_SIZE: usize = 512;
+pub const FRAME_SIZE: usize = RECORD_HEADER_SIZE + FIELD_COUNT * 2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub scale: f32,
+}
+
+const fn field(name: &'static str, scale: f32) -> Field {
+    Field { name, scale }
+}
+
+pub const FIELDS: &[Field] = &[
+    field("gyro_x", 1000.0),
+    field("gyro_y", 1000.0),
+    field("gyro_z", 1000.0),
+    field("acc_x", 100.0),
+    field("acc_y", 100.0),
+    field("acc_z", 100.0),
+    field("roll", 10000.0),
+    field("pitch", 10000.0),
+    field("yaw", 10000.0),
+    field("alt", 100.0),
+    field("rc_roll", 10000.0),
+    field("rc_pitch", 10000.0),
+    field("rc_throttle", 10000.0),
+    field("rc_yaw", 10000.0),
+    field("pid_roll", 10000.0),
+    field("pid_pitch", 10000.0),
+    field("pid_yaw", 10000.0),
+    field("pid_alt", 10000.0),
+    field("motor1", 1.0),
+    field("motor2", 1.0),
+    field("motor3", 1.0),
+    field("motor4", 1.0),
+    field("motor5", 1.0),
+    field("motor6", 1.0),
+    field("servo", 1.0),
+];
+
+pub const FIELD_COUNT: usize = FIELDS.len();
+
+pub mod modes {
+    pub const ARMED: u8 = 1 << 0;
+    pub const ALT_HOLD: u8 = 1 << 1;
+    /// RC lost, flying on stand-in sticks.
+    pub const FAILSAFE: u8 = 1 << 2;
+    /// Failsafe descent stage.
+    pub const FS_DESCENT: u8 = 1 << 3;
+    pub const ACRO: u8 = 1 << 4;
+    pub const HORIZON: u8 = 1 << 5;
+    /// A motor stalled or lost sync according to its ESC.
+    pub const MOTOR_FAULT: u8 = 1 << 6;
+    /// Battery low or critical.
+    pub const BATTERY_LOW: u8 = 1 << 7;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Event {
+    Armed = 1,
+    Disarmed = 2,
+    AltHoldOn = 3,
+    AltHoldOff = 4,
+    FramesDropped = 5,
+    FailsafeOn = 6,
+    FailsafeOff = 7,
+    DescentOn = 8,
+    DescentOff = 9,
+    AcroOn = 10,
+    AcroOff = 11,
+    HorizonOn = 12,
+    HorizonOff = 13,
+    MotorFaultOn = 14,
+    MotorFaultOff = 15,
+    BatteryLowOn = 16,
+    BatteryLowOff = 17,
+    /// Ran out of erased sectors, nothing more is logged until disarmed.
+    LogFull = 18,
+    /// The flight loop missed a tick.
+    LoopOverrun = 19,
+}
+
+// (mode bit, event when set, event when cleared)
+const MODE_EVENTS: &[(u8, Event, Event)] = &[
+    (modes::ARMED, Event::Armed, Event::Disarmed),
+    (modes::ALT_HOLD, Event::AltHoldOn, Event::AltHoldOff),
+    (modes::FAILSAFE, Event::FailsafeOn, Event::FailsafeOff),
+    (modes::FS_DESCENT, Event::DescentOn, Event::DescentOff),
+    (modes::ACRO, Event::AcroOn, Event::AcroOff),
+    (modes::HORIZON, Event::HorizonOn, Event::HorizonOff),
+    (
+        modes::MOTOR_FAULT,
+        Event::MotorFaultOn,
+        Event::MotorFaultOff,
+    ),
+    (
+        modes::BATTERY_LOW,
+        Event::BatteryLowOn,
+        Event::BatteryLowOff,
+    ),
+];
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Armed => "ARMED",
+            Event::Disarmed => "DISARMED",
+            Event::AltHoldOn => "ALT_HOLD_ON",
+            Event::AltHoldOff => "ALT_HOLD_OFF",
+            Event::FramesDropped => "FRAMES_DROPPED",
+            Event::FailsafeOn => "FAILSAFE_ON",
+            Event::FailsafeOff => "FAILSAFE_OFF",
+            Event::DescentOn => "FS_DESCENT_ON",
+            Event::DescentOff => "FS_DESCENT_OFF",
+            Event::AcroOn => "ACRO_ON",
+            Event::AcroOff => "ACRO_OFF",
+            Event::HorizonOn => "HORIZON_ON",
+            Event::HorizonOff => "HORIZON_OFF",
+            Event::MotorFaultOn => "MOTOR_FAULT_ON",
+            Event::MotorFaultOff => "MOTOR_FAULT_OFF",
+            Event::BatteryLowOn => "BATTERY_LOW_ON",
+            Event::BatteryLowOff => "BATTERY_LOW_OFF",
+            Event::LogFull => "LOG_FULL",
+            Event::LoopOverrun => "LOOP_OVERRUN",
+        }
+    }
+
+    /// Events for every mode bit that differs between two snapshots.
+    pub fn from_modes(prev: u8, now: u8) -> impl Iterator<Item = Event> {
+        MODE_EVENTS
+            .iter()
+            .filter(move |(bit, _, _)| (prev ^ now) & bit != 0)
+            .map(move |(bit, on, off)| if now & bit != 0 { *on } else { *off })
+    }
+}
+
+impl TryFrom<u8> for Event {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Event, ()> {
+        match value {
+            1 => Ok(Event::Armed),
+            2 => Ok(Event::Disarmed),
+            3 => Ok(Event::AltHoldOn),
+            4 => Ok(Event::AltHoldOff),
+            5 => Ok(Event::FramesDropped),
+            6 => Ok(Event::FailsafeOn),
+            7 => Ok(Event::FailsafeOff),
+            8 => Ok(Event::DescentOn),
+            9 => Ok(Event::DescentOff),
+            10 => Ok(Event::AcroOn),
+            11 => Ok(Event::AcroOff),
+            12 => Ok(Event::HorizonOn),
+            13 => Ok(Event::HorizonOff),
+            14 => Ok(Event::MotorFaultOn),
+            15 => Ok(Event::MotorFaultOff),
+            16 => Ok(Event::BatteryLowOn),
+            17 => Ok(Event::BatteryLowOff),
+            18 => Ok(Event::LogFull),
+            19 => Ok(Event::LoopOverrun),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Latest state of the flight loop, sampled into blackbox frames.
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    pub gyro: [f32; 3],
+    pub acc: [f32; 3],
+    pub att: [f32; 3],
+    pub alt: f32,
+    pub rc: [f32; 4],
+    pub pid: [f32; 4],
+    pub motors: [u16; MAX_MOTORS],
+    pub servo: u16,
+    pub modes: u8,
+}
+
+impl Snapshot {
+    fn values(&self) -> [f32; FIELD_COUNT] {
+        let [gx, gy, gz] = self.gyro;
+        let [ax, ay, az] = self.acc;
+        let [roll, pitch, yaw] = self.att;
+        let [rc_roll, rc_pitch, rc_throttle, rc_yaw] = self.rc;
+        let [pid_roll, pid_pitch, pid_yaw, pid_alt] = self.pid;
+        let [m1, m2, m3, m4, m5, m6] = self.motors.map(|m| m as f32);
+        #[rustfmt::skip]
+        let values = [
+            gx, gy, gz, ax, ay, az, roll, pitch, yaw, self.alt,
+            rc_roll, rc_pitch, rc_throttle, rc_yaw,
+            pid_roll, pid_pitch, pid_yaw, pid_alt,
+            m1, m2, m3, m4, m5, m6, self.servo as f32,
+        ];
+        values
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlackboxError {
+    Storage,
+    Full,
+}
+
+/// Flash region split into `SECTOR_SIZE` sectors, offsets are relative to its start.
+pub trait BlackboxStorage {
+    fn sectors(&self) -> usize;
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), BlackboxError>;
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BlackboxError>;
+    fn erase(&mut self, sector: usize) -> Result<(), BlackboxError>;
+}
+
+pub struct Recorder<S: BlackboxStorage> {
+    storage: S,
+    sector: usize,
+    sequence: u32,
+    erased_ahead: usize,
+    recording: bool,
+    page: [u8; PAGE_SIZE],
+    page_start: usize,
+    page_len: usize,
+}
+
+impl<S: BlackboxStorage> Recorder<S> {
+    /// Picks up after the newest sector found in storage.
+    pub fn new(mut storage: S) -> Recorder<S> {
+        let sectors = storage.sectors();
+        let header = |storage: &mut S, sector: usize| {
+            let mut header = [0u8; SECTOR_HEADER_SIZE];
+            let offset = (sector * SECTOR_SIZE) as u32;
+            storage.read(offset, &mut header).ok()?;
+            Some((word(&header, 0), word(&header, 4)))
+        };
+
+        let mut newest: Option<(usize, u32)> = None;
+        for sector in 0..sectors {
+            if let Some((SECTOR_MAGIC, sequence)) = header(&mut storage, sector)
+                && newest.is_none_or(|(_, newest)| sequence > newest)
+            {
+                newest = Some((sector, sequence));
+            }
+        }
+        let (sector, sequence) = newest.unwrap_or((sectors - 1, 0));
+
+        let mut erased_ahead = 0;
+        while erased_ahead < sectors
+            && header(&mut storage, (sector + 1 + erased_ahead) % sectors) == Some((ERASED, ERASED))
+        {
+            erased_ahead += 1;
+        }
+
+        Recorder {
+            storage,
+            sector,
+            sequence,
+            erased_ahead,
+            recording: false,
+            page: [0xff; PAGE_SIZE],
+            page_start: SECTOR_SIZE,
+            page_len: 0,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Erases one more sector ahead of the write position, up to `ahead` sectors.
+    /// Erasing stalls flash access for tens of ms, so only call it while disarmed.
+    /// Returns whether anything was erased.
+    pub fn prepare(&mut self, ahead: usize) -> Result<bool, BlackboxError> {
+        let sectors = self.storage.sectors();
+        if self.recording || self.erased_ahead >= ahead.min(sectors) {
+            return Ok(false);
+        }
+        let sector = (self.sector + 1 + self.erased_ahead) % sectors;
+        self.storage.erase(sector)?;
+        self.erased_ahead += 1;
+        Ok(true)
+    }
+
+    /// Forgets every recorded sector, `prepare` then erases the whole region.
+    pub fn clear(&mut self) -> Result<(), BlackboxError> {
+        self.stop()?;
+        self.erased_ahead = 0;
+        Ok(())
+    }
+
+    pub fn start(&mut self, time_ms: u32, rate_hz: u16) -> Result<(), BlackboxError> {
+        self.recording = true;
+        self.open_sector()?;
+
+        let mut record = [0u8; START_MAX_SIZE];
+        record[0] = TAG_START;
+        record[1..5].copy_from_slice(&time_ms.to_le_bytes());
+        record[5..7].copy_from_slice(&rate_hz.to_le_bytes());
+        record[7] = FIELD_COUNT as u8;
+        let mut len = 8;
+        for field in FIELDS {
+            record[len..len + 4].copy_from_slice(&field.scale.to_le_bytes());
+            record[len + 4] = field.name.len() as u8;
+            len += 5;
+            record[len..len + field.name.len()].copy_from_slice(field.name.as_bytes());
+            len += field.name.len();
+        }
+        self.append(&record[..len])
+    }
+
+    pub fn frame(&mut self, time_ms: u32, snapshot: &Snapshot) -> Result<(), BlackboxError> {
+        let mut record = [0u8; FRAME_SIZE];
+        record[0] = TAG_FRAME;
+        record[1..5].copy_from_slice(&time_ms.to_le_bytes());
+        for (i, (value, field)) in snapshot.values().iter().zip(FIELDS).enumerate() {
+            let raw = libm::roundf(value * field.scale).clamp(i16::MIN as f32, i16::MAX as f32);
+            let at = RECORD_HEADER_SIZE + i * 2;
+            record[at..at + 2].copy_from_slice(&(raw as i16).to_le_bytes());
+        }
+        self.append(&record)
+    }
+
+    pub fn event(&mut self, time_ms: u32, event: Event) -> Result<(), BlackboxError> {
+        self.append(&event_record(time_ms, event))
+    }
+
+    pub fn stop(&mut self) -> Result<(), BlackboxError> {
+        self.recording = false;
+        self.flush()
+    }
+
+    fn open_sector(&mut self) -> Result<(), BlackboxError> {
+        self.flush()?;
+        if self.erased_ahead == 0 {
+            self.recording = false;
+            return Err(BlackboxError::Full);
+        }
+        self.sector = (self.sector + 1) % self.storage.sectors();
+        self.erased_ahead -= 1;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.page_start = 0;
+
+        let mut header = [0u8; SECTOR_HEADER_SIZE];
+        header[0..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        self.push(&header)
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<(), BlackboxError> {
+        if !self.recording {
+            return Ok(());
+        }
+        // The last erased sector keeps room to end with a LOG_FULL event
+        let reserve = if self.erased_ahead == 0 {
+            EVENT_SIZE
+        } else {
+            0
+        };
+        if self.page_start + self.page_len + record.len() + reserve > SECTOR_SIZE {
+            if self.erased_ahead == 0 {
+                self.push(&event_record(word(record, 1), Event::LogFull))?;
+            }
+            self.open_sector()?;
+        }
+        self.push(record)
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) -> Result<(), BlackboxError> {
+        while !bytes.is_empty() {
+            let n = bytes.len().min(PAGE_SIZE - self.page_len);
+            self.page[self.page_len..self.page_len + n].copy_from_slice(&bytes[..n]);
+            self.page_len += n;
+            bytes = &bytes[n..];
+            if self.page_len == PAGE_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlackboxError> {
+        if self.page_len == 0 {
+            return Ok(());
+        }
+        let offset = (self.sector * SECTOR_SIZE + self.page_start) as u32;
+        let result = self.storage.write(offset, &self.page[..self.page_len]);
+        self.page_start += self.page_len;
+        self.page_len = 0;
+        result
+    }
+}
+
+fn event_record(time_ms: u32, event: Event) -> [u8; EVENT_SIZE] {
+    let mut record = [0u8; EVENT_SIZE];
+    record[0] = TAG_EVENT;
+    record[1..5].copy_from_slice(&time_ms.to_le_bytes());
+    record[5] = event as u8;
+    record
+}
+
+fn word(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn half(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+/// Field description carried by a session start record.
+#[derive(Copy, Clone)]
+pub struct Fields<'a> {
+    count: usize,
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// (name, scale) pairs, invalid UTF-8 names come back empty.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, f32)> + 'a {
+        let mut data = self.data;
+        (0..self.count).map_while(move |_| {
+            let scale = f32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+            let len = *data.get(4)? as usize;
+            let name = data.get(5..5 + len)?;
+            data = &data[5 + len..];
+            Some((core::str::from_utf8(name).unwrap_or(""), scale))
+        })
+    }
+
+    /// Scaled values of a frame record.
+    pub fn values(&self, frame: &'a [u8]) -> impl Iterator<Item = f32> + 'a {
+        frame
+            .chunks_exact(2)
+            .zip(self.iter())
+            .map(|(raw, (_, scale))| i16::from_le_bytes([raw[0], raw[1]]) as f32 / scale)
+    }
+}
+
+pub enum Record<'a> {
+    Start {
+        time_ms: u32,
+        rate_hz: u16,
+        fields: Fields<'a>,
+    },
+    Frame {
+        time_ms: u32,
+        data: &'a [u8],
+    },
+    Event {
+        time_ms: u32,
+        event: u8,
+    },
+}
+
+/// Walks a raw dump of the blackbox region, oldest sector first.
+pub fn decode<'a>(region: &'a [u8], mut on_record: impl FnMut(Record<'a>)) {
+    let sectors: &[[u8; SECTOR_SIZE]] = region.as_chunks().0;
+    let sequence = |sector: &[u8]| (word(sector, 0) == SECTOR_MAGIC).then(|| word(sector, 4));
+
+    let mut field_count = FIELD_COUNT;
+    let mut last: Option<u32> = None;
+    while let Some(sector) = sectors
+        .iter()
+        .filter(|s| sequence(&s[..]).is_some_and(|seq| last.is_none_or(|last| seq > last)))
+        .min_by_key(|s| sequence(&s[..]))
+    {
+        last = sequence(sector);
+
+        let mut at = SECTOR_HEADER_SIZE;
+        while at + RECORD_HEADER_SIZE <= SECTOR_SIZE {
+            let time_ms = word(sector, at + 1);
+            let body = &sector[at + RECORD_HEADER_SIZE..];
+            let len = match sector[at] {
+                TAG_START if body.len() >= 3 => {
+                    let count = body[2] as usize;
+                    let mut len = 3;
+                    for _ in 0..count {
+                        len += 5 + body.get(len + 4).copied().unwrap_or(0) as usize;
+                    }
+                    if len > body.len() {
+                        break;
+                    }
+                    field_count = count;
+                    on_record(Record::Start {
+                        time_ms,
+                        rate_hz: half(body, 0),
+                        fields: Fields {
+                            count,
+                            data: &body[3..len],
+                        },
+                    });
+                    len
+                }
+                TAG_FRAME if body.len() >= field_count * 2 => {
+                    let len = field_count * 2;
+                    on_record(Record::Frame {
+                        time_ms,
+                        data: &body[..len],
+                    });
+                    len
+                }
+                TAG_EVENT if !body.is_empty() => {
+                    on_record(Record::Event {
+                        time_ms,
+                        event: body[0],
+                    });
+                    1
+                }
+                _ => break,
+            };
+            at += RECORD_HEADER_SIZE + len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MemFlash(Vec<u8>);
+
+    impl MemFlash {
+        fn new(sectors: usize) -> MemFlash {
+            MemFlash(vec![0xff; sectors * SECTOR_SIZE])
+        }
+    }
+
+    impl BlackboxStorage for &mut MemFlash {
+        fn sectors(&self) -> usize {
+            self.0.len() / SECTOR_SIZE
+        }
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), BlackboxError> {
+            let at = offset as usize;
+            buf.copy_from_slice(&self.0[at..at + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BlackboxError> {
+            // NOR flash can only clear bits
+            let at = offset as usize;
+            for (cell, byte) in self.0[at..at + data.len()].iter_mut().zip(data) {
+                *cell &= byte;
+            }
+            Ok(())
+        }
+
+        fn erase(&mut self, sector: usize) -> Result<(), BlackboxError> {
+            self.0[sector * SECTOR_SIZE..(sector + 1) * SECTOR_SIZE].fill(0xff);
+            Ok(())
+        }
+    }
+
+    fn snapshot(i: u16) -> Snapshot {
+        Snapshot {
+            gyro: [0.5, -1.25, i as f32 / 1000.0],
+            acc: [0.1, 0.2, 9.81],
+            att: [0.01, -0.02, 3.0],
+            alt: 1.5,
+            rc: [0.0, 0.1, 0.33, -0.2],
+            pid: [0.05, -0.05, 0.0, 0.01],
+            motors: [48 + i, 500, 600, 2047, 0, 1000],
+            servo: 1500,
+            modes: modes::ARMED,
+        }
+    }
+
+    type Start = (u32, u16, Vec<(String, f32)>);
+
+    #[derive(Default)]
+    struct Decoded {
+        starts: Vec<Start>,
+        frames: Vec<(u32, Vec<f32>)>,
+        events: Vec<(u32, u8)>,
+    }
+
+    fn decode_all(flash: &MemFlash) -> Decoded {
+        let mut out = Decoded::default();
+        let mut fields = None;
+        decode(&flash.0, |record| match record {
+            Record::Start {
+                time_ms,
+                rate_hz,
+                fields: f,
+            } => {
+                let names = f.iter().map(|(n, s)| (n.to_string(), s)).collect();
+                out.starts.push((time_ms, rate_hz, names));
+                fields = Some(f);
+            }
+            Record::Frame { time_ms, data } => {
+                let values = fields.unwrap().values(data).collect();
+                out.frames.push((time_ms, values));
+            }
+            Record::Event { time_ms, event } => out.events.push((time_ms, event)),
+        });
+        out
+    }
+
+    fn ready(flash: &mut MemFlash) -> Recorder<&mut MemFlash> {
+        let mut recorder = Recorder::new(flash);
+        while recorder.prepare(usize::MAX).unwrap() {}
+        recorder
+    }
+
+    #[test]
+    fn session_roundtrips() {
+        let mut flash = MemFlash::new(8);
+        let mut recorder = ready(&mut flash);
+        recorder.start(1000, 100).unwrap();
+        recorder.event(1000, Event::Armed).unwrap();
+        for i in 0..200 {
+            recorder.frame(1000 + i as u32 * 10, &snapshot(i)).unwrap();
+        }
+        recorder.event(3000, Event::Disarmed).unwrap();
+        recorder.stop().unwrap();
+
+        let decoded = decode_all(&flash);
+        assert_eq!(decoded.starts.len(), 1);
+        let (time_ms, rate_hz, fields) = &decoded.starts[0];
+        assert_eq!((*time_ms, *rate_hz), (1000, 100));
+        let names: Vec<_> = fields.iter().map(|(n, _)| n.as_str()).collect();
+        let expected: Vec<_> = FIELDS.iter().map(|f| f.name).collect();
+        assert_eq!(names, expected);
+
+        assert_eq!(decoded.frames.len(), 200);
+        for (i, (time_ms, values)) in decoded.frames.iter().enumerate() {
+            assert_eq!(*time_ms, 1000 + i as u32 * 10);
+            let original = snapshot(i as u16).values();
+            for ((value, original), field) in values.iter().zip(original).zip(FIELDS) {
+                assert!(
+                    (value - original).abs() <= 0.5 / field.scale,
+                    "{}: {} vs {}",
+                    field.name,
+                    value,
+                    original
+                );
+            }
+        }
+        assert_eq!(
+            decoded.events,
+            vec![(1000, Event::Armed as u8), (3000, Event::Disarmed as u8)]
+        );
+    }
+
+    #[test]
+    fn out_of_range_values_saturate() {
+        let mut flash = MemFlash::new(2);
+        let mut recorder = ready(&mut flash);
+        recorder.start(0, 100).unwrap();
+        let mut s = snapshot(0);
+        s.gyro[0] = 1000.0;
+        recorder.frame(0, &s).unwrap();
+        recorder.stop().unwrap();
+        let decoded = decode_all(&flash);
+        assert_eq!(decoded.frames[0].1[0], i16::MAX as f32 / 1000.0);
+    }
+
+    #[test]
+    fn stops_when_nothing_is_erased() {
+        let mut flash = MemFlash::new(4);
+        let mut recorder = Recorder::new(&mut flash);
+        recorder.prepare(1).unwrap();
+        recorder.start(0, 100).unwrap();
+        let mut result = Ok(());
+        let mut last = 0;
+        for i in 0..1000 {
+            result = recorder.frame(i, &snapshot(0));
+            if result.is_err() {
+                last = i;
+                break;
+            }
+        }
+        assert_eq!(result, Err(BlackboxError::Full));
+        assert!(!recorder.is_recording());
+        assert_eq!(recorder.frame(0, &snapshot(0)), Ok(()));
+
+        // The cut-off is marked at the time of the first frame that did not fit
+        let decoded = decode_all(&flash);
+        assert_eq!(decoded.frames.last().unwrap().0, last - 1);
+        assert_eq!(decoded.events, vec![(last, Event::LogFull as u8)]);
+    }
+
+    #[test]
+    fn wraps_and_decodes_oldest_first() {
+        let mut flash = MemFlash::new(4);
+        for session in 0..3u32 {
+            let mut recorder = ready(&mut flash);
+            recorder.start(session * 100_000, 100).unwrap();
+            // a bit more than a sector per session
+            for i in 0..100 {
+                recorder.frame(session * 100_000 + i, &snapshot(0)).unwrap();
+            }
+            recorder.stop().unwrap();
+        }
+
+        let decoded = decode_all(&flash);
+        let times: Vec<_> = decoded.frames.iter().map(|(t, _)| *t).collect();
+        let mut sorted = times.clone();
+        sorted.sort();
+        assert_eq!(times, sorted);
+        assert_eq!(decoded.starts.last().unwrap().0, 200_000);
+        assert_eq!(*times.last().unwrap(), 200_099);
+    }
+
+    #[test]
+    fn clear_then_prepare_wipes_everything() {
+        let mut flash = MemFlash::new(4);
+        {
+            let mut recorder = ready(&mut flash);
+            recorder.start(0, 100).unwrap();
+            recorder.frame(0, &snapshot(0)).unwrap();
+            recorder.clear().unwrap();
+            while recorder.prepare(usize::MAX).unwrap() {}
+        }
+        assert!(flash.0.iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn mode_changes_become_events() {
+        let events: Vec<_> = Event::from_modes(0, modes::ARMED).collect();
+        assert_eq!(events, vec![Event::Armed]);
+        let events: Vec<_> = Event::from_modes(modes::ARMED | modes::ALT_HOLD, 0).collect();
+        assert_eq!(events, vec![Event::Disarmed, Event::AltHoldOff]);
+        assert_eq!(Event::from_modes(modes::ARMED, modes::ARMED).count(), 0);
+        for event in [Event::Armed, Event::FramesDropped, Event::BatteryLowOff] {
+            assert_eq!(Event::try_from(event as u8), Ok(event));
+        }
+    }
+}
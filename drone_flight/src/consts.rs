@@ -0,0 +1,79 @@
+// --- Loop ---
+pub const TICK_HZ: u64 = 1000;
+pub const CYCLE_TIME: f32 = 1.0 / TICK_HZ as f32;
+pub const BARO_HZ: u64 = 50;
+pub const BATTERY_HZ: u64 = 50;
+
+// --- Telemetry ---
+#[cfg(feature = "telemetry")]
+pub mod tele_consts {
+    pub const TELE_SYNC: u8 = 0xAA;
+    pub const TELE_VERSION: u8 = 2;
+    pub const TELE_CATEGORIES: usize = 17;
+    pub const TELE_MAX_VALUES: usize = 9;
+    pub const TELE_HEADER_SIZE: usize = 10;
+    pub const TELE_CRC_SIZE: usize = 2;
+    pub const TELE_FRAME_SIZE: usize = TELE_HEADER_SIZE + TELE_MAX_VALUES * 4 + TELE_CRC_SIZE;
+}
+
+#[cfg(feature = "telemetry")]
+pub use tele_consts::*;
+
+/// Telemetry category of the per-motor RPM frames, above the shared categories.
+pub const TELE_ESC_RPM: u8 = 15;
+/// Telemetry category of the battery frames.
+pub const TELE_BATTERY: u8 = 16;
+
+// --- RC & Input ---
+pub const RC_MIN: u16 = 240;
+pub const RC_MAX: u16 = 1807;
+pub const ARM_HOLD_TICKS: u64 = 1000;
+pub const DISARM_HOLD_TICKS: u64 = 100;
+
+// --- Pre-arm ---
+pub const PREARM_GYRO_MAX: f32 = 0.2; // rad/s
+pub const PREARM_LOOP_TOLERANCE: f32 = 0.25; // fraction of CYCLE_TIME
+pub const PREARM_RC_RECOVERY_TICKS: u64 = 5000;
+
+// --- Failsafe ---
+pub const FS_VZ_GAIN: f32 = 0.1; // throttle per m/s of climb rate error
+pub const FS_LANDED_BAND: f32 = 0.2; // m
+pub const FS_LANDED_TICKS: u64 = 1000;
+pub const FS_LANDED_DROP: f32 = 0.5; // m below the highest point of the descent
+pub const FS_AIRBORNE_THROTTLE: f32 = 0.1; // stick above idle
+pub const FS_AIRBORNE_ALT: f32 = 0.5; // m above the altitude at arming
+
+// --- ESC telemetry ---
+pub const ESC_CHECK_THROTTLE: u16 = 200; // DShot value from which a motor has to spin
+pub const ESC_STALL_RPM: f32 = 1000.0;
+pub const ESC_DESYNC_RATIO: f32 = 0.3; // of the mean RPM of the other motors
+pub const ESC_FAULT_TICKS: u64 = 200;
+
+// --- Battery ---
+pub const BATTERY_MIN_V: f32 = 2.0; // below is no battery, powered over USB
+pub const BATTERY_CELL_MAX: f32 = 4.35; // for counting cells
+pub const BATTERY_SETTLE_S: f32 = 1.0; // before counting cells
+pub const BATTERY_LPF_HZ: f32 = 2.0;
+pub const BATTERY_WARN_S: f32 = 2.0; // a level has to hold this long to be reported
+pub const BATTERY_HYSTERESIS: f32 = 0.1; // per cell, to leave a warning again
+pub const BATTERY_COMP_LPF_HZ: f32 = 0.5; // well under the loop, sag follows throttle
+
+// --- Dynamic notch ---
+pub const DYN_NOTCH_THRESHOLD: f32 = 3.0; // peak over the mean spectrum magnitude
+pub const DYN_NOTCH_SMOOTHING: f32 = 0.5; // share of a new peak taken per window
+
+// --- Tuning ---
+// Gains, limits and filters live in `params`, these are fixed by protocol or RC setup.
+pub const THROTTLE_MIN: f32 = 48.0;
+pub const THROTTLE_MAX: f32 = 2047.0;
+pub const SLOPE: f32 = THROTTLE_MAX - THROTTLE_MIN;
+
+pub const ALT_KP_MIN: f32 = 0.0;
+pub const ALT_KP_MAX: f32 = 0.5;
+pub const ALT_KD_MIN: f32 = 0.0;
+pub const ALT_KD_MAX: f32 = 0.05;
+pub const ALT_HOLD_THROTTLE_MIN: f32 = 0.15;
+pub const ALT_HOLD_THROTTLE_MAX: f32 = 0.50;
+
+// --- IMU ---
+pub const CALIBRATION_TICKS: usize = 2000;
@@ -0,0 +1,332 @@
+use crate::consts::{DYN_NOTCH_SMOOTHING, DYN_NOTCH_THRESHOLD};
+use crate::filter::{Biquad, BiquadCoeffs};
+use crate::params::Params;
+use core::f32::consts::PI;
+use nalgebra::Vector3;
+
+pub const FFT_SIZE: usize = 64;
+const FFT_STAGES: usize = FFT_SIZE.trailing_zeros() as usize;
+const BINS: usize = FFT_SIZE / 2;
+// A new window every half window of samples
+const HOP: usize = FFT_SIZE / 2;
+pub const DYN_NOTCH_MAX: usize = 3;
+
+/// Gyro samples per axis, oldest first.
+pub type Window = [[f32; FFT_SIZE]; 3];
+/// Strongest noise peaks per axis in Hz, in ascending frequency.
+pub type Peaks = [[Option<f32>; DYN_NOTCH_MAX]; 3];
+
+/// Collects the gyro stream into overlapping analysis windows.
+pub struct GyroWindow {
+    samples: Window,
+    pos: usize,
+    fill: usize,
+}
+
+impl GyroWindow {
+    pub fn new() -> GyroWindow {
+        GyroWindow {
+            samples: [[0.0; FFT_SIZE]; 3],
+            pos: 0,
+            fill: 0,
+        }
+    }
+
+    /// Returns a full window once every `HOP` samples.
+    pub fn push(&mut self, gyro: &Vector3<f32>) -> Option<Window> {
+        for (axis, value) in self.samples.iter_mut().zip(gyro.iter()) {
+            axis[self.pos] = *value;
+        }
+        self.pos = (self.pos + 1) % FFT_SIZE;
+        self.fill += 1;
+        if self.fill < FFT_SIZE || !self.fill.is_multiple_of(HOP) {
+            return None;
+        }
+        self.fill = FFT_SIZE;
+        Some(
+            self.samples
+                .map(|axis| core::array::from_fn(|i| axis[(self.pos + i) % FFT_SIZE])),
+        )
+    }
+}
+
+impl Default for GyroWindow {
+    fn default() -> GyroWindow {
+        GyroWindow::new()
+    }
+}
+
+/// Finds the noise peaks of a gyro window with a Hann windowed FFT, a bounded slice
+/// of work per `step` so it can share a core with the IMU loop.
+pub struct Analyser {
+    sample_hz: f32,
+    min_hz: f32,
+    max_hz: f32,
+    count: usize,
+    hann: [f32; FFT_SIZE],
+    cos: [f32; BINS],
+    sin: [f32; BINS],
+    window: Window,
+    re: [f32; FFT_SIZE],
+    im: [f32; FFT_SIZE],
+    axis: usize,
+    /// 0 loads the samples, 1..=FFT_STAGES are butterfly stages, then the peak search.
+    stage: usize,
+    peaks: Peaks,
+}
+
+impl Analyser {
+    pub fn new(params: &Params, sample_hz: f32) -> Analyser {
+        Analyser {
+            sample_hz,
+            min_hz: params.dyn_notch_min_hz,
+            max_hz: params.dyn_notch_max_hz,
+            count: (params.dyn_notch_count as usize).min(DYN_NOTCH_MAX),
+            hann: core::array::from_fn(|i| {
+                0.5 - 0.5 * libm::cosf(2.0 * PI * i as f32 / (FFT_SIZE - 1) as f32)
+            }),
+            cos: core::array::from_fn(|k| libm::cosf(2.0 * PI * k as f32 / FFT_SIZE as f32)),
+            sin: core::array::from_fn(|k| libm::sinf(2.0 * PI * k as f32 / FFT_SIZE as f32)),
+            window: [[0.0; FFT_SIZE]; 3],
+            re: [0.0; FFT_SIZE],
+            im: [0.0; FFT_SIZE],
+            axis: 3,
+            stage: 0,
+            peaks: [[None; DYN_NOTCH_MAX]; 3],
+        }
+    }
+
+    pub fn start(&mut self, window: &Window) {
+        self.window = *window;
+        self.axis = 0;
+        self.stage = 0;
+    }
+
+    /// Does the next slice of the analysis, true once the peaks of the window are ready.
+    pub fn step(&mut self) -> bool {
+        if self.axis >= 3 {
+            return true;
+        }
+        match self.stage {
+            0 => self.load(),
+            stage if stage <= FFT_STAGES => self.butterflies(stage),
+            _ => {
+                self.peaks[self.axis] = self.find_peaks();
+                self.axis += 1;
+                self.stage = 0;
+                return self.axis >= 3;
+            }
+        }
+        self.stage += 1;
+        false
+    }
+
+    pub fn peaks(&self) -> &Peaks {
+        &self.peaks
+    }
+
+    fn load(&mut self) {
+        let bits = FFT_STAGES as u32;
+        for (i, sample) in self.window[self.axis].iter().enumerate() {
+            let j = (i as u32).reverse_bits() >> (32 - bits);
+            self.re[j as usize] = sample * self.hann[i];
+            self.im[j as usize] = 0.0;
+        }
+    }
+
+    fn butterflies(&mut self, stage: usize) {
+        let size = 1 << stage;
+        let half = size / 2;
+        let twiddle_step = FFT_SIZE / size;
+        for start in (0..FFT_SIZE).step_by(size) {
+            for k in 0..half {
+                let (cos, sin) = (self.cos[k * twiddle_step], -self.sin[k * twiddle_step]);
+                let (a, b) = (start + k, start + k + half);
+                let re = self.re[b] * cos - self.im[b] * sin;
+                let im = self.re[b] * sin + self.im[b] * cos;
+                self.re[b] = self.re[a] - re;
+                self.im[b] = self.im[a] - im;
+                self.re[a] += re;
+                self.im[a] += im;
+            }
+        }
+    }
+
+    fn find_peaks(&self) -> [Option<f32>; DYN_NOTCH_MAX] {
+        let bin_hz = self.sample_hz / FFT_SIZE as f32;
+        let magnitude: [f32; BINS] = core::array::from_fn(|k| {
+            libm::sqrtf(self.re[k] * self.re[k] + self.im[k] * self.im[k])
+        });
+        let first = ((self.min_hz / bin_hz) as usize).max(1);
+        let last = ((self.max_hz / bin_hz) as usize).min(BINS - 2);
+        if first > last {
+            return [None; DYN_NOTCH_MAX];
+        }
+        let mean = magnitude[first..=last].iter().sum::<f32>() / (last - first + 1) as f32;
+
+        // Strongest local maxima standing out of the noise floor
+        let mut found: [Option<(f32, f32)>; DYN_NOTCH_MAX] = [None; DYN_NOTCH_MAX];
+        for k in first..=last {
+            let m = magnitude[k];
+            if m <= magnitude[k - 1] || m < magnitude[k + 1] || m < mean * DYN_NOTCH_THRESHOLD {
+                continue;
+            }
+            let Some(slot) = (0..self.count).min_by(|&a, &b| {
+                let weight = |s: usize| found[s].map_or(-1.0, |(_, m)| m);
+                weight(a).total_cmp(&weight(b))
+            }) else {
+                continue;
+            };
+            if found[slot].is_none_or(|(_, weakest)| weakest < m) {
+                // Parabolic fit through the neighbours for a frequency between bins
+                let (l, r) = (magnitude[k - 1], magnitude[k + 1]);
+                let curve = l - 2.0 * m + r;
+                let offset = if curve < 0.0 {
+                    0.5 * (l - r) / curve
+                } else {
+                    0.0
+                };
+                found[slot] = Some(((k as f32 + offset) * bin_hz, m));
+            }
+        }
+
+        let mut peaks = found.map(|f| f.map(|(hz, _)| hz));
+        peaks.sort_by(|a, b| match (a, b) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        peaks
+    }
+}
+
+/// Gyro notches on the peaks the `Analyser` finds, for ESCs without RPM telemetry.
+pub struct DynNotch {
+    q: f32,
+    sample_hz: f32,
+    center: [[Option<f32>; DYN_NOTCH_MAX]; 3],
+    coeffs: [[Option<BiquadCoeffs>; DYN_NOTCH_MAX]; 3],
+    state: [[Biquad; DYN_NOTCH_MAX]; 3],
+}
+
+impl DynNotch {
+    pub fn new(params: &Params, sample_hz: f32) -> DynNotch {
+        DynNotch {
+            q: params.dyn_notch_q,
+            sample_hz,
+            center: [[None; DYN_NOTCH_MAX]; 3],
+            coeffs: [[None; DYN_NOTCH_MAX]; 3],
+            state: [[Biquad::default(); DYN_NOTCH_MAX]; 3],
+        }
+    }
+
+    /// Moves the notches towards the latest peaks, smoothed so they don't jump
+    /// between bins.
+    pub fn update(&mut self, peaks: &Peaks) {
+        let notches = self.center.iter_mut().flatten();
+        let notches = notches.zip(self.coeffs.iter_mut().flatten());
+        let notches = notches.zip(self.state.iter_mut().flatten());
+        for (((center, coeffs), state), peak) in notches.zip(peaks.iter().flatten()) {
+            *center = match (*center, peak) {
+                (Some(prev), Some(peak)) => Some(prev + (peak - prev) * DYN_NOTCH_SMOOTHING),
+                (None, Some(peak)) => {
+                    *state = Biquad::default();
+                    Some(*peak)
+                }
+                (_, None) => None,
+            };
+            *coeffs = center.map(|hz| BiquadCoeffs::notch(hz, self.q, self.sample_hz));
+        }
+    }
+
+    pub fn apply(&mut self, gyro: Vector3<f32>) -> Vector3<f32> {
+        let mut out = gyro;
+        for ((value, coeffs), state) in out.iter_mut().zip(&self.coeffs).zip(&mut self.state) {
+            for (coeffs, state) in coeffs.iter().zip(state.iter_mut()) {
+                if let Some(coeffs) = coeffs {
+                    *value = state.apply(coeffs, *value);
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::gain;
+
+    const SAMPLE_HZ: f32 = 1000.0;
+
+    fn tones(hz: &[(f32, f32)], n: usize) -> f32 {
+        hz.iter()
+            .map(|(hz, amp)| amp * libm::sinf(2.0 * PI * hz * n as f32 / SAMPLE_HZ))
+            .sum()
+    }
+
+    fn analyse(params: &Params, signal: impl Fn(usize) -> f32) -> Peaks {
+        let mut window = GyroWindow::new();
+        let full = (0..FFT_SIZE)
+            .filter_map(|n| window.push(&Vector3::new(signal(n), 0.0, 0.0)))
+            .last()
+            .unwrap();
+        let mut analyser = Analyser::new(params, SAMPLE_HZ);
+        analyser.start(&full);
+        let steps = (1..).find(|_| analyser.step()).unwrap();
+        assert_eq!(steps, 3 * (FFT_STAGES + 2));
+        *analyser.peaks()
+    }
+
+    #[test]
+    fn window_hops_in_order() {
+        let mut window = GyroWindow::new();
+        let mut full = Vec::new();
+        for n in 0..FFT_SIZE + HOP {
+            if let Some(w) = window.push(&Vector3::new(n as f32, 0.0, 0.0)) {
+                full.push((n, w[0]));
+            }
+        }
+        assert_eq!(full.len(), 2);
+        assert_eq!(full[0].0, FFT_SIZE - 1);
+        assert_eq!(full[1].1[0], HOP as f32);
+        assert_eq!(full[1].1[FFT_SIZE - 1], (FFT_SIZE + HOP - 1) as f32);
+    }
+
+    #[test]
+    fn finds_the_strongest_tones() {
+        let params = Params::defaults();
+        let peaks = analyse(&params, |n| tones(&[(180.0, 1.0), (330.0, 0.5)], n));
+        let [low, high, none] = peaks[0];
+        assert!((low.unwrap() - 180.0).abs() < 5.0, "{low:?}");
+        assert!((high.unwrap() - 330.0).abs() < 5.0, "{high:?}");
+        assert_eq!(none, None);
+        assert_eq!(peaks[1], [None; DYN_NOTCH_MAX]);
+    }
+
+    #[test]
+    fn tones_out_of_range_are_ignored() {
+        let params = Params::defaults();
+        let peaks = analyse(&params, |n| tones(&[(30.0, 1.0)], n));
+        assert_eq!(peaks[0], [None; DYN_NOTCH_MAX]);
+    }
+
+    #[test]
+    fn notch_follows_the_peak() {
+        let params = Params::defaults();
+        let mut notch = DynNotch::new(&params, SAMPLE_HZ);
+        let mut peaks = [[None; DYN_NOTCH_MAX]; 3];
+        peaks[0][0] = Some(250.0);
+        notch.update(&peaks);
+        let roll = |notch: &mut DynNotch, hz| {
+            gain(hz, SAMPLE_HZ, 2000, |x| {
+                notch.apply(Vector3::new(x, 0.0, 0.0)).x
+            })
+        };
+        assert!(roll(&mut notch, 250.0) < 0.01);
+        assert!(roll(&mut notch, 20.0) > 0.95);
+
+        peaks[0][0] = Some(300.0);
+        notch.update(&peaks);
+        assert!(roll(&mut notch, 250.0 + 50.0 * DYN_NOTCH_SMOOTHING) < 0.01);
+    }
+}
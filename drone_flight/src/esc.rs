@@ -0,0 +1,213 @@
+use crate::consts::{
+    ESC_CHECK_THROTTLE, ESC_DESYNC_RATIO, ESC_FAULT_TICKS, ESC_STALL_RPM, THROTTLE_MAX,
+    THROTTLE_MIN,
+};
+use crate::mixer::MAX_MOTORS;
+use crate::params::Params;
+
+/// Electrical RPM per motor pin from bidirectional DShot, `None` without a valid reply.
+pub type Erpm = [Option<u32>; MAX_MOTORS];
+/// Mechanical RPM per motor pin.
+pub type Rpm = [Option<f32>; MAX_MOTORS];
+
+/// An ESC sees one electrical revolution per pole pair.
+pub fn erpm_to_rpm(erpm: u32, poles: f32) -> f32 {
+    erpm as f32 * 2.0 / poles
+}
+
+/// Speed field of a DShot frame, 0-1999 above the command range, for a motor output
+/// of 48-2047. `None` for 0, which stops the motor.
+pub fn dshot_speed(output: u16) -> Option<u16> {
+    (output != 0)
+        .then(|| output.clamp(THROTTLE_MIN as u16, THROTTLE_MAX as u16) - THROTTLE_MIN as u16)
+}
+
+/// Watches the RPM the ESCs report against what they were told, and flags motors
+/// that stopped or lost sync while they should be spinning.
+pub struct EscMonitor {
+    enabled: bool,
+    poles: f32,
+    /// Bit per motor pin the frame uses.
+    used: u8,
+    rpm: Rpm,
+    bad_ticks: [u64; MAX_MOTORS],
+    faults: u8,
+}
+
+impl EscMonitor {
+    pub fn new(params: &Params, used: u8) -> EscMonitor {
+        EscMonitor {
+            enabled: params.dshot_bidir != 0.0,
+            poles: params.motor_poles,
+            used,
+            rpm: [None; MAX_MOTORS],
+            bad_ticks: [0; MAX_MOTORS],
+            faults: 0,
+        }
+    }
+
+    /// `erpm` answers the `motors` commanded on the previous tick. Returns the pins
+    /// at fault as a bit mask.
+    pub fn update(&mut self, erpm: &Erpm, motors: &[u16; MAX_MOTORS]) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        self.rpm = erpm.map(|e| e.map(|e| erpm_to_rpm(e, self.poles)));
+
+        let checked = |pin: usize| self.used & (1 << pin) != 0 && motors[pin] >= ESC_CHECK_THROTTLE;
+        let mut faults = 0;
+        for pin in 0..MAX_MOTORS {
+            if !checked(pin) {
+                self.bad_ticks[pin] = 0;
+                continue;
+            }
+            // A desynced motor spins well below the others at a similar command
+            let others = (0..MAX_MOTORS)
+                .filter(|&other| other != pin && checked(other))
+                .filter_map(|other| self.rpm[other]);
+            let (sum, count) = others.fold((0.0, 0), |(sum, n), rpm| (sum + rpm, n + 1));
+            let bad = match self.rpm[pin] {
+                Some(rpm) => {
+                    rpm < ESC_STALL_RPM
+                        || (count > 0 && rpm < ESC_DESYNC_RATIO * sum / count as f32)
+                }
+                None => true,
+            };
+            self.bad_ticks[pin] = if bad { self.bad_ticks[pin] + 1 } else { 0 };
+            if self.bad_ticks[pin] >= ESC_FAULT_TICKS {
+                faults |= 1 << pin;
+            }
+        }
+
+        if faults != self.faults {
+            if faults & !self.faults != 0 {
+                log::warn!("[ESC] motor fault on pins {:#08b}", faults);
+            } else {
+                log::info!("[ESC] motors recovered {:#08b}", faults);
+            }
+        }
+        self.faults = faults;
+        faults
+    }
+
+    pub fn rpm(&self) -> &Rpm {
+        &self.rpm
+    }
+
+    pub fn faults(&self) -> u8 {
+        self.faults
+    }
+
+    /// Whether every motor the frame uses answered on the last tick, always true
+    /// without bidirectional DShot.
+    pub fn all_reporting(&self) -> bool {
+        !self.enabled
+            || (0..MAX_MOTORS)
+                .filter(|pin| self.used & (1 << pin) != 0)
+                .all(|pin| self.rpm[pin].is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: u8 = 0b1111;
+    const SPINNING: [u16; MAX_MOTORS] = [600, 600, 600, 600, 0, 0];
+
+    #[test]
+    fn outputs_map_to_dshot_speeds() {
+        assert_eq!(dshot_speed(0), None);
+        assert_eq!(dshot_speed(THROTTLE_MIN as u16), Some(0));
+        assert_eq!(dshot_speed(1048), Some(1000));
+        assert_eq!(dshot_speed(THROTTLE_MAX as u16), Some(1999));
+        // Out of range outputs still land on a valid frame
+        assert_eq!(dshot_speed(20), Some(0));
+        assert_eq!(dshot_speed(3000), Some(1999));
+    }
+
+    fn monitor() -> EscMonitor {
+        let params = Params {
+            dshot_bidir: 1.0,
+            motor_poles: 14.0,
+            ..Params::defaults()
+        };
+        EscMonitor::new(&params, QUAD)
+    }
+
+    fn erpm(rpm: [u32; 4]) -> Erpm {
+        let mut erpm = [None; MAX_MOTORS];
+        for (out, rpm) in erpm.iter_mut().zip(rpm) {
+            *out = Some(rpm * 7);
+        }
+        erpm
+    }
+
+    fn run(monitor: &mut EscMonitor, erpm: &Erpm, motors: &[u16; MAX_MOTORS], ticks: u64) -> u8 {
+        (0..ticks)
+            .map(|_| monitor.update(erpm, motors))
+            .last()
+            .unwrap()
+    }
+
+    #[test]
+    fn erpm_converts_with_pole_pairs() {
+        assert_eq!(erpm_to_rpm(70_000, 14.0), 10_000.0);
+        let mut monitor = monitor();
+        monitor.update(&erpm([10_000; 4]), &SPINNING);
+        assert_eq!(monitor.rpm()[0], Some(10_000.0));
+        assert_eq!(monitor.rpm()[4], None);
+    }
+
+    #[test]
+    fn healthy_motors_have_no_fault() {
+        let mut monitor = monitor();
+        let uneven = erpm([9_000, 12_000, 10_000, 11_000]);
+        assert_eq!(run(&mut monitor, &uneven, &SPINNING, 1000), 0);
+        assert!(monitor.all_reporting());
+    }
+
+    #[test]
+    fn stalled_motor_is_flagged_after_a_while() {
+        let mut monitor = monitor();
+        let stalled = erpm([10_000, 10_000, 0, 10_000]);
+        assert_eq!(
+            run(&mut monitor, &stalled, &SPINNING, ESC_FAULT_TICKS - 1),
+            0
+        );
+        assert_eq!(monitor.update(&stalled, &SPINNING), 0b0100);
+        assert_eq!(monitor.faults(), 0b0100);
+
+        let recovered = erpm([10_000; 4]);
+        assert_eq!(monitor.update(&recovered, &SPINNING), 0);
+    }
+
+    #[test]
+    fn desynced_or_silent_motor_is_flagged() {
+        let mut monitor = monitor();
+        let desynced = erpm([10_000, 2_000, 10_000, 10_000]);
+        assert_eq!(
+            run(&mut monitor, &desynced, &SPINNING, ESC_FAULT_TICKS),
+            0b0010
+        );
+
+        let mut silent = erpm([10_000; 4]);
+        silent[3] = None;
+        assert_eq!(
+            run(&mut monitor, &silent, &SPINNING, ESC_FAULT_TICKS),
+            0b1000
+        );
+        assert!(!monitor.all_reporting());
+    }
+
+    #[test]
+    fn idle_or_unused_motors_are_not_checked() {
+        let mut monitor = monitor();
+        let stopped = erpm([0; 4]);
+        assert_eq!(run(&mut monitor, &stopped, &[48; MAX_MOTORS], 1000), 0);
+        assert!(monitor.all_reporting());
+
+        let off = EscMonitor::new(&Params::defaults(), QUAD);
+        assert!(off.all_reporting());
+    }
+}
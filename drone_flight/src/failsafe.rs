@@ -0,0 +1,344 @@
+use crate::consts::{
+    FS_AIRBORNE_ALT, FS_AIRBORNE_THROTTLE, FS_LANDED_BAND, FS_LANDED_DROP, FS_LANDED_TICKS,
+    FS_VZ_GAIN, TICK_HZ,
+};
+use crate::params::Params;
+use crate::rc::RcData;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Stage {
+    /// Link up, the pilot flies.
+    Idle = 0,
+    /// Link lost, keep flying on the last sticks for a moment.
+    Hold = 1,
+    /// Level out and descend at `fs_descent_rate`.
+    Descend = 2,
+    /// On the ground or out of time, disarm.
+    Landed = 3,
+}
+
+/// Stands in for the pilot while the RC link is lost, so the motors aren't cut in the air,
+/// and lands on a critical battery.
+pub struct Failsafe {
+    stage: Stage,
+    ticks: u64,
+    still_ticks: u64,
+    still_alt: f32,
+    top_alt: f32,
+    descending: bool,
+    ground_alt: f32,
+    last_rc: Option<RcData>,
+    hold_ticks: u64,
+    land_ticks: u64,
+    throttle: f32,
+    descent_rate: f32,
+}
+
+impl Failsafe {
+    pub fn new(params: &Params) -> Failsafe {
+        let mut failsafe = Failsafe {
+            stage: Stage::Idle,
+            ticks: 0,
+            still_ticks: 0,
+            still_alt: 0.0,
+            top_alt: 0.0,
+            descending: false,
+            ground_alt: 0.0,
+            last_rc: None,
+            hold_ticks: 0,
+            land_ticks: 0,
+            throttle: 0.0,
+            descent_rate: 0.0,
+        };
+        failsafe.apply_params(params);
+        failsafe
+    }
+
+    pub fn apply_params(&mut self, params: &Params) {
+        self.hold_ticks = params.fs_hold_ms as u64 * TICK_HZ / 1000;
+        self.land_ticks = params.fs_land_s as u64 * TICK_HZ;
+        self.throttle = params.fs_throttle;
+        self.descent_rate = params.fs_descent_rate;
+    }
+
+    /// Sticks to fly on this tick: the pilot's while the link is up, stand-ins while it's
+    /// lost and armed. `None` once landed, which disarms. `alt` and `vz` are the estimated
+    /// altitude and climb rate. A critical battery goes straight to the descent in the air,
+    /// and the pilot doesn't get control back from it. On the ground it disarms.
+    pub fn update(
+        &mut self,
+        rc: Option<RcData>,
+        armed: bool,
+        alt: f32,
+        vz: f32,
+        battery_critical: bool,
+    ) -> Option<RcData> {
+        let landing = armed && battery_critical;
+        if !armed {
+            self.ground_alt = alt;
+        }
+        if let Some(rc) = rc {
+            if !landing {
+                if self.stage != Stage::Idle {
+                    log::info!("[FAILSAFE] RC back, pilot has control");
+                    self.enter(Stage::Idle);
+                }
+                self.last_rc = Some(rc.clone());
+                return Some(rc);
+            }
+            if self.stage == Stage::Idle {
+                self.last_rc = Some(rc);
+            }
+        }
+        if !armed {
+            self.stage = Stage::Idle;
+            return None;
+        }
+
+        self.ticks += 1;
+        match self.stage {
+            Stage::Idle | Stage::Hold if landing && self.airborne(alt) => {
+                log::warn!("[FAILSAFE] battery critical, descending");
+                self.enter(Stage::Descend);
+            }
+            Stage::Idle | Stage::Hold if landing => {
+                log::warn!("[FAILSAFE] battery critical on the ground, disarming");
+                self.enter(Stage::Landed);
+            }
+            Stage::Idle => {
+                log::warn!("[FAILSAFE] RC lost, holding last sticks");
+                self.enter(Stage::Hold);
+            }
+            Stage::Hold if self.ticks >= self.hold_ticks => {
+                log::warn!("[FAILSAFE] descending");
+                self.enter(Stage::Descend);
+            }
+            Stage::Descend => {
+                // Only a drone that came down and then stayed put counts as landed. Judged
+                // on altitude, the estimated climb rate lags it by up to a second.
+                self.top_alt = self.top_alt.max(alt);
+                self.descending |= self.top_alt - alt > FS_LANDED_DROP;
+                if (alt - self.still_alt).abs() < FS_LANDED_BAND {
+                    self.still_ticks += 1;
+                } else {
+                    self.still_alt = alt;
+                    self.still_ticks = 0;
+                }
+                if self.descending && self.still_ticks >= FS_LANDED_TICKS {
+                    log::warn!("[FAILSAFE] landed, disarming");
+                    self.enter(Stage::Landed);
+                } else if self.ticks >= self.land_ticks {
+                    log::warn!("[FAILSAFE] descent timed out, disarming");
+                    self.enter(Stage::Landed);
+                }
+            }
+            _ => {}
+        }
+
+        match self.stage {
+            Stage::Hold => self.last_rc.clone(),
+            Stage::Descend => {
+                let throttle = self.throttle + FS_VZ_GAIN * (-self.descent_rate - vz);
+                let rc = self.last_rc.as_ref()?;
+                Some(rc.failsafe(throttle.clamp(0.0, 1.0)))
+            }
+            Stage::Idle | Stage::Landed => None,
+        }
+    }
+
+    /// Throttle up or climbed since arming, otherwise the motors only idle on the ground.
+    fn airborne(&self, alt: f32) -> bool {
+        let throttle = self.last_rc.as_ref().map_or(0.0, |rc| rc.throttle());
+        throttle > FS_AIRBORNE_THROTTLE || alt - self.ground_alt > FS_AIRBORNE_ALT
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.ticks = 0;
+        self.still_ticks = 0;
+        self.top_alt = f32::MIN;
+        self.descending = false;
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::{RC_MAX, RC_MIN};
+
+    fn sticks() -> RcData {
+        let mut channels = [RC_MIN; 16];
+        channels[0] = RC_MAX; // full roll
+        channels[2] = (RC_MIN + RC_MAX) / 2;
+        channels[6] = RC_MAX; // armed
+        channels[7] = RC_MAX; // alt hold
+        RcData::from_channels(channels)
+    }
+
+    fn params() -> Params {
+        Params {
+            fs_hold_ms: 100.0,
+            fs_land_s: 10.0,
+            ..Params::defaults()
+        }
+    }
+
+    fn run(failsafe: &mut Failsafe, ticks: u64, alt: f32, vz: f32) -> Option<RcData> {
+        (0..ticks)
+            .map(|_| failsafe.update(None, true, alt, vz, false))
+            .last()
+            .flatten()
+    }
+
+    #[test]
+    fn holds_last_sticks_then_levels_out() {
+        let mut failsafe = Failsafe::new(&params());
+        failsafe.update(Some(sticks()), true, 0.0, 0.0, false);
+
+        let held = failsafe.update(None, true, 0.0, 0.0, false).unwrap();
+        assert_eq!(failsafe.stage(), Stage::Hold);
+        assert_eq!(held.roll(), 1.0);
+
+        let level = run(&mut failsafe, 100, 0.0, 0.0).unwrap();
+        assert_eq!(failsafe.stage(), Stage::Descend);
+        assert!(level.roll().abs() < 0.01);
+        assert!(level.altitude_switch() < 0.5);
+        assert!(level.arm_switch() > 0.5);
+    }
+
+    #[test]
+    fn descent_throttle_follows_climb_rate() {
+        let p = params();
+        let mut failsafe = Failsafe::new(&p);
+        failsafe.update(Some(sticks()), true, 0.0, 0.0, false);
+        run(&mut failsafe, 101, 0.0, 0.0);
+        assert_eq!(failsafe.stage(), Stage::Descend);
+
+        let hovering = failsafe
+            .update(None, true, 0.0, 0.0, false)
+            .unwrap()
+            .throttle();
+        let on_rate = failsafe
+            .update(None, true, 0.0, -p.fs_descent_rate, false)
+            .unwrap()
+            .throttle();
+        let falling = failsafe
+            .update(None, true, 0.0, -3.0, false)
+            .unwrap()
+            .throttle();
+        assert!(hovering < on_rate && on_rate < falling);
+        assert!((on_rate - p.fs_throttle).abs() < 0.01);
+    }
+
+    #[test]
+    fn lands_after_sinking_then_stopping() {
+        let p = params();
+        let mut failsafe = Failsafe::new(&p);
+        failsafe.update(Some(sticks()), true, 0.0, 0.0, false);
+        run(&mut failsafe, 101, 0.0, 0.0);
+
+        // Hovering in place is not landed
+        assert!(run(&mut failsafe, FS_LANDED_TICKS * 2, 3.0, 0.0).is_some());
+        run(&mut failsafe, 100, 1.0, -p.fs_descent_rate);
+        // Touched down, the climb rate estimate is still catching up
+        assert!(run(&mut failsafe, FS_LANDED_TICKS, 0.0, -0.3).is_some());
+        assert!(failsafe.update(None, true, 0.1, -0.2, false).is_none());
+        assert_eq!(failsafe.stage(), Stage::Landed);
+    }
+
+    #[test]
+    fn stalled_descent_is_not_landed() {
+        let p = params();
+        let mut failsafe = Failsafe::new(&p);
+        failsafe.update(Some(sticks()), true, 0.0, 0.0, false);
+        run(&mut failsafe, 101, 0.0, 0.0);
+
+        // A lagging climb rate says sinking, the altitude barely moved
+        run(&mut failsafe, 100, 3.0, -p.fs_descent_rate);
+        let stalled = run(&mut failsafe, FS_LANDED_TICKS * 2, 2.8, -p.fs_descent_rate);
+        assert!(stalled.is_some());
+        assert_eq!(failsafe.stage(), Stage::Descend);
+    }
+
+    #[test]
+    fn descent_times_out() {
+        let mut failsafe = Failsafe::new(&params());
+        failsafe.update(Some(sticks()), true, 0.0, 0.0, false);
+        run(&mut failsafe, 101, 0.0, 0.0);
+        assert!(run(&mut failsafe, 10 * TICK_HZ, 0.0, 0.0).is_none());
+        assert_eq!(failsafe.stage(), Stage::Landed);
+    }
+
+    #[test]
+    fn rc_back_returns_control() {
+        let mut failsafe = Failsafe::new(&params());
+        failsafe.update(Some(sticks()), true, 0.0, 0.0, false);
+        run(&mut failsafe, 150, 0.0, 0.0);
+        let rc = failsafe
+            .update(Some(sticks()), true, 0.0, 0.0, false)
+            .unwrap();
+        assert_eq!(failsafe.stage(), Stage::Idle);
+        assert_eq!(rc.roll(), 1.0);
+    }
+
+    #[test]
+    fn nothing_to_fly_while_disarmed() {
+        let mut failsafe = Failsafe::new(&params());
+        failsafe.update(Some(sticks()), false, 0.0, 0.0, false);
+        assert!(failsafe.update(None, false, 0.0, 0.0, false).is_none());
+        assert_eq!(failsafe.stage(), Stage::Idle);
+    }
+
+    #[test]
+    fn critical_battery_lands_despite_the_pilot() {
+        let mut failsafe = Failsafe::new(&params());
+        failsafe.update(Some(sticks()), true, 0.0, 0.0, false);
+        let rc = failsafe
+            .update(Some(sticks()), true, 0.0, 0.0, true)
+            .unwrap();
+        assert_eq!(failsafe.stage(), Stage::Descend);
+        assert!(rc.roll().abs() < 0.01);
+        assert!(
+            failsafe
+                .update(Some(sticks()), true, 0.0, 0.0, true)
+                .is_some()
+        );
+        assert_eq!(failsafe.stage(), Stage::Descend);
+
+        // Not while disarmed
+        let mut failsafe = Failsafe::new(&params());
+        let rc = failsafe
+            .update(Some(sticks()), false, 0.0, 0.0, true)
+            .unwrap();
+        assert_eq!(rc.roll(), 1.0);
+        assert_eq!(failsafe.stage(), Stage::Idle);
+    }
+
+    #[test]
+    fn critical_battery_on_the_ground_disarms() {
+        let mut channels = [RC_MIN; 16];
+        channels[6] = RC_MAX; // armed
+        let idle = RcData::from_channels(channels);
+        let mut failsafe = Failsafe::new(&params());
+        failsafe.update(Some(idle.clone()), false, 2.0, 0.0, false);
+        failsafe.update(Some(idle.clone()), true, 2.1, 0.0, false);
+        assert!(
+            failsafe
+                .update(Some(idle.clone()), true, 2.1, 0.0, true)
+                .is_none()
+        );
+        assert_eq!(failsafe.stage(), Stage::Landed);
+
+        // Idle throttle well above the arming altitude is still flying
+        let mut failsafe = Failsafe::new(&params());
+        failsafe.update(Some(idle.clone()), false, 0.0, 0.0, false);
+        failsafe.update(Some(idle.clone()), true, 3.0, 0.0, false);
+        assert!(failsafe.update(Some(idle), true, 3.0, 0.0, true).is_some());
+        assert_eq!(failsafe.stage(), Stage::Descend);
+    }
+}
@@ -0,0 +1,299 @@
+use core::f32::consts::PI;
+
+/// Coefficients of a second order IIR section, normalized so a0 is 1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BiquadCoeffs {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl BiquadCoeffs {
+    /// Second order low-pass, Butterworth with `q` 1/sqrt(2) (RBJ cookbook).
+    pub fn low_pass(cutoff_hz: f32, q: f32, sample_hz: f32) -> BiquadCoeffs {
+        let omega = 2.0 * PI * cutoff_hz / sample_hz;
+        let cos = libm::cosf(omega);
+        let alpha = libm::sinf(omega) / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        let b1 = (1.0 - cos) / a0;
+        BiquadCoeffs {
+            b0: b1 / 2.0,
+            b1,
+            b2: b1 / 2.0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+
+    /// Notch at `center_hz`, narrower with higher `q` (RBJ cookbook).
+    pub fn notch(center_hz: f32, q: f32, sample_hz: f32) -> BiquadCoeffs {
+        let omega = 2.0 * PI * center_hz / sample_hz;
+        let cos = libm::cosf(omega);
+        let alpha = libm::sinf(omega) / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        BiquadCoeffs {
+            b0: 1.0 / a0,
+            b1: -2.0 * cos / a0,
+            b2: 1.0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+}
+
+/// State of one biquad, direct form 1 so the coefficients can move from sample to
+/// sample without glitches. One set of coefficients can drive several of them.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Biquad {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    pub fn apply(&mut self, c: &BiquadCoeffs, input: f32) -> f32 {
+        let output =
+            c.b0 * input + c.b1 * self.x1 + c.b2 * self.x2 - c.a1 * self.y1 - c.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = input;
+        self.y2 = self.y1;
+        self.y1 = output;
+        output
+    }
+}
+
+/// `order` first order low-pass stages. From `new` each is set higher than `cutoff_hz`
+/// so the chain is 3 dB down at it.
+#[derive(Copy, Clone, Debug)]
+pub struct Pt<const ORDER: usize> {
+    alpha: f32,
+    state: [f32; ORDER],
+}
+
+pub type Pt1 = Pt<1>;
+pub type Pt2 = Pt<2>;
+pub type Pt3 = Pt<3>;
+
+impl<const ORDER: usize> Pt<ORDER> {
+    pub fn new(cutoff_hz: f32, sample_hz: f32) -> Pt<ORDER> {
+        let correction = 1.0 / libm::sqrtf(libm::powf(2.0, 1.0 / ORDER as f32) - 1.0);
+        Pt {
+            alpha: 1.0 - libm::expf(-2.0 * PI * cutoff_hz * correction / sample_hz),
+            state: [0.0; ORDER],
+        }
+    }
+
+    /// Every stage at `stage_hz` with an RC alpha and no correction, the response the
+    /// PID filters always had. A PT2 at 40 Hz is 3 dB down around 23 Hz at 1 kHz.
+    pub fn stages(stage_hz: f32, sample_hz: f32) -> Pt<ORDER> {
+        let rc = 1.0 / (2.0 * PI * stage_hz);
+        let dt = 1.0 / sample_hz;
+        Pt {
+            alpha: dt / (rc + dt),
+            state: [0.0; ORDER],
+        }
+    }
+
+    /// Starts from `value` as if it had been the input for a long time.
+    pub fn reset(&mut self, value: f32) {
+        self.state = [value; ORDER];
+    }
+
+    pub fn apply(&mut self, input: f32) -> f32 {
+        self.state.iter_mut().fold(input, |input, state| {
+            *state += self.alpha * (input - *state);
+            *state
+        })
+    }
+}
+
+/// Low-pass picked by a `*_lpf_type` param, or a notch.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterSpec {
+    /// Two `Pt::stages` at `stage_hz`, 3 dB down well below it.
+    Pt2Stages {
+        stage_hz: f32,
+    },
+    Pt1 {
+        cutoff_hz: f32,
+    },
+    Pt2 {
+        cutoff_hz: f32,
+    },
+    Pt3 {
+        cutoff_hz: f32,
+    },
+    LowPass {
+        cutoff_hz: f32,
+    },
+    Notch {
+        center_hz: f32,
+        q: f32,
+    },
+}
+
+impl FilterSpec {
+    pub fn low_pass(kind: f32, cutoff_hz: f32) -> FilterSpec {
+        match kind as u8 {
+            1 => FilterSpec::Pt1 { cutoff_hz },
+            2 => FilterSpec::Pt2 { cutoff_hz },
+            3 => FilterSpec::Pt3 { cutoff_hz },
+            4 => FilterSpec::LowPass { cutoff_hz },
+            _ => FilterSpec::Pt2Stages {
+                stage_hz: cutoff_hz,
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Filter {
+    Pt1(Pt1),
+    Pt2(Pt2),
+    Pt3(Pt3),
+    Biquad(BiquadCoeffs, Biquad),
+}
+
+impl Filter {
+    pub fn new(spec: &FilterSpec, sample_hz: f32) -> Filter {
+        match *spec {
+            FilterSpec::Pt2Stages { stage_hz } => Filter::Pt2(Pt::stages(stage_hz, sample_hz)),
+            FilterSpec::Pt1 { cutoff_hz } => Filter::Pt1(Pt::new(cutoff_hz, sample_hz)),
+            FilterSpec::Pt2 { cutoff_hz } => Filter::Pt2(Pt::new(cutoff_hz, sample_hz)),
+            FilterSpec::Pt3 { cutoff_hz } => Filter::Pt3(Pt::new(cutoff_hz, sample_hz)),
+            FilterSpec::LowPass { cutoff_hz } => Filter::Biquad(
+                BiquadCoeffs::low_pass(cutoff_hz, core::f32::consts::FRAC_1_SQRT_2, sample_hz),
+                Biquad::default(),
+            ),
+            FilterSpec::Notch { center_hz, q } => Filter::Biquad(
+                BiquadCoeffs::notch(center_hz, q, sample_hz),
+                Biquad::default(),
+            ),
+        }
+    }
+
+    pub fn apply(&mut self, input: f32) -> f32 {
+        match self {
+            Filter::Pt1(pt) => pt.apply(input),
+            Filter::Pt2(pt) => pt.apply(input),
+            Filter::Pt3(pt) => pt.apply(input),
+            Filter::Biquad(coeffs, biquad) => biquad.apply(coeffs, input),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Peak output over the last half of `samples` of a unit sine at `hz`.
+    pub(crate) fn gain(
+        hz: f32,
+        sample_hz: f32,
+        samples: usize,
+        mut f: impl FnMut(f32) -> f32,
+    ) -> f32 {
+        (0..samples)
+            .map(|n| f(libm::sinf(2.0 * PI * hz * n as f32 / sample_hz)))
+            .skip(samples / 2)
+            .fold(0.0, |peak, y| y.abs().max(peak))
+    }
+
+    #[test]
+    fn notch_removes_its_center_only() {
+        let coeffs = BiquadCoeffs::notch(200.0, 5.0, 1000.0);
+        let run = |hz| {
+            let mut notch = Biquad::default();
+            gain(hz, 1000.0, 2000, |x| notch.apply(&coeffs, x))
+        };
+        assert!(run(200.0) < 0.01);
+        assert!(run(20.0) > 0.99);
+        assert!(run(400.0) > 0.9);
+    }
+
+    #[test]
+    fn pt_filters_are_3db_down_at_cutoff() {
+        let mut pt1 = Pt1::new(50.0, 1000.0);
+        let mut pt2 = Pt2::new(50.0, 1000.0);
+        let mut pt3 = Pt3::new(50.0, 1000.0);
+        for g in [
+            gain(50.0, 1000.0, 2000, |x| pt1.apply(x)),
+            gain(50.0, 1000.0, 2000, |x| pt2.apply(x)),
+            gain(50.0, 1000.0, 2000, |x| pt3.apply(x)),
+        ] {
+            assert!((g - core::f32::consts::FRAC_1_SQRT_2).abs() < 0.05, "{g}");
+        }
+    }
+
+    #[test]
+    fn higher_orders_roll_off_faster() {
+        let stop = |f: &mut dyn FnMut(f32) -> f32| gain(200.0, 1000.0, 2000, f);
+        let mut pt1 = Pt1::new(50.0, 1000.0);
+        let mut pt2 = Pt2::new(50.0, 1000.0);
+        let mut pt3 = Pt3::new(50.0, 1000.0);
+        let (g1, g2, g3) = (
+            stop(&mut |x| pt1.apply(x)),
+            stop(&mut |x| pt2.apply(x)),
+            stop(&mut |x| pt3.apply(x)),
+        );
+        assert!(g1 < 0.3 && g2 < g1 && g3 < g2, "{g1} {g2} {g3}");
+    }
+
+    #[test]
+    fn pt_settles_to_input() {
+        let mut pt = Pt2::new(40.0, 1000.0);
+        let first = pt.apply(1.0);
+        assert!(first > 0.0 && first < 0.2);
+        for _ in 0..1000 {
+            pt.apply(1.0);
+        }
+        assert!((pt.apply(1.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn biquad_low_pass_response() {
+        let coeffs = BiquadCoeffs::low_pass(100.0, core::f32::consts::FRAC_1_SQRT_2, 1000.0);
+        let run = |hz| {
+            let mut lpf = Biquad::default();
+            gain(hz, 1000.0, 2000, |x| lpf.apply(&coeffs, x))
+        };
+        assert!(run(5.0) > 0.99);
+        assert!((run(100.0) - core::f32::consts::FRAC_1_SQRT_2).abs() < 0.02);
+        // 12 dB per octave
+        assert!(run(400.0) < 0.1);
+    }
+
+    #[test]
+    fn pt2_stages_keep_the_old_pid_response() {
+        let run = |hz| {
+            let mut pt = Pt2::stages(40.0, 1000.0);
+            gain(hz, 1000.0, 4000, |x| pt.apply(x))
+        };
+        assert!((run(23.0) - core::f32::consts::FRAC_1_SQRT_2).abs() < 0.02);
+        assert!((run(40.0) - 0.445).abs() < 0.02);
+    }
+
+    #[test]
+    fn specs_build_their_filter() {
+        let run = |spec| {
+            let mut filter = Filter::new(&spec, 1000.0);
+            gain(50.0, 1000.0, 2000, |x| filter.apply(x))
+        };
+        for kind in 1..=4 {
+            let g = run(FilterSpec::low_pass(kind as f32, 50.0));
+            assert!(
+                (g - core::f32::consts::FRAC_1_SQRT_2).abs() < 0.05,
+                "{kind} {g}"
+            );
+        }
+        assert!(run(FilterSpec::low_pass(0.0, 50.0)) < 0.6);
+        let notch = FilterSpec::Notch {
+            center_hz: 50.0,
+            q: 5.0,
+        };
+        assert!(run(notch) < 0.01);
+    }
+}
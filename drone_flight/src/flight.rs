@@ -0,0 +1,208 @@
+use crate::{
+    acro::Acro,
+    alt_estimator::AltitudeEstimator,
+    alt_hold::AltHold,
+    arming::{ARM_REQUEST, ARM_RESULT, Arming, ArmingContext},
+    attitude::Attitude,
+    battery::{BatteryState, Level},
+    blackbox::{Snapshot, modes},
+    consts::{CYCLE_TIME, TELE_ESC_RPM},
+    esc::{Erpm, EscMonitor, Rpm},
+    failsafe::{Failsafe, Stage},
+    horizon::Horizon,
+    imu::ImuData,
+    mixer::Outputs,
+    motor::MotorInput,
+    params::Params,
+    prearm::PreArm,
+    rc::RcData,
+    switch::{Switch, SwitchState},
+};
+use drone_consts::telemetry::Category;
+
+const ZERO_RC: RcData = RcData::from_channels([0; 16]);
+
+/// One tick of the flight loop, independent of where the sensor data comes from.
+///
+/// The firmware feeds it from the IMU/RC/baro watches, the simulator from its
+/// rigid-body model. Returns motor and servo outputs only while armed.
+pub struct FlightController {
+    motor: MotorInput,
+    arming: Switch<Arming>,
+    prearm: PreArm,
+    esc: EscMonitor,
+    failsafe: Failsafe,
+    alt_hold: Switch<AltHold>,
+    acro: Switch<Acro>,
+    horizon: Switch<Horizon>,
+    att_transformer: Attitude,
+    alt_estimator: AltitudeEstimator,
+    snapshot: Snapshot,
+    battery_level: Level,
+}
+
+impl FlightController {
+    pub fn new(params: &Params) -> FlightController {
+        let motor = MotorInput::new(CYCLE_TIME, params);
+        FlightController {
+            esc: EscMonitor::new(params, motor.mixer().used_pins()),
+            motor,
+            arming: Switch::new(),
+            prearm: PreArm::new(params),
+            failsafe: Failsafe::new(params),
+            alt_hold: Switch::new(),
+            acro: Switch::new(),
+            horizon: Switch::new(),
+            att_transformer: Attitude::new(params.ahrs_beta),
+            alt_estimator: AltitudeEstimator::new(),
+            snapshot: Snapshot::default(),
+            battery_level: Level::Ok,
+        }
+    }
+
+    /// Picks up new gains and limits, accelerometer calibration needs a reboot.
+    pub fn apply_params(&mut self, params: &Params) {
+        self.motor = MotorInput::new(CYCLE_TIME, params);
+        self.esc = EscMonitor::new(params, self.motor.mixer().used_pins());
+        self.att_transformer.set_beta(params.ahrs_beta);
+        self.prearm.apply_params(params);
+        self.failsafe.apply_params(params);
+    }
+
+    pub fn update(
+        &mut self,
+        imu: Option<ImuData>,
+        rc: Option<RcData>,
+        baro_alt: Option<f32>,
+        erpm: &Erpm,
+        battery: Option<&BatteryState>,
+    ) -> Option<Outputs> {
+        // ESC replies answer the previous tick's commands
+        self.esc.update(erpm, &self.snapshot.motors);
+        let rpm = self.esc.rpm().map(|r| r.unwrap_or(-1.0));
+        tele!(
+            TELE_ESC_RPM,
+            rpm[0],
+            rpm[1],
+            rpm[2],
+            rpm[3],
+            rpm[4],
+            rpm[5],
+            self.esc.faults()
+        );
+
+        // Without a link or battery the failsafe flies on stand-in sticks until it has landed
+        let link = rc.is_some();
+        self.battery_level = battery.map_or(Level::Ok, |b| b.level);
+        let rc = self.failsafe.update(
+            rc,
+            self.is_armed(),
+            self.snapshot.alt,
+            self.alt_estimator.velocity(),
+            self.battery_level == Level::Critical,
+        );
+        let rc_ref = rc.as_ref().unwrap_or(&ZERO_RC);
+        // Tilt is judged on the previous tick's attitude, this one isn't estimated yet
+        let ctx = ArmingContext {
+            rc_valid: rc.is_some(),
+            prearm_failures: self.prearm.update(
+                imu.as_ref(),
+                link,
+                baro_alt.is_some(),
+                self.esc.all_reporting(),
+                battery.is_some_and(|b| b.level == Level::Ok),
+                &self.snapshot.att,
+            ),
+        };
+        self.arming.update(rc_ref, ctx);
+        if let Some(on) = ARM_REQUEST.try_take() {
+            ARM_RESULT.signal(self.arming.command(on, rc_ref, ctx));
+        }
+        self.alt_hold.update(rc_ref, self.is_armed());
+        let level = self.failsafe.stage() == Stage::Descend;
+        self.acro.update(rc_ref, level);
+        self.horizon.update(rc_ref, level);
+        self.snapshot.modes = self.modes();
+        self.motor.set_battery(battery);
+        self.snapshot.motors = Default::default();
+        self.snapshot.servo = 0;
+
+        let (Some(imu), Some(rc), Some(baro_alt)) = (imu, rc, baro_alt) else {
+            return None;
+        };
+
+        let quat = self
+            .att_transformer
+            .update(&imu.gyro, &imu.acc, &imu.mag, imu.dt)?;
+        let alt = self.alt_estimator.update(&quat, &imu, baro_alt);
+        let att: [f32; 3] = quat.euler_angles().into();
+        tele!(
+            Category::Attitude,
+            att[0],
+            att[1],
+            att[2],
+            alt,
+            self.prearm.failures() as f32,
+            self.failsafe.stage() as u8 as f32
+        );
+
+        let outputs = self.motor.update(&rc, &imu, &att, alt, self.snapshot.modes);
+
+        let outputs = self.is_armed().then_some(outputs);
+
+        self.snapshot.gyro = imu.gyro.into();
+        self.snapshot.acc = imu.acc.into();
+        self.snapshot.att = att;
+        self.snapshot.alt = alt;
+        self.snapshot.rc = [rc.roll(), rc.pitch(), rc.throttle(), rc.yaw()];
+        self.snapshot.pid = self.motor.pid_out();
+        let Outputs { motors, servo } = outputs.unwrap_or_default();
+        self.snapshot.motors = motors;
+        self.snapshot.servo = servo;
+
+        outputs
+    }
+
+    /// Motor RPM the ESCs reported on the last update.
+    pub fn rpm(&self) -> &Rpm {
+        self.esc.rpm()
+    }
+
+    /// State of the last update, for the blackbox.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    fn modes(&self) -> u8 {
+        let mut bits = 0;
+        if self.is_armed() {
+            bits |= modes::ARMED;
+        }
+        if self.alt_hold.state() == SwitchState::Active {
+            bits |= modes::ALT_HOLD;
+        }
+        if self.acro.state() == SwitchState::Active {
+            bits |= modes::ACRO;
+        }
+        if self.horizon.state() == SwitchState::Active {
+            bits |= modes::HORIZON;
+        }
+        match self.failsafe.stage() {
+            Stage::Hold => bits |= modes::FAILSAFE,
+            Stage::Descend => bits |= modes::FAILSAFE | modes::FS_DESCENT,
+            Stage::Idle | Stage::Landed => {}
+        }
+        if self.esc.faults() != 0 {
+            bits |= modes::MOTOR_FAULT;
+        }
+        if self.battery_level != Level::Ok {
+            bits |= modes::BATTERY_LOW;
+        }
+        bits
+    }
+
+    #[inline(always)]
+    pub fn is_armed(&self) -> bool {
+        self.arming.state() == SwitchState::Active
+    }
+}
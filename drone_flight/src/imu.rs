@@ -0,0 +1,9 @@
+use nalgebra::Vector3;
+
+#[derive(Clone)]
+pub struct ImuData {
+    pub gyro: Vector3<f32>,
+    pub acc: Vector3<f32>,
+    pub mag: Vector3<f32>,
+    pub dt: f32,
+}
@@ -0,0 +1,35 @@
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+pub mod telemetry;
+
+pub mod acro;
+pub mod alt_estimator;
+pub mod alt_hold;
+pub mod arming;
+pub mod attitude;
+pub mod battery;
+pub mod blackbox;
+pub mod consts;
+pub mod dyn_notch;
+pub mod esc;
+pub mod failsafe;
+pub mod filter;
+pub mod flight;
+pub mod horizon;
+pub mod imu;
+pub mod mavlink;
+pub mod mixer;
+pub mod motor;
+pub mod msp;
+pub mod params;
+pub mod pid;
+pub mod prearm;
+pub mod protocol;
+pub mod rates;
+pub mod rc;
+pub mod rc_protocol;
+pub mod rpm_filter;
+pub mod schedule;
+pub mod switch;
+pub mod ticks;
@@ -0,0 +1,734 @@
+use crate::mixer::MAX_MOTORS;
+use crate::params::{PARAM_COUNT, PARAM_INFO, Params};
+use crc::{CRC_16_MCRF4XX, Crc};
+
+// MAVLink v2 subset, enough for ground station status, params and arming.
+// Frame: STX, len, incompat_flags, compat_flags, seq, sysid, compid, msgid u24, payload[len], crc16
+// CRC-16/MCRF4XX over len..payload plus the message's CRC_EXTRA byte. Trailing zero payload bytes
+// are trimmed on send and zero-filled on receive. Signed frames are accepted without checking.
+pub const STX: u8 = 0xfd;
+pub const SYSTEM_ID: u8 = 1;
+pub const COMPONENT_ID: u8 = 1;
+pub const MAX_PAYLOAD: usize = 48;
+const HEADER_SIZE: usize = 10;
+const CRC_SIZE: usize = 2;
+const SIGNATURE_SIZE: usize = 13;
+const FLAG_SIGNED: u8 = 0x01;
+pub const MAX_FRAME: usize = HEADER_SIZE + MAX_PAYLOAD + CRC_SIZE;
+
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_MCRF4XX);
+
+pub mod msg {
+    pub const HEARTBEAT: u32 = 0;
+    pub const SYS_STATUS: u32 = 1;
+    pub const PARAM_REQUEST_READ: u32 = 20;
+    pub const PARAM_REQUEST_LIST: u32 = 21;
+    pub const PARAM_VALUE: u32 = 22;
+    pub const PARAM_SET: u32 = 23;
+    pub const ATTITUDE: u32 = 30;
+    pub const SERVO_OUTPUT_RAW: u32 = 36;
+    pub const RC_CHANNELS: u32 = 65;
+    pub const VFR_HUD: u32 = 74;
+    pub const COMMAND_LONG: u32 = 76;
+    pub const COMMAND_ACK: u32 = 77;
+    pub const RAW_RPM: u32 = 339;
+}
+
+// (message id, CRC_EXTRA)
+const CRC_EXTRA: &[(u32, u8)] = &[
+    (msg::HEARTBEAT, 50),
+    (msg::SYS_STATUS, 124),
+    (msg::PARAM_REQUEST_READ, 214),
+    (msg::PARAM_REQUEST_LIST, 159),
+    (msg::PARAM_VALUE, 220),
+    (msg::PARAM_SET, 168),
+    (msg::ATTITUDE, 39),
+    (msg::SERVO_OUTPUT_RAW, 222),
+    (msg::RC_CHANNELS, 118),
+    (msg::VFR_HUD, 20),
+    (msg::COMMAND_LONG, 152),
+    (msg::COMMAND_ACK, 143),
+    (msg::RAW_RPM, 199),
+];
+
+fn crc_extra(id: u32) -> Option<u8> {
+    CRC_EXTRA
+        .iter()
+        .find(|(msg, _)| *msg == id)
+        .map(|(_, extra)| *extra)
+}
+
+pub const MAV_CMD_PREFLIGHT_STORAGE: u16 = 245;
+pub const MAV_CMD_COMPONENT_ARM_DISARM: u16 = 400;
+
+pub const MAV_RESULT_ACCEPTED: u8 = 0;
+pub const MAV_RESULT_TEMPORARILY_REJECTED: u8 = 1;
+pub const MAV_RESULT_DENIED: u8 = 2;
+pub const MAV_RESULT_UNSUPPORTED: u8 = 3;
+pub const MAV_RESULT_FAILED: u8 = 4;
+
+const MAV_TYPE_QUADROTOR: u8 = 2;
+const MAV_AUTOPILOT_GENERIC: u8 = 0;
+const MAV_MODE_FLAG_SAFETY_ARMED: u8 = 0x80;
+const MAV_MODE_FLAG_MANUAL_INPUT_ENABLED: u8 = 0x40;
+const MAV_MODE_FLAG_STABILIZE_ENABLED: u8 = 0x10;
+const MAV_MODE_FLAG_CUSTOM_MODE_ENABLED: u8 = 0x01;
+const MAV_STATE_STANDBY: u8 = 3;
+const MAV_STATE_ACTIVE: u8 = 4;
+const MAV_PARAM_TYPE_REAL32: u8 = 9;
+const PARAM_ID_SIZE: usize = 16;
+
+pub mod sensors {
+    pub const GYRO: u32 = 1 << 0;
+    pub const ACCEL: u32 = 1 << 1;
+    pub const MAG: u32 = 1 << 2;
+    pub const ABSOLUTE_PRESSURE: u32 = 1 << 3;
+    pub const ANGULAR_RATE_CONTROL: u32 = 1 << 10;
+    pub const ATTITUDE_STABILIZATION: u32 = 1 << 11;
+    pub const Z_ALTITUDE_CONTROL: u32 = 1 << 13;
+    pub const MOTOR_OUTPUTS: u32 = 1 << 15;
+    pub const RC_RECEIVER: u32 = 1 << 16;
+    pub const BATTERY: u32 = 1 << 25;
+    pub const PREARM_CHECK: u32 = 1 << 28;
+}
+
+/// Messages sent to the ground station.
+pub enum Outgoing<'a> {
+    /// `custom_mode` carries the blackbox mode bits.
+    Heartbeat {
+        armed: bool,
+        custom_mode: u32,
+    },
+    /// `voltage_mv` u16::MAX and `current_ca` -1 when unknown.
+    SysStatus {
+        present: u32,
+        healthy: u32,
+        voltage_mv: u16,
+        current_ca: i16,
+    },
+    Attitude {
+        time_ms: u32,
+        att: [f32; 3],
+        rates: [f32; 3],
+    },
+    RcChannels {
+        time_ms: u32,
+        channels_us: &'a [u16],
+        rssi: u8,
+    },
+    ServoOutputRaw {
+        time_us: u32,
+        outputs_us: [u16; MAX_MOTORS],
+    },
+    VfrHud {
+        alt: f32,
+        climb: f32,
+        heading: i16,
+        throttle: u16,
+    },
+    ParamValue {
+        index: usize,
+        value: f32,
+    },
+    CommandAck {
+        command: u16,
+        result: u8,
+    },
+    /// One message per motor, `index` being its pin.
+    RawRpm {
+        index: u8,
+        rpm: f32,
+    },
+}
+
+struct Payload {
+    buf: [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Payload {
+    fn new() -> Payload {
+        Payload {
+            buf: [0; MAX_PAYLOAD],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> &mut Payload {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        self
+    }
+}
+
+impl Outgoing<'_> {
+    fn payload(&self) -> (u32, Payload) {
+        let mut p = Payload::new();
+        let id = match self {
+            Outgoing::Heartbeat { armed, custom_mode } => {
+                let mut base_mode = MAV_MODE_FLAG_MANUAL_INPUT_ENABLED
+                    | MAV_MODE_FLAG_STABILIZE_ENABLED
+                    | MAV_MODE_FLAG_CUSTOM_MODE_ENABLED;
+                let mut state = MAV_STATE_STANDBY;
+                if *armed {
+                    base_mode |= MAV_MODE_FLAG_SAFETY_ARMED;
+                    state = MAV_STATE_ACTIVE;
+                }
+                p.push(&custom_mode.to_le_bytes()).push(&[
+                    MAV_TYPE_QUADROTOR,
+                    MAV_AUTOPILOT_GENERIC,
+                    base_mode,
+                    state,
+                    3,
+                ]);
+                msg::HEARTBEAT
+            }
+            Outgoing::SysStatus {
+                present,
+                healthy,
+                voltage_mv,
+                current_ca,
+            } => {
+                p.push(&present.to_le_bytes())
+                    .push(&present.to_le_bytes())
+                    .push(&healthy.to_le_bytes())
+                    .push(&0u16.to_le_bytes()) // load
+                    .push(&voltage_mv.to_le_bytes())
+                    .push(&current_ca.to_le_bytes())
+                    .push(&[0; 12]) // drop rate, comm and sensor errors
+                    .push(&[-1i8 as u8]); // remaining unknown
+                msg::SYS_STATUS
+            }
+            Outgoing::Attitude {
+                time_ms,
+                att,
+                rates,
+            } => {
+                p.push(&time_ms.to_le_bytes());
+                for v in att.iter().chain(rates) {
+                    p.push(&v.to_le_bytes());
+                }
+                msg::ATTITUDE
+            }
+            Outgoing::RcChannels {
+                time_ms,
+                channels_us,
+                rssi,
+            } => {
+                p.push(&time_ms.to_le_bytes());
+                for i in 0..18 {
+                    // UINT16_MAX marks channels that are not there
+                    let us = channels_us.get(i).copied().unwrap_or(u16::MAX);
+                    p.push(&us.to_le_bytes());
+                }
+                p.push(&[channels_us.len().min(18) as u8, *rssi]);
+                msg::RC_CHANNELS
+            }
+            Outgoing::ServoOutputRaw {
+                time_us,
+                outputs_us,
+            } => {
+                p.push(&time_us.to_le_bytes());
+                for i in 0..8 {
+                    let us = outputs_us.get(i).copied().unwrap_or(0);
+                    p.push(&us.to_le_bytes());
+                }
+                p.push(&[0]); // port
+                msg::SERVO_OUTPUT_RAW
+            }
+            Outgoing::VfrHud {
+                alt,
+                climb,
+                heading,
+                throttle,
+            } => {
+                p.push(&0f32.to_le_bytes()) // airspeed
+                    .push(&0f32.to_le_bytes()) // groundspeed
+                    .push(&alt.to_le_bytes())
+                    .push(&climb.to_le_bytes())
+                    .push(&heading.to_le_bytes())
+                    .push(&throttle.to_le_bytes());
+                msg::VFR_HUD
+            }
+            Outgoing::ParamValue { index, value } => {
+                let mut id = [0u8; PARAM_ID_SIZE];
+                let name = PARAM_INFO
+                    .get(*index)
+                    .map_or("", |info| info.name)
+                    .as_bytes();
+                let n = name.len().min(PARAM_ID_SIZE);
+                id[..n].copy_from_slice(&name[..n]);
+                p.push(&value.to_le_bytes())
+                    .push(&(PARAM_COUNT as u16).to_le_bytes())
+                    .push(&(*index as u16).to_le_bytes())
+                    .push(&id)
+                    .push(&[MAV_PARAM_TYPE_REAL32]);
+                msg::PARAM_VALUE
+            }
+            Outgoing::CommandAck { command, result } => {
+                p.push(&command.to_le_bytes()).push(&[*result]);
+                msg::COMMAND_ACK
+            }
+            Outgoing::RawRpm { index, rpm } => {
+                p.push(&rpm.to_le_bytes()).push(&[*index]);
+                msg::RAW_RPM
+            }
+        };
+        (id, p)
+    }
+
+    pub fn encode(&self, seq: u8, out: &mut [u8; MAX_FRAME]) -> usize {
+        let (id, payload) = self.payload();
+        let mut len = payload.len;
+        while len > 1 && payload.buf[len - 1] == 0 {
+            len -= 1;
+        }
+
+        out[0] = STX;
+        out[1] = len as u8;
+        out[2] = 0;
+        out[3] = 0;
+        out[4] = seq;
+        out[5] = SYSTEM_ID;
+        out[6] = COMPONENT_ID;
+        out[7..10].copy_from_slice(&id.to_le_bytes()[..3]);
+        out[HEADER_SIZE..HEADER_SIZE + len].copy_from_slice(&payload.buf[..len]);
+        let end = HEADER_SIZE + len;
+        let crc = checksum(&out[1..end], id);
+        out[end..end + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+        end + CRC_SIZE
+    }
+}
+
+fn checksum(data: &[u8], id: u32) -> u16 {
+    let mut digest = CRC.digest();
+    digest.update(data);
+    digest.update(&[crc_extra(id).unwrap_or(0)]);
+    digest.finalize()
+}
+
+/// Messages understood from the ground station.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Heartbeat,
+    ParamRequestList,
+    /// Looked up by `index`, or by name when it is negative.
+    ParamRequestRead {
+        index: i16,
+        id: [u8; PARAM_ID_SIZE],
+    },
+    ParamSet {
+        id: [u8; PARAM_ID_SIZE],
+        value: f32,
+    },
+    CommandLong {
+        command: u16,
+        params: [f32; 7],
+    },
+}
+
+fn f32_at(p: &[u8], at: usize) -> f32 {
+    f32::from_le_bytes([p[at], p[at + 1], p[at + 2], p[at + 3]])
+}
+
+fn u16_at(p: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([p[at], p[at + 1]])
+}
+
+fn for_us(target_system: u8) -> bool {
+    target_system == 0 || target_system == SYSTEM_ID
+}
+
+impl Message {
+    /// `None` for messages that are ignored or addressed to another system.
+    fn decode(id: u32, p: &[u8; MAX_PAYLOAD]) -> Option<Message> {
+        let param_id = |at: usize| {
+            let mut id = [0u8; PARAM_ID_SIZE];
+            id.copy_from_slice(&p[at..at + PARAM_ID_SIZE]);
+            id
+        };
+        match id {
+            msg::HEARTBEAT => Some(Message::Heartbeat),
+            msg::PARAM_REQUEST_LIST if for_us(p[0]) => Some(Message::ParamRequestList),
+            msg::PARAM_REQUEST_READ if for_us(p[2]) => Some(Message::ParamRequestRead {
+                index: u16_at(p, 0) as i16,
+                id: param_id(4),
+            }),
+            msg::PARAM_SET if for_us(p[4]) => Some(Message::ParamSet {
+                id: param_id(6),
+                value: f32_at(p, 0),
+            }),
+            msg::COMMAND_LONG if for_us(p[30]) => Some(Message::CommandLong {
+                command: u16_at(p, 28),
+                params: core::array::from_fn(|i| f32_at(p, i * 4)),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Byte-wise frame decoder. Frames too long to buffer or with unknown ids are skipped.
+pub struct Parser {
+    buf: [u8; MAX_FRAME],
+    pos: usize,
+    total: usize,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            buf: [0; MAX_FRAME],
+            pos: 0,
+            total: 0,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pos == 0
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Message> {
+        if self.pos == 0 && byte != STX {
+            return None;
+        }
+        if self.pos < MAX_FRAME {
+            self.buf[self.pos] = byte;
+        }
+        self.pos += 1;
+
+        if self.pos == 3 {
+            let signature = if self.buf[2] & FLAG_SIGNED != 0 {
+                SIGNATURE_SIZE
+            } else {
+                0
+            };
+            self.total = HEADER_SIZE + self.buf[1] as usize + CRC_SIZE + signature;
+        }
+        if self.pos < 3 || self.pos < self.total {
+            return None;
+        }
+        self.pos = 0;
+
+        let len = self.buf[1] as usize;
+        let id = u32::from_le_bytes([self.buf[7], self.buf[8], self.buf[9], 0]);
+        crc_extra(id)?;
+        if len > MAX_PAYLOAD {
+            return None;
+        }
+        let end = HEADER_SIZE + len;
+        let crc = u16::from_le_bytes([self.buf[end], self.buf[end + 1]]);
+        if checksum(&self.buf[1..end], id) != crc {
+            return None;
+        }
+
+        let mut payload = [0u8; MAX_PAYLOAD];
+        payload[..len].copy_from_slice(&self.buf[HEADER_SIZE..end]);
+        Message::decode(id, &payload)
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+/// What the caller has to do for a message, mirrors `protocol::Action`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    None,
+    SendParam(usize),
+    SendAllParams,
+    /// The param was changed, send it back and apply it.
+    Apply(usize),
+    Save,
+    /// Arm or disarm through the arming switch, ack with the outcome.
+    Arm(bool),
+    Ack(u16, u8),
+}
+
+fn find_param(id: &[u8; PARAM_ID_SIZE]) -> Option<usize> {
+    let len = id.iter().position(|b| *b == 0).unwrap_or(PARAM_ID_SIZE);
+    Params::find(core::str::from_utf8(&id[..len]).ok()?)
+}
+
+/// Parameter writes and saving are refused while `armed`, a refused write still echoes the value.
+pub fn handle(message: &Message, params: &mut Params, armed: bool) -> Action {
+    match message {
+        Message::Heartbeat => Action::None,
+        Message::ParamRequestList => Action::SendAllParams,
+        Message::ParamRequestRead { index, id } => {
+            let index = if *index >= 0 {
+                Some(*index as usize).filter(|i| *i < PARAM_COUNT)
+            } else {
+                find_param(id)
+            };
+            index.map_or(Action::None, Action::SendParam)
+        }
+        Message::ParamSet { id, value } => match find_param(id) {
+            Some(index) if armed => Action::SendParam(index),
+            Some(index) => match params.set(index, *value) {
+                Ok(()) => Action::Apply(index),
+                Err(_) => Action::SendParam(index),
+            },
+            None => Action::None,
+        },
+        Message::CommandLong { command, params } => match *command {
+            MAV_CMD_COMPONENT_ARM_DISARM => Action::Arm(params[0] == 1.0),
+            MAV_CMD_PREFLIGHT_STORAGE if armed => Action::Ack(*command, MAV_RESULT_DENIED),
+            MAV_CMD_PREFLIGHT_STORAGE if params[0] == 1.0 => Action::Save,
+            _ => Action::Ack(*command, MAV_RESULT_UNSUPPORTED),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built independently from the MAVLink definitions, seq 7
+    const HEARTBEAT_ARMED: [u8; 21] = [
+        0xfd, 0x09, 0x00, 0x00, 0x07, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0xd1, 0x04, 0x03, 0x06, 0x9c,
+    ];
+
+    fn frame(id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![STX, payload.len() as u8, 0, 0, 0, 255, 190];
+        out.extend_from_slice(&id.to_le_bytes()[..3]);
+        out.extend_from_slice(payload);
+        let crc = checksum(&out[1..], id);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    fn param_id(name: &str) -> [u8; PARAM_ID_SIZE] {
+        let mut id = [0; PARAM_ID_SIZE];
+        id[..name.len()].copy_from_slice(name.as_bytes());
+        id
+    }
+
+    fn param_set(name: &str, value: f32) -> Vec<u8> {
+        let mut payload = value.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[SYSTEM_ID, COMPONENT_ID]);
+        payload.extend_from_slice(&param_id(name));
+        payload.push(MAV_PARAM_TYPE_REAL32);
+        frame(msg::PARAM_SET, &payload)
+    }
+
+    fn command(command: u16, param1: f32) -> Message {
+        let mut params = [0.0; 7];
+        params[0] = param1;
+        Message::CommandLong { command, params }
+    }
+
+    #[test]
+    fn heartbeat_matches_reference() {
+        let mut out = [0; MAX_FRAME];
+        let heartbeat = Outgoing::Heartbeat {
+            armed: true,
+            custom_mode: 0,
+        };
+        let n = heartbeat.encode(7, &mut out);
+        assert_eq!(&out[..n], &HEARTBEAT_ARMED);
+    }
+
+    #[test]
+    fn trailing_zeros_are_trimmed() {
+        let mut out = [0; MAX_FRAME];
+        let ack = Outgoing::CommandAck {
+            command: MAV_CMD_COMPONENT_ARM_DISARM,
+            result: MAV_RESULT_ACCEPTED,
+        };
+        let n = ack.encode(0, &mut out);
+        assert_eq!(out[1], 2);
+        assert_eq!(n, HEADER_SIZE + 2 + CRC_SIZE);
+    }
+
+    #[test]
+    fn raw_rpm_has_a_three_byte_id() {
+        let mut out = [0; MAX_FRAME];
+        let rpm = Outgoing::RawRpm {
+            index: 2,
+            rpm: 12_000.0,
+        };
+        let n = rpm.encode(0, &mut out);
+        assert_eq!(out[1], 5);
+        assert_eq!(&out[7..10], &[0x53, 0x01, 0x00]);
+        assert_eq!(
+            &out[HEADER_SIZE..HEADER_SIZE + 5],
+            &[0, 0x80, 0x3b, 0x46, 2]
+        );
+        // CRC_EXTRA 199 from the message definition
+        assert_eq!(&out[n - 2..n], &[0x06, 0xc3]);
+    }
+
+    #[test]
+    fn parses_after_garbage_and_skips_unknown() {
+        // A stray STX in the garbage costs one bogus frame, not the real ones
+        let mut bytes = vec![0x00, STX, 0x00, 0x42];
+        bytes.extend(frame(0x1234, &[1, 2, 3]));
+        bytes.extend(frame(msg::RC_CHANNELS, &[0; 42]));
+        bytes.extend(param_set("rate_kp", 0.2));
+        let mut parser = Parser::new();
+        let messages: Vec<_> = bytes.iter().filter_map(|&b| parser.push(b)).collect();
+        assert_eq!(
+            messages,
+            vec![Message::ParamSet {
+                id: param_id("rate_kp"),
+                value: 0.2
+            }]
+        );
+    }
+
+    #[test]
+    fn corrupt_and_foreign_frames_are_dropped() {
+        let mut parser = Parser::new();
+        let mut bytes = param_set("rate_kp", 0.2);
+        bytes[12] ^= 1;
+        assert!(bytes.iter().all(|&b| parser.push(b).is_none()));
+
+        let mut payload = 0.2f32.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[SYSTEM_ID + 1, COMPONENT_ID]);
+        payload.extend_from_slice(&param_id("rate_kp"));
+        let bytes = frame(msg::PARAM_SET, &payload);
+        assert!(bytes.iter().all(|&b| parser.push(b).is_none()));
+        assert!(parser.is_idle());
+    }
+
+    #[test]
+    fn truncated_payload_is_zero_filled() {
+        // Sender trimmed the last byte, the parser has to pad it back
+        let mut bytes = param_set("rate_kp", 0.2);
+        let len = bytes[1] as usize - 1;
+        bytes.truncate(HEADER_SIZE + len);
+        bytes[1] = len as u8;
+        let crc = checksum(&bytes[1..], msg::PARAM_SET);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        let mut parser = Parser::new();
+        assert!(bytes.iter().find_map(|&b| parser.push(b)).is_some());
+    }
+
+    #[test]
+    fn signed_and_oversized_frames_keep_the_stream_in_step() {
+        // The flags are under the CRC, the signature after it is not checked
+        let mut signed = param_set("rate_kp", 0.2);
+        signed[2] = FLAG_SIGNED;
+        let end = signed.len() - CRC_SIZE;
+        let crc = checksum(&signed[1..end], msg::PARAM_SET);
+        signed[end..].copy_from_slice(&crc.to_le_bytes());
+        signed.extend_from_slice(&[0xaa; SIGNATURE_SIZE]);
+        // Longer than we buffer, skipped by its length byte
+        let mut bytes = frame(msg::COMMAND_LONG, &[0; MAX_PAYLOAD + 20]);
+        bytes.extend(&signed);
+        bytes.extend(param_set("alt_ki", 0.1));
+
+        let mut parser = Parser::new();
+        let messages: Vec<_> = bytes.iter().filter_map(|&b| parser.push(b)).collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            Message::ParamSet {
+                id: param_id("rate_kp"),
+                value: 0.2
+            }
+        );
+        assert!(parser.is_idle());
+    }
+
+    #[test]
+    fn command_long_fields_come_from_their_offsets() {
+        let mut payload: Vec<u8> = (1..=7).flat_map(|i| (i as f32).to_le_bytes()).collect();
+        payload.extend_from_slice(&MAV_CMD_COMPONENT_ARM_DISARM.to_le_bytes());
+        payload.extend_from_slice(&[SYSTEM_ID, COMPONENT_ID, 0]);
+        let mut parser = Parser::new();
+        let message = frame(msg::COMMAND_LONG, &payload)
+            .iter()
+            .find_map(|&b| parser.push(b));
+        assert_eq!(
+            message,
+            Some(Message::CommandLong {
+                command: MAV_CMD_COMPONENT_ARM_DISARM,
+                params: [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0],
+            })
+        );
+    }
+
+    #[test]
+    fn param_value_roundtrips_through_set() {
+        let mut params = Params::defaults();
+        let message = Message::ParamSet {
+            id: param_id("max_power"),
+            value: 0.5,
+        };
+        let index = Params::find("max_power").unwrap();
+        assert_eq!(handle(&message, &mut params, false), Action::Apply(index));
+        assert_eq!(params.max_power, 0.5);
+
+        let mut out = [0; MAX_FRAME];
+        let n = Outgoing::ParamValue { index, value: 0.5 }.encode(1, &mut out);
+        let payload = &out[HEADER_SIZE..n - CRC_SIZE];
+        assert_eq!(f32_at(payload, 0), 0.5);
+        assert_eq!(u16_at(payload, 6) as usize, index);
+        assert_eq!(&payload[8..17], b"max_power");
+    }
+
+    #[test]
+    fn writes_are_refused_while_armed() {
+        let mut params = Params::defaults();
+        let index = Params::find("rate_kp").unwrap();
+        let message = Message::ParamSet {
+            id: param_id("rate_kp"),
+            value: 0.2,
+        };
+        assert_eq!(
+            handle(&message, &mut params, true),
+            Action::SendParam(index)
+        );
+        assert_eq!(params, Params::defaults());
+
+        let save = command(MAV_CMD_PREFLIGHT_STORAGE, 1.0);
+        assert_eq!(
+            handle(&save, &mut params, true),
+            Action::Ack(MAV_CMD_PREFLIGHT_STORAGE, MAV_RESULT_DENIED)
+        );
+        assert_eq!(handle(&save, &mut params, false), Action::Save);
+    }
+
+    #[test]
+    fn arm_and_param_requests() {
+        let mut params = Params::defaults();
+        let arm = command(MAV_CMD_COMPONENT_ARM_DISARM, 1.0);
+        assert_eq!(handle(&arm, &mut params, false), Action::Arm(true));
+        let disarm = command(MAV_CMD_COMPONENT_ARM_DISARM, 0.0);
+        assert_eq!(handle(&disarm, &mut params, true), Action::Arm(false));
+        assert_eq!(
+            handle(&command(512, 0.0), &mut params, false),
+            Action::Ack(512, MAV_RESULT_UNSUPPORTED)
+        );
+
+        let by_name = Message::ParamRequestRead {
+            index: -1,
+            id: param_id("alt_ki"),
+        };
+        let index = Params::find("alt_ki").unwrap();
+        assert_eq!(
+            handle(&by_name, &mut params, true),
+            Action::SendParam(index)
+        );
+        let past_the_end = Message::ParamRequestRead {
+            index: PARAM_COUNT as i16,
+            id: param_id(""),
+        };
+        assert_eq!(handle(&past_the_end, &mut params, true), Action::None);
+
+        let mut parser = Parser::new();
+        let list = frame(msg::PARAM_REQUEST_LIST, &[SYSTEM_ID, 0])
+            .iter()
+            .find_map(|&b| parser.push(b));
+        assert_eq!(list, Some(Message::ParamRequestList));
+        assert_eq!(
+            handle(&Message::ParamRequestList, &mut params, true),
+            Action::SendAllParams
+        );
+    }
+}
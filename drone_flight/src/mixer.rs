@@ -0,0 +1,567 @@
+use crate::motor::pid_to_throttle;
+use crate::params::Params;
+use drone_consts::telemetry::Category;
+
+pub const MAX_MOTORS: usize = 6;
+
+// Yaw servo pulse widths in us
+const SERVO_MID_US: f32 = 1500.0;
+const SERVO_THROW_US: f32 = 500.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Frame {
+    QuadX = 0,
+    QuadPlus = 1,
+    HexX = 2,
+    Y6 = 3,
+    Tri = 4,
+}
+
+/// Share of each correction one motor takes, positive speeds it up.
+///
+/// Positive roll speeds up the left side, pitch the back and yaw the props
+/// spinning counter-clockwise seen from above.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MotorMix {
+    pub throttle: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+const fn motor(roll: f32, pitch: f32, yaw: f32) -> MotorMix {
+    MotorMix {
+        throttle: 1.0,
+        roll,
+        pitch,
+        yaw,
+    }
+}
+
+const UNUSED: MotorMix = MotorMix {
+    throttle: 0.0,
+    roll: 0.0,
+    pitch: 0.0,
+    yaw: 0.0,
+};
+
+// sin(60°), for the arms that aren't on an axis
+const S60: f32 = 0.866_025_4;
+
+// Motor orders follow PX4 where it has the frame.
+const QUAD_X: &[MotorMix] = &[
+    motor(-1.0, -1.0, 1.0), // Front Right, CCW
+    motor(1.0, 1.0, 1.0),   // Back Left, CCW
+    motor(1.0, -1.0, -1.0), // Front Left, CW
+    motor(-1.0, 1.0, -1.0), // Back Right, CW
+];
+
+const QUAD_PLUS: &[MotorMix] = &[
+    motor(-1.0, 0.0, 1.0),  // Right, CCW
+    motor(1.0, 0.0, 1.0),   // Left, CCW
+    motor(0.0, -1.0, -1.0), // Front, CW
+    motor(0.0, 1.0, -1.0),  // Back, CW
+];
+
+const HEX_X: &[MotorMix] = &[
+    motor(-1.0, 0.0, -1.0), // Right, CW
+    motor(1.0, 0.0, 1.0),   // Left, CCW
+    motor(0.5, -S60, -1.0), // Front Left, CW
+    motor(-0.5, S60, 1.0),  // Back Right, CCW
+    motor(-0.5, -S60, 1.0), // Front Right, CCW
+    motor(0.5, S60, -1.0),  // Back Left, CW
+];
+
+// Coaxial pairs, the top props spin CW and the bottom ones CCW
+const Y6: &[MotorMix] = &[
+    motor(-S60, -0.5, -1.0), // Front Right top
+    motor(-S60, -0.5, 1.0),  // Front Right bottom
+    motor(0.0, 1.0, -1.0),   // Back top
+    motor(0.0, 1.0, 1.0),    // Back bottom
+    motor(S60, -0.5, -1.0),  // Front Left top
+    motor(S60, -0.5, 1.0),   // Front Left bottom
+];
+
+// Yaw comes from the servo tilting the back motor
+const TRI: &[MotorMix] = &[
+    motor(-S60, -0.5, 0.0), // Front Right
+    motor(S60, -0.5, 0.0),  // Front Left
+    motor(0.0, 1.0, 0.0),   // Back
+];
+
+impl Frame {
+    fn from_param(value: f32) -> Frame {
+        match value as u8 {
+            1 => Frame::QuadPlus,
+            2 => Frame::HexX,
+            3 => Frame::Y6,
+            4 => Frame::Tri,
+            _ => Frame::QuadX,
+        }
+    }
+
+    pub fn motors(self) -> &'static [MotorMix] {
+        match self {
+            Frame::QuadX => QUAD_X,
+            Frame::QuadPlus => QUAD_PLUS,
+            Frame::HexX => HEX_X,
+            Frame::Y6 => Y6,
+            Frame::Tri => TRI,
+        }
+    }
+
+    pub fn has_servo(self) -> bool {
+        self == Frame::Tri
+    }
+}
+
+/// What goes out to the ESCs and the servo on one tick.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Outputs {
+    /// DShot throttle per motor pin, 0 on pins the frame doesn't use.
+    pub motors: [u16; MAX_MOTORS],
+    /// Yaw servo pulse width in us, 0 for no pulses.
+    pub servo: u16,
+}
+
+/// Turns throttle and the roll/pitch/yaw PID outputs into motor commands for the
+/// configured frame, already in the order of the motor pins.
+pub struct Mixer {
+    frame: Frame,
+    pins: [MotorMix; MAX_MOTORS],
+    count: usize,
+    max_power: f32,
+    pid_limit: f32,
+    servo_sign: f32,
+    airmode: bool,
+    gain: f32,
+}
+
+impl Mixer {
+    pub fn new(params: &Params) -> Mixer {
+        let frame = Frame::from_param(params.mixer);
+        let motors = frame.motors();
+        let order = motor_order(params.motor_order, motors.len());
+
+        let mut pins = [UNUSED; MAX_MOTORS];
+        for (mix, pin) in motors.iter().zip(order) {
+            pins[pin] = *mix;
+            // Props out turns every prop around, and with it the yaw torque
+            if params.props_out != 0.0 {
+                pins[pin].yaw = -mix.yaw;
+            }
+        }
+
+        Mixer {
+            frame,
+            pins,
+            count: motors.len(),
+            max_power: params.max_power,
+            pid_limit: params.pid_limit,
+            servo_sign: if params.servo_reverse != 0.0 {
+                -1.0
+            } else {
+                1.0
+            },
+            airmode: params.airmode != 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// Scales throttle and corrections on the motors, not the servo, before they
+    /// are fitted into `0..max_power`.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    /// Bit per motor pin the frame drives.
+    pub fn used_pins(&self) -> u8 {
+        (0..MAX_MOTORS)
+            .filter(|&pin| self.pins[pin].throttle != 0.0)
+            .fold(0, |bits, pin| bits | 1 << pin)
+    }
+
+    /// `pid` is the roll, pitch and yaw correction. Motors are stopped and the
+    /// servo without pulses while disarmed.
+    pub fn mix(&self, throttle: f32, pid: [f32; 3], is_armed: bool) -> Outputs {
+        let yaw = pid[2];
+        let mut corrections = self.corrections(pid);
+        let (throttle, _) = self.desaturate(throttle * self.gain, &mut corrections[..self.count]);
+        let mixed: [f32; MAX_MOTORS] =
+            core::array::from_fn(|i| throttle * self.pins[i].throttle + corrections[i]);
+
+        tele!(
+            Category::Mix,
+            mixed[0],
+            mixed[1],
+            mixed[2],
+            mixed[3],
+            mixed[4],
+            mixed[5]
+        );
+
+        let mut outputs = Outputs::default();
+        if is_armed {
+            for (out, value) in outputs.motors.iter_mut().zip(mixed).take(self.count) {
+                *out = pid_to_throttle(value, self.max_power);
+            }
+            if self.frame.has_servo() {
+                let deflection = (self.servo_sign * yaw / self.pid_limit).clamp(-1.0, 1.0);
+                outputs.servo = libm::roundf(SERVO_MID_US + deflection * SERVO_THROW_US) as u16;
+            }
+        }
+
+        let m = outputs.motors;
+        tele!(
+            Category::Dshot,
+            m[0],
+            m[1],
+            m[2],
+            m[3],
+            m[4],
+            m[5],
+            outputs.servo
+        );
+
+        outputs
+    }
+
+    /// Share of the roll/pitch/yaw corrections `mix` passes on, 1 unless they had to
+    /// be scaled down to fit.
+    pub fn authority(&self, throttle: f32, pid: [f32; 3]) -> f32 {
+        let mut corrections = self.corrections(pid);
+        self.desaturate(throttle * self.gain, &mut corrections[..self.count])
+            .1
+    }
+
+    fn corrections(&self, pid: [f32; 3]) -> [f32; MAX_MOTORS] {
+        let [roll, pitch, yaw] = pid.map(|p| p * self.gain);
+        self.pins
+            .map(|m| roll * m.roll + pitch * m.pitch + yaw * m.yaw)
+    }
+
+    /// Fits the corrections into `0..max_power` by moving collective throttle rather
+    /// than clipping single motors, so the differential between them survives.
+    /// Returns the throttle to mix with and the scale the corrections got.
+    fn desaturate(&self, throttle: f32, corrections: &mut [f32]) -> (f32, f32) {
+        let low = corrections.iter().copied().fold(0.0, f32::min);
+        let high = corrections.iter().copied().fold(0.0, f32::max);
+
+        // Too much asked for to fit at any throttle, keep the proportions
+        let spread = high - low;
+        let mut scale = if spread > self.max_power {
+            self.max_power / spread
+        } else {
+            1.0
+        };
+        let (low, high) = (low * scale, high * scale);
+
+        // Punch-outs give up collective before attitude. RC throttle can dip under zero
+        let mut throttle = throttle.max(0.0).min(self.max_power - high);
+        if low < 0.0 && throttle + low < 0.0 {
+            if self.airmode {
+                // Full authority even at zero throttle, motors speed up as needed
+                throttle = -low;
+            } else {
+                // Never above the stick, the corrections shrink with throttle instead
+                throttle = throttle.max(0.0);
+                scale *= throttle / -low;
+            }
+        }
+
+        for correction in corrections.iter_mut() {
+            *correction *= scale;
+        }
+        (throttle, scale)
+    }
+}
+
+/// Pin of each preset motor from the decimal digits of `motor_order`, `2143` puts
+/// motor 1 on pin 2, motor 2 on pin 1 and so on. Anything but a reordering of
+/// all `count` motors keeps the preset order.
+fn motor_order(digits: f32, count: usize) -> [usize; MAX_MOTORS] {
+    let preset = core::array::from_fn(|i| i);
+    let mut value = digits as u32;
+    if value == 0 {
+        return preset;
+    }
+
+    let mut order = preset;
+    let mut seen = 0u32;
+    for pin in order[..count].iter_mut().rev() {
+        let digit = (value % 10) as usize;
+        value /= 10;
+        if digit == 0 || digit > count || seen & (1 << digit) != 0 {
+            log::warn!("[MIXER] motor_order {} ignored", digits as u32);
+            return preset;
+        }
+        seen |= 1 << digit;
+        *pin = digit - 1;
+    }
+    if value != 0 {
+        log::warn!("[MIXER] motor_order {} ignored", digits as u32);
+        return preset;
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::THROTTLE_MIN;
+
+    const HOVER: f32 = 0.3;
+    const MAX_POWER: f32 = Params::defaults().max_power;
+    const CORRECTION: f32 = 0.05;
+
+    fn mixer(frame: Frame) -> Mixer {
+        Mixer::new(&Params {
+            mixer: frame as u8 as f32,
+            ..Params::defaults()
+        })
+    }
+
+    fn quad(pid: [f32; 3]) -> [u16; 4] {
+        let m = mixer(Frame::QuadX).mix(HOVER, pid, true).motors;
+        [m[0], m[1], m[2], m[3]]
+    }
+
+    #[test]
+    fn disarmed_mixer_outputs_zero() {
+        for frame in [Frame::QuadX, Frame::HexX, Frame::Tri] {
+            let out = mixer(frame).mix(HOVER, [0.1; 3], false);
+            assert_eq!(out, Outputs::default());
+        }
+    }
+
+    #[test]
+    fn no_correction_gives_equal_motors() {
+        let out = mixer(Frame::QuadX).mix(HOVER, [0.0; 3], true);
+        let hover = pid_to_throttle(HOVER, MAX_POWER);
+        assert_eq!(out.motors, [hover, hover, hover, hover, 0, 0]);
+        assert_eq!(out.servo, 0);
+    }
+
+    #[test]
+    fn roll_raises_left_motors() {
+        let [fr, bl, fl, br] = quad([CORRECTION, 0.0, 0.0]);
+        assert!(bl > fr && bl > br);
+        assert!(fl > fr && fl > br);
+        assert_eq!(bl, fl);
+        assert_eq!(fr, br);
+    }
+
+    #[test]
+    fn pitch_raises_back_motors() {
+        let [fr, bl, fl, br] = quad([0.0, CORRECTION, 0.0]);
+        assert!(bl > fr && bl > fl);
+        assert!(br > fr && br > fl);
+        assert_eq!(bl, br);
+        assert_eq!(fr, fl);
+    }
+
+    #[test]
+    fn yaw_raises_one_diagonal() {
+        let [fr, bl, fl, br] = quad([0.0, 0.0, CORRECTION]);
+        assert!(fr > fl && fr > br);
+        assert_eq!(fr, bl);
+        assert_eq!(fl, br);
+    }
+
+    fn spread(motors: &[u16]) -> i32 {
+        let max = *motors.iter().max().unwrap() as i32;
+        let min = *motors.iter().min().unwrap() as i32;
+        max - min
+    }
+
+    #[test]
+    fn full_throttle_keeps_the_correction() {
+        let quad = mixer(Frame::QuadX);
+        let hover = quad.mix(HOVER, [CORRECTION, 0.0, 0.0], true).motors;
+        let punch = quad.mix(MAX_POWER, [CORRECTION, 0.0, 0.0], true).motors;
+        let max = pid_to_throttle(MAX_POWER, MAX_POWER);
+        assert_eq!(punch[1], max);
+        assert_eq!(punch[2], max);
+        assert!((spread(&punch[..4]) - spread(&hover[..4])).abs() <= 1);
+    }
+
+    #[test]
+    fn oversized_corrections_keep_their_proportions() {
+        let out = mixer(Frame::QuadX).mix(HOVER, [0.6, 0.2, 0.0], true).motors;
+        let [fr, bl, fl, br] = [out[0], out[1], out[2], out[3]].map(|m| m as i32);
+        let stopped = THROTTLE_MIN as i32;
+        let max = pid_to_throttle(MAX_POWER, MAX_POWER) as i32;
+        // Back left +0.8, front left +0.4, back right -0.4, front right -0.8
+        assert_eq!(bl, max);
+        assert_eq!(fr, stopped);
+        assert!((fl - stopped - 3 * (br - stopped)).abs() <= 2);
+    }
+
+    #[test]
+    fn zero_throttle_needs_airmode_for_authority() {
+        let idle = pid_to_throttle(0.0, MAX_POWER);
+        let out = mixer(Frame::QuadX).mix(0.0, [CORRECTION, 0.0, 0.0], true);
+        assert!(out.motors[..4].iter().all(|m| *m == idle));
+
+        let airmode = Mixer::new(&Params {
+            airmode: 1.0,
+            ..Params::defaults()
+        });
+        let out = airmode.mix(0.0, [CORRECTION, 0.0, 0.0], true).motors;
+        assert_eq!(out[0], idle);
+        assert_eq!(out[1], pid_to_throttle(2.0 * CORRECTION, MAX_POWER));
+        let hover = airmode.mix(HOVER, [CORRECTION, 0.0, 0.0], true).motors;
+        assert!((spread(&out[..4]) - spread(&hover[..4])).abs() <= 1);
+    }
+
+    #[test]
+    fn throttle_below_zero_idles() {
+        let idle = pid_to_throttle(0.0, MAX_POWER);
+        let quad = mixer(Frame::QuadX);
+        for pid in [[0.0; 3], [CORRECTION, 0.0, 0.0]] {
+            let out = quad.mix(-0.05, pid, true);
+            assert!(out.motors[..4].iter().all(|m| *m == idle), "{pid:?}");
+            assert_eq!(
+                quad.authority(-0.05, pid),
+                if pid[0] == 0.0 { 1.0 } else { 0.0 }
+            );
+        }
+    }
+
+    #[test]
+    fn low_throttle_scales_the_correction_down() {
+        let quad = mixer(Frame::QuadX);
+        let low = quad
+            .mix(CORRECTION / 2.0, [CORRECTION, 0.0, 0.0], true)
+            .motors;
+        let hover = quad.mix(HOVER, [CORRECTION, 0.0, 0.0], true).motors;
+        assert_eq!(low[0], THROTTLE_MIN as u16);
+        assert!((2 * spread(&low[..4]) - spread(&hover[..4])).abs() <= 2);
+    }
+
+    #[test]
+    fn authority_reports_scaled_corrections() {
+        let quad = mixer(Frame::QuadX);
+        assert_eq!(quad.authority(HOVER, [CORRECTION, 0.0, 0.0]), 1.0);
+        assert_eq!(quad.authority(HOVER, [0.6, 0.2, 0.0]), MAX_POWER / 1.6);
+        let low = quad.authority(CORRECTION / 2.0, [CORRECTION, 0.0, 0.0]);
+        assert!((low - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn presets_are_balanced() {
+        for frame in [
+            Frame::QuadX,
+            Frame::QuadPlus,
+            Frame::HexX,
+            Frame::Y6,
+            Frame::Tri,
+        ] {
+            let motors = frame.motors();
+            assert!(motors.len() <= MAX_MOTORS);
+            // Each axis nets out to zero, so corrections don't change total thrust
+            let axes: [fn(&MotorMix) -> f32; 3] = [|m| m.roll, |m| m.pitch, |m| m.yaw];
+            for axis in axes {
+                let sum: f32 = motors.iter().map(axis).sum();
+                assert!(sum.abs() < 1e-5, "{frame:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn unused_pins_stay_stopped() {
+        let hex = mixer(Frame::HexX).mix(HOVER, [0.0; 3], true);
+        assert!(hex.motors.iter().all(|m| *m > THROTTLE_MIN as u16));
+
+        let tri = mixer(Frame::Tri).mix(HOVER, [0.0; 3], true);
+        assert!(tri.motors[..3].iter().all(|m| *m > THROTTLE_MIN as u16));
+        assert_eq!(tri.motors[3..], [0, 0, 0]);
+
+        assert_eq!(mixer(Frame::HexX).used_pins(), 0b11_1111);
+        assert_eq!(mixer(Frame::Tri).used_pins(), 0b111);
+    }
+
+    #[test]
+    fn tri_yaws_with_the_servo() {
+        let limit = Params::defaults().pid_limit;
+        let tri = mixer(Frame::Tri);
+        assert_eq!(tri.mix(HOVER, [0.0; 3], true).servo, 1500);
+        let yawing = tri.mix(HOVER, [0.0, 0.0, limit / 2.0], true);
+        assert_eq!(yawing.servo, 1750);
+        // Motors don't see yaw at all
+        assert_eq!(yawing.motors, tri.mix(HOVER, [0.0; 3], true).motors);
+        assert_eq!(tri.mix(HOVER, [0.0, 0.0, limit * 4.0], true).servo, 2000);
+
+        let reversed = Mixer::new(&Params {
+            mixer: Frame::Tri as u8 as f32,
+            servo_reverse: 1.0,
+            ..Params::defaults()
+        });
+        assert_eq!(
+            reversed.mix(HOVER, [0.0, 0.0, limit / 2.0], true).servo,
+            1250
+        );
+    }
+
+    #[test]
+    fn gain_scales_motors_but_not_the_servo() {
+        let limit = Params::defaults().pid_limit;
+        let pid = [0.0, 0.0, limit / 2.0];
+        let mut tri = mixer(Frame::Tri);
+        let plain = tri.mix(HOVER, pid, true);
+        tri.set_gain(1.2);
+        let boosted = tri.mix(HOVER, pid, true);
+        assert_eq!(boosted.servo, plain.servo);
+        for (b, p) in boosted.motors.iter().zip(plain.motors).take(3) {
+            let expected = THROTTLE_MIN + (p as f32 - THROTTLE_MIN) * 1.2;
+            assert!((*b as f32 - expected).abs() <= 2.0);
+        }
+
+        // Still fits under max_power
+        let mut quad = mixer(Frame::QuadX);
+        let plain = quad.mix(MAX_POWER, [CORRECTION, 0.0, 0.0], true).motors;
+        quad.set_gain(1.5);
+        let boosted = quad.mix(MAX_POWER, [CORRECTION, 0.0, 0.0], true).motors;
+        assert_eq!(
+            boosted.iter().max(),
+            Some(&pid_to_throttle(MAX_POWER, MAX_POWER))
+        );
+        let expected = spread(&plain[..4]) as f32 * 1.5;
+        assert!((spread(&boosted[..4]) as f32 - expected).abs() <= 2.0);
+    }
+
+    #[test]
+    fn props_out_flips_yaw() {
+        let props_out = Mixer::new(&Params {
+            props_out: 1.0,
+            ..Params::defaults()
+        });
+        let [fr, bl, fl, br] = quad([0.0, 0.0, CORRECTION]);
+        let out = props_out.mix(HOVER, [0.0, 0.0, CORRECTION], true).motors;
+        assert_eq!(out[..4], [fl, br, fr, bl]);
+    }
+
+    #[test]
+    fn motor_order_moves_motors_to_pins() {
+        let reordered = Mixer::new(&Params {
+            motor_order: 2143.0,
+            ..Params::defaults()
+        });
+        let [fr, bl, fl, br] = quad([CORRECTION, CORRECTION, 0.0]);
+        let out = reordered
+            .mix(HOVER, [CORRECTION, CORRECTION, 0.0], true)
+            .motors;
+        assert_eq!(out[..4], [bl, fr, br, fl]);
+    }
+
+    #[test]
+    fn invalid_motor_order_keeps_preset() {
+        assert_eq!(motor_order(0.0, 4), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(motor_order(4321.0, 4), [3, 2, 1, 0, 4, 5]);
+        assert_eq!(motor_order(654321.0, 6), [5, 4, 3, 2, 1, 0]);
+        for bad in [1123.0, 1235.0, 123.0, 12345.0, 1203.0] {
+            assert_eq!(motor_order(bad, 4), [0, 1, 2, 3, 4, 5], "{bad}");
+        }
+    }
+}
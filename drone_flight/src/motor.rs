@@ -0,0 +1,495 @@
+use crate::alt_hold::{ALT_HOLD_OFF_SIGNAL, ALT_HOLD_ON_SIGNAL};
+use crate::consts::{
+    ALT_HOLD_THROTTLE_MAX, ALT_HOLD_THROTTLE_MIN, ALT_KD_MIN, ALT_KP_MIN, BATTERY_COMP_LPF_HZ,
+    SLOPE, THROTTLE_MIN,
+};
+use crate::{
+    battery::BatteryState,
+    blackbox::modes,
+    filter::{FilterSpec, Pt1},
+    imu::ImuData,
+    mixer::{Mixer, Outputs},
+    params::Params,
+    pid::{self, Feedforward, ItermRelax, Pid, Windup},
+    rates::Rates,
+    rc::RcData,
+    schedule::{GainSchedule, Tpa},
+};
+use drone_consts::telemetry::Category;
+
+/// How the sticks turn into rate setpoints.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlightMode {
+    /// Sticks set lean angles.
+    Angle,
+    /// Self-levels around center stick, fades into rates towards full deflection.
+    Horizon,
+    /// Sticks set body rates.
+    Acro,
+}
+
+impl FlightMode {
+    fn from_modes(mode_bits: u8) -> FlightMode {
+        if mode_bits & modes::ACRO != 0 {
+            FlightMode::Acro
+        } else if mode_bits & modes::HORIZON != 0 {
+            FlightMode::Horizon
+        } else {
+            FlightMode::Angle
+        }
+    }
+}
+
+pub fn pid_to_throttle(rc: f32, max_power: f32) -> u16 {
+    let clamped_rc = rc.clamp(0.0, max_power);
+    (THROTTLE_MIN + SLOPE * clamped_rc) as u16
+}
+
+/// DShot throttle as a 1000-2000 us pulse width for PWM based tools, stopped reads 1000.
+pub fn dshot_to_us(dshot: u16) -> u16 {
+    let above_min = (dshot as f32 - THROTTLE_MIN).max(0.0);
+    1000 + libm::roundf(above_min * 1000.0 / SLOPE) as u16
+}
+
+/// Inverse of `dshot_to_us`, 1000 us and below stops the motor.
+pub fn us_to_dshot(us: u16) -> u16 {
+    if us <= 1000 {
+        return 0;
+    }
+    let above_min = (us.min(2000) - 1000) as f32;
+    libm::roundf(THROTTLE_MIN + above_min * SLOPE / 1000.0) as u16
+}
+
+/// Scales the motors by nominal over measured cell voltage, so the same command gives
+/// the same thrust as the pack drains and a captured hover throttle keeps holding.
+struct VoltageComp {
+    enabled: bool,
+    nominal_cell: f32,
+    max_gain: f32,
+    cell_lp: Pt1,
+}
+
+impl VoltageComp {
+    fn new(params: &Params, sample_hz: f32) -> VoltageComp {
+        let mut cell_lp = Pt1::new(BATTERY_COMP_LPF_HZ, sample_hz);
+        cell_lp.reset(params.bat_comp_cell);
+        VoltageComp {
+            enabled: params.bat_comp != 0.0,
+            nominal_cell: params.bat_comp_cell,
+            max_gain: params.bat_comp_max,
+            cell_lp,
+        }
+    }
+
+    fn update(&mut self, battery: Option<&BatteryState>) -> f32 {
+        // The loaded voltage, sag is what costs thrust
+        let cell = battery
+            .filter(|b| self.enabled && b.cells != 0)
+            .map(|b| b.voltage / b.cells as f32);
+        let Some(cell) = cell else {
+            // Starts from nominal once a reading turns up, no step in the outputs
+            self.cell_lp.reset(self.nominal_cell);
+            return 1.0;
+        };
+        let cell = self.cell_lp.apply(cell);
+        (self.nominal_cell / cell).clamp(1.0 / self.max_gain, self.max_gain)
+    }
+}
+
+pub struct MotorInput {
+    pid_roll: Pid,
+    pid_pitch: Pid,
+    pid_yaw: Pid,
+    pid_alt: Pid,
+    mixer: Mixer,
+    target_alt: f32,
+    hover_throttle: f32,
+    max_power: f32,
+    max_lean_angle: f32,
+    angle_p_gain: f32,
+    yaw_rate: f32,
+    rp_rates: Rates,
+    yaw_rates: Rates,
+    horizon_transition: f32,
+    horizon_strength: f32,
+    mode: FlightMode,
+    i_term_throttle_limit: f32,
+    tpa: Tpa,
+    voltage_comp: VoltageComp,
+    pid_out: [f32; 4],
+}
+
+impl MotorInput {
+    pub fn new(cycle_time: f32, params: &Params) -> MotorInput {
+        let pid_limits = Some(pid::Limits {
+            min: -params.pid_limit,
+            max: params.pid_limit,
+        });
+
+        let rate_ff = Feedforward {
+            kff: params.rate_kff,
+            setpoint_weight: params.rate_sp_weight,
+            lpf_hz: params.ff_lpf_hz,
+            jitter: params.ff_jitter_dps.to_radians(),
+        };
+        let yaw_ff = Feedforward {
+            kff: params.yaw_kff,
+            setpoint_weight: params.yaw_sp_weight,
+            ..rate_ff
+        };
+
+        let rate_windup = Windup::from_param(params.rate_windup, params.windup_gain);
+        let yaw_windup = Windup::from_param(params.yaw_windup, params.windup_gain);
+        let rate_lpf = FilterSpec::low_pass(params.rate_lpf_type, params.rate_lpf_hz);
+        let dterm_lpf = FilterSpec::low_pass(params.dterm_lpf_type, params.dterm_lpf_hz);
+        let relax = ItermRelax {
+            cutoff_hz: params.relax_hz,
+            threshold: params.relax_dps.to_radians(),
+        };
+
+        MotorInput {
+            pid_roll: Pid::new(
+                params.rate_kp,
+                params.rate_ki,
+                params.rate_kd,
+                cycle_time,
+                pid_limits,
+                Some(rate_lpf),
+                Some(dterm_lpf),
+            )
+            .with_feedforward(rate_ff)
+            .with_windup(rate_windup, params.iterm_limit)
+            .with_iterm_relax((params.rate_iterm_relax != 0.0).then_some(relax)),
+            pid_pitch: Pid::new(
+                params.rate_kp,
+                params.rate_ki,
+                params.rate_kd,
+                cycle_time,
+                pid_limits,
+                Some(rate_lpf),
+                Some(dterm_lpf),
+            )
+            .with_feedforward(rate_ff)
+            .with_windup(rate_windup, params.iterm_limit)
+            .with_iterm_relax((params.rate_iterm_relax != 0.0).then_some(relax)),
+            pid_yaw: Pid::new(
+                params.yaw_kp,
+                params.yaw_ki,
+                params.yaw_kd,
+                cycle_time,
+                pid_limits,
+                Some(rate_lpf),
+                None,
+            )
+            .with_feedforward(yaw_ff)
+            .with_windup(yaw_windup, params.iterm_limit)
+            .with_iterm_relax((params.yaw_iterm_relax != 0.0).then_some(relax)),
+            pid_alt: Pid::new(
+                ALT_KP_MIN,
+                params.alt_ki,
+                ALT_KD_MIN,
+                cycle_time,
+                pid_limits,
+                None,
+                None,
+            ),
+            mixer: Mixer::new(params),
+            target_alt: 0.0,
+            hover_throttle: 0.0,
+            max_power: params.max_power,
+            max_lean_angle: params.max_lean_deg.to_radians(),
+            angle_p_gain: params.angle_p,
+            yaw_rate: params.yaw_rate_dps.to_radians(),
+            rp_rates: Rates::roll_pitch(params),
+            yaw_rates: Rates::yaw(params),
+            horizon_transition: params.horizon_transition,
+            horizon_strength: params.horizon_strength,
+            mode: FlightMode::Angle,
+            i_term_throttle_limit: params.iterm_throttle,
+            tpa: Tpa::new(params),
+            voltage_comp: VoltageComp::new(params, 1.0 / cycle_time),
+            pid_out: [0.0; 4],
+        }
+    }
+
+    /// Angle mode: sticks set lean angles, the angle error sets the rates.
+    fn angle_rates(&self, rc_data: &RcData, att: &[f32; 3]) -> [f32; 3] {
+        let target_angle_roll = -rc_data.roll() * self.max_lean_angle;
+        let angle_error_roll = target_angle_roll - att[0];
+
+        let target_angle_pitch = rc_data.pitch() * self.max_lean_angle;
+        let angle_error_pitch = target_angle_pitch - att[1];
+
+        [
+            angle_error_roll * self.angle_p_gain,
+            angle_error_pitch * self.angle_p_gain,
+            rc_data.yaw() * self.yaw_rate,
+        ]
+    }
+
+    fn acro_rates(&self, rc_data: &RcData) -> [f32; 3] {
+        [
+            -self.rp_rates.rate(rc_data.roll()),
+            self.rp_rates.rate(rc_data.pitch()),
+            self.yaw_rates.rate(rc_data.yaw()),
+        ]
+    }
+
+    /// Share of angle mode in horizon mode, `horizon_strength` at center stick down to
+    /// none at `horizon_transition` deflection.
+    fn horizon_level(&self, rc_data: &RcData) -> f32 {
+        let deflection = rc_data.roll().abs().max(rc_data.pitch().abs());
+        self.horizon_strength * (1.0 - deflection / self.horizon_transition).clamp(0.0, 1.0)
+    }
+
+    fn rates(&self, mode: FlightMode, rc_data: &RcData, att: &[f32; 3]) -> [f32; 3] {
+        match mode {
+            FlightMode::Angle => self.angle_rates(rc_data, att),
+            FlightMode::Acro => self.acro_rates(rc_data),
+            FlightMode::Horizon => {
+                let angle = self.angle_rates(rc_data, att);
+                let acro = self.acro_rates(rc_data);
+                let level = self.horizon_level(rc_data);
+                let blend = |a: f32, r: f32| a * level + r * (1.0 - level);
+                [blend(angle[0], acro[0]), blend(angle[1], acro[1]), acro[2]]
+            }
+        }
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// Feeds the pack voltage to the `bat_comp` scaling, every tick.
+    pub fn set_battery(&mut self, battery: Option<&BatteryState>) {
+        let gain = self.voltage_comp.update(battery);
+        self.mixer.set_gain(gain);
+    }
+
+    /// Roll, pitch, yaw and altitude PID outputs of the last update.
+    pub fn pid_out(&self) -> [f32; 4] {
+        self.pid_out
+    }
+
+    pub fn update(
+        &mut self,
+        rc_data: &RcData,
+        imu: &ImuData,
+        att: &[f32; 3],
+        alt: f32,
+        mode_bits: u8,
+    ) -> Outputs {
+        let is_armed = mode_bits & modes::ARMED != 0;
+        let alt_hold = mode_bits & modes::ALT_HOLD != 0;
+        let mode = FlightMode::from_modes(mode_bits);
+
+        self.pid_alt.kp = rc_data.kp_gain();
+        self.pid_alt.kd = rc_data.kd_gain();
+
+        let allow_i_term = rc_data.throttle() > self.i_term_throttle_limit;
+
+        if !allow_i_term || !is_armed {
+            // With anti-windup low throttle counts as no authority instead, see below
+            for pid in [&mut self.pid_roll, &mut self.pid_pitch, &mut self.pid_yaw] {
+                if !is_armed || pid.windup() == Windup::Clamp {
+                    pid.i = 0.0;
+                }
+            }
+
+            if !alt_hold {
+                self.pid_alt.i = 0.0;
+            }
+        }
+
+        if ALT_HOLD_ON_SIGNAL.try_take().is_some() {
+            self.target_alt = alt;
+            self.hover_throttle = rc_data
+                .throttle()
+                .clamp(ALT_HOLD_THROTTLE_MIN, ALT_HOLD_THROTTLE_MAX);
+            self.pid_alt.i = 0.0;
+            log::info!(
+                "AltHold locked: {:.2}m | Hover throttle: {:.2}",
+                self.target_alt,
+                self.hover_throttle
+            );
+        }
+
+        if ALT_HOLD_OFF_SIGNAL.try_take().is_some() {
+            self.pid_alt.i = 0.0;
+        }
+
+        let mut pid_alt = 0.0;
+        let throttle = if alt_hold {
+            let alt_error = self.target_alt - alt;
+            pid_alt = self.pid_alt.update(alt_error, alt);
+            (self.hover_throttle + pid_alt).clamp(0.0, self.max_power)
+        } else {
+            rc_data.throttle()
+        };
+
+        let rates = self.rates(mode, rc_data, att);
+        if mode != self.mode {
+            let from = self.rates(self.mode, rc_data, att);
+            self.pid_roll.transfer(from[0], rates[0]);
+            self.pid_pitch.transfer(from[1], rates[1]);
+            self.pid_yaw.transfer(from[2], rates[2]);
+            self.mode = mode;
+        }
+
+        // Less gain on punch-outs, where more airflow makes the same gains oscillate
+        let tpa = self.tpa.scale(throttle / self.max_power);
+        self.pid_roll.schedule(tpa);
+        self.pid_pitch.schedule(tpa);
+
+        let [target_rate_roll, target_rate_pitch, target_rate_yaw] = rates;
+        let pid_roll = self.pid_roll.update(target_rate_roll, imu.gyro[0]);
+        let pid_pitch = self.pid_pitch.update(target_rate_pitch, imu.gyro[1]);
+        let pid_yaw = self.pid_yaw.update(target_rate_yaw, -imu.gyro[2]);
+
+        self.pid_out = [pid_roll, pid_pitch, pid_yaw, pid_alt];
+
+        tele!(
+            Category::Pid,
+            pid_roll,
+            pid_pitch,
+            pid_yaw,
+            pid_alt,
+            self.pid_roll.i,
+            self.pid_pitch.i,
+            self.pid_yaw.i,
+            self.pid_alt.i,
+        );
+
+        let pid = [pid_roll, pid_pitch, pid_yaw];
+        let authority = if allow_i_term {
+            self.mixer.authority(throttle, pid)
+        } else {
+            0.0
+        };
+        for pid in [&mut self.pid_roll, &mut self.pid_pitch, &mut self.pid_yaw] {
+            pid.set_authority(authority);
+        }
+
+        self.mixer.mix(throttle, pid, is_armed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::THROTTLE_MAX;
+
+    const MAX_POWER: f32 = Params::defaults().max_power;
+
+    #[test]
+    fn throttle_maps_onto_dshot_range() {
+        assert_eq!(pid_to_throttle(0.0, MAX_POWER), THROTTLE_MIN as u16);
+        assert_eq!(pid_to_throttle(-1.0, MAX_POWER), THROTTLE_MIN as u16);
+        assert_eq!(
+            pid_to_throttle(MAX_POWER, MAX_POWER),
+            (THROTTLE_MIN + SLOPE * MAX_POWER) as u16
+        );
+        assert_eq!(
+            pid_to_throttle(1.0, MAX_POWER),
+            pid_to_throttle(MAX_POWER, MAX_POWER)
+        );
+    }
+
+    #[test]
+    fn pulse_widths_cover_dshot_range() {
+        assert_eq!(dshot_to_us(0), 1000);
+        assert_eq!(dshot_to_us(THROTTLE_MIN as u16), 1000);
+        assert_eq!(dshot_to_us(THROTTLE_MAX as u16), 2000);
+        assert_eq!(us_to_dshot(1000), 0);
+        assert_eq!(us_to_dshot(2500), THROTTLE_MAX as u16);
+        for us in [1001, 1250, 1500, 1999] {
+            assert_eq!(dshot_to_us(us_to_dshot(us)), us);
+        }
+    }
+
+    fn sticks(roll: f32, pitch: f32) -> RcData {
+        use crate::consts::{RC_MAX, RC_MIN};
+        let channel = |v: f32| (RC_MIN as f32 + (v + 1.0) / 2.0 * (RC_MAX - RC_MIN) as f32) as u16;
+        let mut channels = [RC_MIN; 16];
+        channels[0] = channel(roll);
+        channels[1] = channel(pitch);
+        channels[3] = channel(0.0);
+        RcData::from_channels(channels)
+    }
+
+    #[test]
+    fn acro_wins_over_horizon() {
+        assert_eq!(FlightMode::from_modes(0), FlightMode::Angle);
+        assert_eq!(FlightMode::from_modes(modes::HORIZON), FlightMode::Horizon);
+        assert_eq!(
+            FlightMode::from_modes(modes::HORIZON | modes::ACRO),
+            FlightMode::Acro
+        );
+    }
+
+    #[test]
+    fn horizon_levels_at_center_and_rolls_at_full_stick() {
+        let input = MotorInput::new(0.001, &Params::defaults());
+        let tilted = [0.3, -0.2, 0.0];
+
+        let center = sticks(0.0, 0.0);
+        let horizon = input.rates(FlightMode::Horizon, &center, &tilted);
+        let angle = input.rates(FlightMode::Angle, &center, &tilted);
+        assert!((horizon[0] - angle[0]).abs() < 1e-2);
+        assert!((horizon[1] - angle[1]).abs() < 1e-2);
+
+        let full = sticks(1.0, 0.0);
+        let horizon = input.rates(FlightMode::Horizon, &full, &tilted);
+        let acro = input.rates(FlightMode::Acro, &full, &tilted);
+        assert_eq!(horizon, acro);
+
+        let half = sticks(0.3, 0.0);
+        let level = input.horizon_level(&half);
+        assert!(level > 0.0 && level < 1.0);
+    }
+
+    fn pack(cell: f32) -> BatteryState {
+        BatteryState {
+            voltage: cell * 4.0,
+            cells: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn voltage_comp_is_off_by_default() {
+        let mut comp = VoltageComp::new(&Params::defaults(), 1000.0);
+        for _ in 0..5000 {
+            assert_eq!(comp.update(Some(&pack(3.4))), 1.0);
+        }
+    }
+
+    #[test]
+    fn voltage_comp_follows_sag_slowly_within_limits() {
+        let params = Params {
+            bat_comp: 1.0,
+            ..Params::defaults()
+        };
+        let mut comp = VoltageComp::new(&params, 1000.0);
+        assert_eq!(comp.update(None), 1.0);
+        // Uncounted cells are no reading either
+        assert_eq!(comp.update(Some(&BatteryState::default())), 1.0);
+
+        let first = comp.update(Some(&pack(3.5)));
+        assert!(first > 1.0 && first < 1.001);
+        let mut gain = first;
+        for _ in 0..10_000 {
+            gain = comp.update(Some(&pack(3.5)));
+        }
+        assert!((gain - params.bat_comp_cell / 3.5).abs() < 1e-3);
+
+        for _ in 0..10_000 {
+            gain = comp.update(Some(&pack(2.5)));
+        }
+        assert_eq!(gain, params.bat_comp_max);
+        for _ in 0..10_000 {
+            gain = comp.update(Some(&pack(6.0)));
+        }
+        assert_eq!(gain, 1.0 / params.bat_comp_max);
+    }
+}
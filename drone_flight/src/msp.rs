@@ -0,0 +1,436 @@
+use crate::blackbox::Snapshot;
+use crate::consts::CYCLE_TIME;
+use crate::imu::ImuData;
+use crate::mixer::MAX_MOTORS;
+use crate::motor::{dshot_to_us, us_to_dshot};
+use crate::rc::RcData;
+
+// MSP v1, as spoken by Betaflight style configurators.
+// Request:  '$', 'M', '<', size, cmd, payload[size], checksum
+// Response: '$', 'M', '>' ('!' on error), size, cmd, payload[size], checksum
+// Checksum is the XOR of size, cmd and payload. Bad checksums are dropped silently.
+pub const PREAMBLE: u8 = b'$';
+const VERSION: u8 = b'M';
+const TO_FC: u8 = b'<';
+const FROM_FC: u8 = b'>';
+const ERROR: u8 = b'!';
+const HEADER_SIZE: usize = 5;
+pub const MAX_PAYLOAD: usize = 32;
+pub const MAX_FRAME: usize = HEADER_SIZE + MAX_PAYLOAD + 1;
+
+const GRAVITY: f32 = 9.80665;
+// Betaflight scales raw accelerometer readings to 512 per g
+const ACC_1G: f32 = 512.0;
+
+const SENSOR_ACC: u16 = 1 << 0;
+const SENSOR_BARO: u16 = 1 << 1;
+const SENSOR_MAG: u16 = 1 << 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Command {
+    Status = 101,
+    RawImu = 102,
+    Motor = 104,
+    Rc = 105,
+    Attitude = 108,
+    Altitude = 109,
+    SetMotor = 214,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Command, ()> {
+        match value {
+            101 => Ok(Command::Status),
+            102 => Ok(Command::RawImu),
+            104 => Ok(Command::Motor),
+            105 => Ok(Command::Rc),
+            108 => Ok(Command::Attitude),
+            109 => Ok(Command::Altitude),
+            214 => Ok(Command::SetMotor),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Request {
+    pub cmd: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Request {
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+}
+
+pub struct Response {
+    pub cmd: u8,
+    pub ok: bool,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Response {
+    pub fn new(cmd: u8, ok: bool) -> Response {
+        Response {
+            cmd,
+            ok,
+            len: 0,
+            payload: [0; MAX_PAYLOAD],
+        }
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> &mut Response {
+        let n = bytes.len().min(MAX_PAYLOAD - self.len);
+        self.payload[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+        self
+    }
+
+    pub fn encode(&self, out: &mut [u8; MAX_FRAME]) -> usize {
+        out[0] = PREAMBLE;
+        out[1] = VERSION;
+        out[2] = if self.ok { FROM_FC } else { ERROR };
+        out[3] = self.len as u8;
+        out[4] = self.cmd;
+        out[HEADER_SIZE..HEADER_SIZE + self.len].copy_from_slice(self.payload());
+        let end = HEADER_SIZE + self.len;
+        out[end] = checksum(&out[3..end]);
+        end + 1
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+/// Byte-wise request decoder, resynchronizes on `PREAMBLE` after any error.
+pub struct Parser {
+    buf: [u8; MAX_FRAME],
+    pos: usize,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            buf: [0; MAX_FRAME],
+            pos: 0,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// Returns a request, or an error response for oversized ones, once a frame is complete.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Request, Response>> {
+        let expected = match self.pos {
+            0 => Some(PREAMBLE),
+            1 => Some(VERSION),
+            2 => Some(TO_FC),
+            _ => None,
+        };
+        if expected.is_some_and(|expected| expected != byte) {
+            self.pos = 0;
+            return None;
+        }
+        self.buf[self.pos] = byte;
+        self.pos += 1;
+
+        if self.pos < HEADER_SIZE {
+            return None;
+        }
+        let (len, cmd) = (self.buf[3] as usize, self.buf[4]);
+        if len > MAX_PAYLOAD {
+            self.pos = 0;
+            return Some(Err(Response::new(cmd, false)));
+        }
+        let end = HEADER_SIZE + len;
+        if self.pos <= end {
+            return None;
+        }
+        self.pos = 0;
+
+        if checksum(&self.buf[3..end]) != self.buf[end] {
+            return None;
+        }
+        let mut payload = [0; MAX_PAYLOAD];
+        payload[..len].copy_from_slice(&self.buf[HEADER_SIZE..end]);
+        Some(Ok(Request { cmd, len, payload }))
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+/// Latest sensor and flight loop data the replies are built from, missing data reads as zero.
+pub struct State<'a> {
+    pub imu: Option<&'a ImuData>,
+    pub rc: Option<&'a RcData>,
+    pub alt: Option<f32>,
+    pub snapshot: &'a Snapshot,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    None,
+    /// Bench motor test, DShot values to send while disarmed.
+    SetMotors([u16; MAX_MOTORS]),
+}
+
+fn i16_of(value: f32) -> [u8; 2] {
+    (libm::roundf(value) as i16).to_le_bytes()
+}
+
+/// Motor writes are refused while `armed`.
+pub fn handle(request: &Request, state: &State, armed: bool) -> (Response, Action) {
+    let mut response = Response::new(request.cmd, true);
+    let Ok(command) = Command::try_from(request.cmd) else {
+        return (Response::new(request.cmd, false), Action::None);
+    };
+
+    match command {
+        Command::Status => {
+            let cycle_us = libm::roundf(CYCLE_TIME * 1e6) as u16;
+            response
+                .push(&cycle_us.to_le_bytes())
+                .push(&0u16.to_le_bytes()) // i2c errors
+                .push(&(SENSOR_ACC | SENSOR_BARO | SENSOR_MAG).to_le_bytes())
+                .push(&(state.snapshot.modes as u32).to_le_bytes())
+                .push(&[0]); // profile
+        }
+        Command::RawImu => {
+            if let Some(imu) = state.imu {
+                for acc in imu.acc.iter() {
+                    response.push(&i16_of(acc / GRAVITY * ACC_1G));
+                }
+                for gyro in imu.gyro.iter() {
+                    response.push(&i16_of(gyro.to_degrees()));
+                }
+                for mag in imu.mag.iter() {
+                    response.push(&i16_of(*mag));
+                }
+            } else {
+                response.push(&[0; 18]);
+            }
+        }
+        Command::Motor => {
+            for i in 0..8 {
+                let us = state.snapshot.motors.get(i).map_or(0, |m| dshot_to_us(*m));
+                response.push(&us.to_le_bytes());
+            }
+        }
+        Command::Rc => {
+            if let Some(rc) = state.rc {
+                for us in rc.channels_us() {
+                    response.push(&us.to_le_bytes());
+                }
+            }
+        }
+        Command::Attitude => {
+            let [roll, pitch, yaw] = state.snapshot.att.map(f32::to_degrees);
+            let heading = if yaw < 0.0 { yaw + 360.0 } else { yaw };
+            response
+                .push(&i16_of(roll * 10.0))
+                .push(&i16_of(pitch * 10.0))
+                .push(&i16_of(heading));
+        }
+        Command::Altitude => {
+            let alt_cm = libm::roundf(state.alt.unwrap_or(state.snapshot.alt) * 100.0) as i32;
+            response
+                .push(&alt_cm.to_le_bytes())
+                .push(&0i16.to_le_bytes()); // vario
+        }
+        Command::SetMotor => {
+            let payload = request.payload();
+            if armed || payload.len() < MAX_MOTORS * 2 {
+                return (Response::new(request.cmd, false), Action::None);
+            }
+            let motors = core::array::from_fn(|i| {
+                us_to_dshot(u16::from_le_bytes([payload[i * 2], payload[i * 2 + 1]]))
+            });
+            return (response, Action::SetMotors(motors));
+        }
+    }
+    (response, Action::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blackbox::modes;
+    use crate::consts::THROTTLE_MAX;
+    use nalgebra::Vector3;
+
+    // MSP_RC, checksum 0 ^ 105
+    const RC_REQUEST: [u8; 6] = [b'$', b'M', b'<', 0, 105, 105];
+    // Command 1 with two payload bytes, checksum 2 ^ 1 ^ 0x10 ^ 0x20
+    const PAYLOAD_REQUEST: [u8; 8] = [b'$', b'M', b'<', 2, 1, 0x10, 0x20, 0x33];
+
+    fn request(cmd: u8, bytes: &[u8]) -> Request {
+        let mut payload = [0; MAX_PAYLOAD];
+        payload[..bytes.len()].copy_from_slice(bytes);
+        Request {
+            cmd,
+            len: bytes.len(),
+            payload,
+        }
+    }
+
+    fn call(cmd: Command, payload: &[u8], state: &State, armed: bool) -> (Response, Action) {
+        handle(&request(cmd as u8, payload), state, armed)
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            att: [0.1, -0.2, -core::f32::consts::FRAC_PI_2],
+            alt: 1.25,
+            motors: [0, 48, 1047, 2047, 0, 0],
+            modes: modes::ARMED,
+            ..Default::default()
+        }
+    }
+
+    fn empty(snapshot: &Snapshot) -> State<'_> {
+        State {
+            imu: None,
+            rc: None,
+            alt: None,
+            snapshot,
+        }
+    }
+
+    fn i16_at(p: &[u8], at: usize) -> i16 {
+        i16::from_le_bytes([p[at], p[at + 1]])
+    }
+
+    #[test]
+    fn parses_after_garbage_and_drops_bad_checksum() {
+        let mut parser = Parser::new();
+        let mut bytes = vec![b'$', b'M', b'x', 0x42];
+        bytes.extend(RC_REQUEST);
+        let request = bytes.iter().find_map(|&b| parser.push(b));
+        assert_eq!(request.unwrap().ok().unwrap().cmd, Command::Rc as u8);
+
+        // Dropped without an answer, the configurator just polls again
+        let mut bad = PAYLOAD_REQUEST;
+        bad[7] ^= 0xff;
+        assert!(bad.iter().all(|&b| parser.push(b).is_none()));
+        assert!(parser.is_idle());
+
+        let request = PAYLOAD_REQUEST.iter().find_map(|&b| parser.push(b));
+        assert_eq!(request.unwrap().ok().unwrap().payload(), &[0x10, 0x20]);
+    }
+
+    #[test]
+    fn echoed_responses_are_not_requests() {
+        // Only '<' frames are for us, a looped back '>' or '!' reply is skipped
+        let snapshot = snapshot();
+        let (response, _) = call(Command::Altitude, &[], &empty(&snapshot), false);
+        let mut out = [0; MAX_FRAME];
+        let n = response.encode(&mut out);
+        let mut parser = Parser::new();
+        assert!(out[..n].iter().all(|&b| parser.push(b).is_none()));
+        assert!(parser.is_idle());
+        assert!(RC_REQUEST.iter().find_map(|&b| parser.push(b)).is_some());
+    }
+
+    #[test]
+    fn response_frame_layout() {
+        let snapshot = snapshot();
+        let (response, _) = call(Command::Altitude, &[], &empty(&snapshot), false);
+        let mut out = [0; MAX_FRAME];
+        let n = response.encode(&mut out);
+        assert_eq!(&out[..5], &[b'$', b'M', b'>', 6, Command::Altitude as u8]);
+        assert_eq!(i32::from_le_bytes([out[5], out[6], out[7], out[8]]), 125);
+        assert_eq!(checksum(&out[3..n - 1]), out[n - 1]);
+    }
+
+    #[test]
+    fn attitude_and_motors_use_msp_units() {
+        let snapshot = snapshot();
+        let state = empty(&snapshot);
+        let (response, _) = call(Command::Attitude, &[], &state, false);
+        let p = response.payload();
+        assert_eq!(i16_at(p, 0), 57);
+        assert_eq!(i16_at(p, 2), -115);
+        assert_eq!(i16_at(p, 4), 270);
+
+        let (response, _) = call(Command::Motor, &[], &state, false);
+        let p = response.payload();
+        let motors: Vec<_> = (0..8).map(|i| i16_at(p, i * 2) as u16).collect();
+        assert_eq!(motors, vec![1000, 1000, 1500, 2000, 1000, 1000, 0, 0]);
+
+        let (response, _) = call(Command::Status, &[], &state, false);
+        assert_eq!(response.payload()[6] & 1, 1);
+    }
+
+    #[test]
+    fn raw_imu_scales_to_betaflight_units() {
+        let snapshot = snapshot();
+        let imu = ImuData {
+            gyro: Vector3::new(1.0, 0.0, 0.0),
+            acc: Vector3::new(0.0, 0.0, GRAVITY),
+            mag: Vector3::new(20.0, 0.0, -40.0),
+            dt: 0.001,
+        };
+        let state = State {
+            imu: Some(&imu),
+            ..empty(&snapshot)
+        };
+        let (response, _) = call(Command::RawImu, &[], &state, false);
+        let p = response.payload();
+        assert_eq!(i16_at(p, 4), 512);
+        assert_eq!(i16_at(p, 6), 57);
+        assert_eq!(i16_at(p, 16), -40);
+    }
+
+    #[test]
+    fn motor_test_only_while_disarmed() {
+        let snapshot = snapshot();
+        let state = empty(&snapshot);
+        let mut payload = Vec::new();
+        for us in [1000u16, 1500, 2000, 1100, 1000, 1000, 1000, 1000] {
+            payload.extend_from_slice(&us.to_le_bytes());
+        }
+
+        let (response, action) = call(Command::SetMotor, &payload, &state, false);
+        assert!(response.ok);
+        let Action::SetMotors(motors) = action else {
+            panic!("no motor test");
+        };
+        assert_eq!(motors[0], 0);
+        assert_eq!(motors[2], THROTTLE_MAX as u16);
+
+        let (response, action) = call(Command::SetMotor, &payload, &state, true);
+        assert!(!response.ok);
+        assert_eq!(action, Action::None);
+    }
+
+    #[test]
+    fn unknown_and_oversized_are_errors() {
+        let snapshot = snapshot();
+        let (response, _) = call(Command::Status, &[], &empty(&snapshot), false);
+        assert!(response.ok);
+        assert!(!handle(&request(1, &[]), &empty(&snapshot), false).0.ok);
+
+        let mut parser = Parser::new();
+        let header = [b'$', b'M', b'<', MAX_PAYLOAD as u8 + 1, 105];
+        let response = header.iter().find_map(|&b| parser.push(b));
+        let mut out = [0; MAX_FRAME];
+        let n = response.unwrap().err().unwrap().encode(&mut out);
+        assert_eq!(&out[..n], &[b'$', b'M', b'!', 0, 105, 105]);
+        assert!(parser.is_idle());
+    }
+}
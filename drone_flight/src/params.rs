@@ -0,0 +1,363 @@
+use crc::{CRC_32_ISO_HDLC, Crc};
+use nalgebra::Vector3;
+
+const PARAMS_MAGIC: u32 = 0x5052_4d53; // "PRMS"
+pub const PARAMS_VERSION: u16 = 19;
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 4;
+pub const PARAMS_BLOB_SIZE: usize = HEADER_SIZE + PARAM_COUNT * 4 + CRC_SIZE;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ParamType {
+    Float,
+    Int,
+    Bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub kind: ParamType,
+    pub default: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParamError {
+    UnknownParam,
+    OutOfRange,
+    Storage,
+    Empty,
+    Stale,
+    Corrupt,
+}
+
+/// Backend holding one serialized parameter blob, e.g. a reserved flash sector.
+pub trait ParamStorage {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), ParamError>;
+    /// Replaces whatever was stored before.
+    fn write(&mut self, data: &[u8]) -> Result<(), ParamError>;
+}
+
+macro_rules! param_table {
+    ($($field:ident: $kind:ident = $default:expr, [$min:expr, $max:expr];)+) => {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Params {
+            $(pub $field: f32,)+
+        }
+
+        pub const PARAM_INFO: &[ParamInfo] = &[
+            $(ParamInfo {
+                name: stringify!($field),
+                kind: ParamType::$kind,
+                default: $default,
+                min: $min,
+                max: $max,
+            },)+
+        ];
+
+        impl Params {
+            pub const fn defaults() -> Params {
+                Params {
+                    $($field: $default,)+
+                }
+            }
+
+            fn values(&self) -> [f32; PARAM_COUNT] {
+                [$(self.$field,)+]
+            }
+
+            fn values_mut(&mut self) -> [&mut f32; PARAM_COUNT] {
+                [$(&mut self.$field,)+]
+            }
+        }
+    };
+}
+
+// Bump PARAMS_VERSION whenever entries are added, removed or reordered.
+param_table! {
+    rate_kp: Float = 0.065, [0.0, 0.5];
+    rate_ki: Float = 0.12, [0.0, 0.5];
+    rate_kd: Float = 0.01, [0.0, 0.1];
+    yaw_kp: Float = 0.08, [0.0, 0.5];
+    yaw_ki: Float = 0.12, [0.0, 0.5];
+    yaw_kd: Float = 0.0, [0.0, 0.1];
+    angle_p: Float = 5.0, [0.0, 20.0];
+    max_lean_deg: Float = 45.0, [5.0, 80.0];
+    yaw_rate_dps: Float = 200.0, [30.0, 1000.0];
+    max_power: Float = 0.4, [0.1, 1.0];
+    pid_limit: Float = 0.2, [0.05, 0.5];
+    rate_lpf_hz: Float = 100.0, [10.0, 500.0];
+    dterm_lpf_hz: Float = 40.0, [10.0, 250.0];
+    iterm_throttle: Float = 0.1, [0.0, 0.5];
+    alt_ki: Float = 0.005, [0.0, 0.1];
+    ahrs_beta: Float = 0.05, [0.001, 1.0];
+    acc_off_x: Float = -0.05, [-2.0, 2.0];
+    acc_off_y: Float = -0.40, [-2.0, 2.0];
+    acc_off_z: Float = 0.05, [-2.0, 2.0];
+    acc_scale_x: Float = 0.993833, [0.8, 1.2];
+    acc_scale_y: Float = 0.998219, [0.8, 1.2];
+    acc_scale_z: Float = 0.990074, [0.8, 1.2];
+    bb_rate_div: Int = 10.0, [1.0, 100.0];
+    arm_max_tilt: Float = 25.0, [5.0, 180.0];
+    fs_hold_ms: Int = 1000.0, [0.0, 10000.0];
+    fs_throttle: Float = 0.3, [0.0, 0.6];
+    fs_descent_rate: Float = 0.7, [0.2, 3.0];
+    fs_land_s: Int = 30.0, [1.0, 120.0];
+    rates_type: Int = 1.0, [0.0, 1.0];
+    rp_rc_rate: Float = 1.0, [0.01, 2.55];
+    rp_super_rate: Float = 0.7, [0.0, 0.99];
+    rp_center_dps: Float = 70.0, [10.0, 1000.0];
+    rp_max_dps: Float = 670.0, [10.0, 1998.0];
+    rp_expo: Float = 0.54, [0.0, 1.0];
+    yaw_rc_rate: Float = 1.0, [0.01, 2.55];
+    yaw_super_rate: Float = 0.7, [0.0, 0.99];
+    yaw_center_dps: Float = 70.0, [10.0, 1000.0];
+    yaw_max_dps: Float = 670.0, [10.0, 1998.0];
+    yaw_expo: Float = 0.54, [0.0, 1.0];
+    horizon_transition: Float = 0.75, [0.1, 1.0];
+    horizon_strength: Float = 1.0, [0.0, 1.0];
+    mixer: Int = 0.0, [0.0, 4.0];
+    motor_order: Int = 0.0, [0.0, 654321.0];
+    props_out: Bool = 0.0, [0.0, 1.0];
+    servo_reverse: Bool = 0.0, [0.0, 1.0];
+    airmode: Bool = 0.0, [0.0, 1.0];
+    dshot_bidir: Bool = 0.0, [0.0, 1.0];
+    motor_poles: Int = 14.0, [2.0, 48.0];
+    rpm_harmonics: Int = 3.0, [0.0, 3.0];
+    rpm_notch_q: Float = 5.0, [1.0, 20.0];
+    rpm_min_hz: Float = 100.0, [50.0, 200.0];
+    dyn_notch_count: Int = 2.0, [0.0, 3.0];
+    dyn_notch_q: Float = 3.0, [1.0, 20.0];
+    dyn_notch_min_hz: Float = 80.0, [20.0, 250.0];
+    dyn_notch_max_hz: Float = 450.0, [100.0, 500.0];
+    rate_kff: Float = 0.0, [0.0, 0.05];
+    yaw_kff: Float = 0.0, [0.0, 0.05];
+    rate_sp_weight: Float = 1.0, [0.0, 1.0];
+    yaw_sp_weight: Float = 1.0, [0.0, 1.0];
+    ff_lpf_hz: Float = 30.0, [5.0, 100.0];
+    ff_jitter_dps: Float = 3.0, [0.0, 20.0];
+    rate_windup: Int = 0.0, [0.0, 2.0];
+    yaw_windup: Int = 0.0, [0.0, 2.0];
+    windup_gain: Float = 10.0, [0.1, 100.0];
+    iterm_limit: Float = 0.5, [0.05, 1.0];
+    rate_iterm_relax: Bool = 0.0, [0.0, 1.0];
+    yaw_iterm_relax: Bool = 0.0, [0.0, 1.0];
+    relax_hz: Float = 15.0, [1.0, 50.0];
+    relax_dps: Float = 40.0, [5.0, 500.0];
+    tpa_rate: Float = 0.0, [0.0, 1.0];
+    tpa_breakpoint: Float = 0.6, [0.0, 1.0];
+    tpa_curve: Float = 1.0, [0.5, 3.0];
+    tpa_mode: Int = 0.0, [0.0, 1.0];
+    bat_cells: Int = 0.0, [0.0, 8.0];
+    bat_low_cell: Float = 3.5, [3.0, 4.0];
+    bat_crit_cell: Float = 3.3, [2.8, 3.8];
+    bat_resistance: Float = 0.0, [0.0, 0.2];
+    bat_comp: Bool = 0.0, [0.0, 1.0];
+    bat_comp_cell: Float = 3.8, [3.3, 4.35];
+    bat_comp_max: Float = 1.3, [1.0, 1.5];
+    rc_protocol: Int = 0.0, [0.0, 4.0];
+    rate_lpf_type: Int = 0.0, [0.0, 4.0];
+    dterm_lpf_type: Int = 0.0, [0.0, 4.0];
+}
+
+pub const PARAM_COUNT: usize = PARAM_INFO.len();
+
+impl Default for Params {
+    fn default() -> Params {
+        Params::defaults()
+    }
+}
+
+impl Params {
+    pub fn find(name: &str) -> Option<usize> {
+        PARAM_INFO.iter().position(|info| info.name == name)
+    }
+
+    pub fn get(&self, index: usize) -> Option<f32> {
+        self.values().get(index).copied()
+    }
+
+    pub fn set(&mut self, index: usize, value: f32) -> Result<(), ParamError> {
+        let info = PARAM_INFO.get(index).ok_or(ParamError::UnknownParam)?;
+        let value = match info.kind {
+            ParamType::Float => value,
+            ParamType::Int | ParamType::Bool => libm::roundf(value),
+        };
+        if !(info.min..=info.max).contains(&value) {
+            return Err(ParamError::OutOfRange);
+        }
+        *self.values_mut()[index] = value;
+        Ok(())
+    }
+
+    pub fn acc_offset(&self) -> Vector3<f32> {
+        Vector3::new(self.acc_off_x, self.acc_off_y, self.acc_off_z)
+    }
+
+    pub fn acc_scale(&self) -> Vector3<f32> {
+        Vector3::new(self.acc_scale_x, self.acc_scale_y, self.acc_scale_z)
+    }
+
+    pub fn to_bytes(&self) -> [u8; PARAMS_BLOB_SIZE] {
+        let mut blob = [0u8; PARAMS_BLOB_SIZE];
+        blob[0..4].copy_from_slice(&PARAMS_MAGIC.to_le_bytes());
+        blob[4..6].copy_from_slice(&PARAMS_VERSION.to_le_bytes());
+        blob[6..8].copy_from_slice(&(PARAM_COUNT as u16).to_le_bytes());
+        for (i, v) in self.values().iter().enumerate() {
+            let at = HEADER_SIZE + i * 4;
+            blob[at..at + 4].copy_from_slice(&v.to_le_bytes());
+        }
+        let crc_at = PARAMS_BLOB_SIZE - CRC_SIZE;
+        let crc = CRC.checksum(&blob[..crc_at]);
+        blob[crc_at..].copy_from_slice(&crc.to_le_bytes());
+        blob
+    }
+
+    pub fn from_bytes(blob: &[u8; PARAMS_BLOB_SIZE]) -> Result<Params, ParamError> {
+        let word =
+            |at: usize| u32::from_le_bytes([blob[at], blob[at + 1], blob[at + 2], blob[at + 3]]);
+        let half = |at: usize| u16::from_le_bytes([blob[at], blob[at + 1]]);
+
+        if word(0) != PARAMS_MAGIC {
+            return Err(ParamError::Empty);
+        }
+        if half(4) != PARAMS_VERSION || half(6) as usize != PARAM_COUNT {
+            return Err(ParamError::Stale);
+        }
+        let crc_at = PARAMS_BLOB_SIZE - CRC_SIZE;
+        if CRC.checksum(&blob[..crc_at]) != word(crc_at) {
+            return Err(ParamError::Corrupt);
+        }
+
+        let mut params = Params::defaults();
+        for index in 0..PARAM_COUNT {
+            let value = f32::from_bits(word(HEADER_SIZE + index * 4));
+            params.set(index, value).map_err(|_| ParamError::Corrupt)?;
+        }
+        Ok(params)
+    }
+
+    /// Falls back to defaults when the stored blob is missing, stale or corrupt.
+    pub fn load(storage: &mut impl ParamStorage) -> Params {
+        let mut blob = [0u8; PARAMS_BLOB_SIZE];
+        let result = storage
+            .read(&mut blob)
+            .and_then(|_| Params::from_bytes(&blob));
+
+        match result {
+            Ok(params) => {
+                log::info!("Params loaded (v{})", PARAMS_VERSION);
+                params
+            }
+            Err(e) => {
+                log::warn!("Params not loaded ({:?}), using defaults", e);
+                Params::defaults()
+            }
+        }
+    }
+
+    pub fn save(&self, storage: &mut impl ParamStorage) -> Result<(), ParamError> {
+        storage.write(&self.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MemStorage([u8; PARAMS_BLOB_SIZE]);
+
+    impl ParamStorage for MemStorage {
+        fn read(&mut self, buf: &mut [u8]) -> Result<(), ParamError> {
+            buf.copy_from_slice(&self.0[..buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), ParamError> {
+            self.0[..data.len()].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn erased() -> MemStorage {
+        MemStorage([0xff; PARAMS_BLOB_SIZE])
+    }
+
+    #[test]
+    fn defaults_are_within_limits() {
+        let params = Params::defaults();
+        for (index, info) in PARAM_INFO.iter().enumerate() {
+            let value = params.get(index).unwrap();
+            assert_eq!(value, info.default, "{}", info.name);
+            assert!((info.min..=info.max).contains(&value), "{}", info.name);
+        }
+    }
+
+    #[test]
+    fn names_are_unique() {
+        for (index, info) in PARAM_INFO.iter().enumerate() {
+            assert_eq!(Params::find(info.name), Some(index));
+        }
+    }
+
+    #[test]
+    fn set_checks_range() {
+        let mut params = Params::defaults();
+        let index = Params::find("rate_kp").unwrap();
+        assert_eq!(params.set(index, 0.2), Ok(()));
+        assert_eq!(params.rate_kp, 0.2);
+        assert_eq!(params.set(index, 5.0), Err(ParamError::OutOfRange));
+        assert_eq!(params.rate_kp, 0.2);
+        assert_eq!(params.set(PARAM_COUNT, 0.0), Err(ParamError::UnknownParam));
+    }
+
+    #[test]
+    fn save_then_load_roundtrips() {
+        let mut storage = erased();
+        let mut params = Params::defaults();
+        params.rate_kp = 0.1;
+        params.acc_off_y = -0.3;
+        params.save(&mut storage).unwrap();
+        assert_eq!(Params::load(&mut storage), params);
+    }
+
+    #[test]
+    fn erased_flash_gives_defaults() {
+        let blob = [0xff; PARAMS_BLOB_SIZE];
+        assert_eq!(Params::from_bytes(&blob), Err(ParamError::Empty));
+        assert_eq!(Params::load(&mut erased()), Params::defaults());
+    }
+
+    #[test]
+    fn corrupt_blob_gives_defaults() {
+        let mut storage = erased();
+        let mut params = Params::defaults();
+        params.rate_kp = 0.1;
+        params.save(&mut storage).unwrap();
+        storage.0[HEADER_SIZE] ^= 0x01;
+        assert_eq!(Params::from_bytes(&storage.0), Err(ParamError::Corrupt));
+        assert_eq!(Params::load(&mut storage), Params::defaults());
+    }
+
+    #[test]
+    fn other_version_is_stale() {
+        let mut blob = Params::defaults().to_bytes();
+        blob[4..6].copy_from_slice(&(PARAMS_VERSION + 1).to_le_bytes());
+        assert_eq!(Params::from_bytes(&blob), Err(ParamError::Stale));
+    }
+
+    #[test]
+    fn out_of_range_value_with_valid_crc_is_rejected() {
+        let mut params = Params::defaults();
+        params.max_power = 10.0;
+        assert_eq!(
+            Params::from_bytes(&params.to_bytes()),
+            Err(ParamError::Corrupt)
+        );
+    }
+}
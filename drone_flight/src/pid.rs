@@ -0,0 +1,462 @@
+use crate::filter::{Filter, FilterSpec, Pt1, Pt2};
+use crate::schedule::GainScale;
+
+#[derive(Copy, Clone)]
+pub struct Limits {
+    pub min: f32,
+    pub max: f32,
+}
+
+/// Feedforward and setpoint weighting of one axis.
+#[derive(Copy, Clone, Debug)]
+pub struct Feedforward {
+    /// Output per rad/s of setpoint change per second.
+    pub kff: f32,
+    /// Share of the setpoint in the P error, 1 for plain error feedback.
+    pub setpoint_weight: f32,
+    pub lpf_hz: f32,
+    /// Setpoint steps under this many rad/s are scaled down as RC jitter.
+    pub jitter: f32,
+}
+
+/// What keeps the integrator from winding up while the output can't follow it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Windup {
+    /// Only the integrator limit.
+    Clamp,
+    /// Holds the integrator while the output is saturated in the direction it would grow.
+    Conditional,
+    /// Bleeds the integrator by `gain` per second times the output lost to saturation.
+    BackCalculation { gain: f32 },
+}
+
+impl Windup {
+    pub fn from_param(value: f32, gain: f32) -> Windup {
+        match value as u8 {
+            0 => Windup::Clamp,
+            2 => Windup::BackCalculation { gain },
+            _ => Windup::Conditional,
+        }
+    }
+}
+
+/// Attenuates integration while the setpoint moves fast, so the I term doesn't
+/// overshoot at the end of flips and rolls.
+#[derive(Copy, Clone, Debug)]
+pub struct ItermRelax {
+    /// Setpoint low-pass, what moves faster than it counts as a stick move.
+    pub cutoff_hz: f32,
+    /// Setpoint rate in rad/s above the low-passed one where integration stops.
+    pub threshold: f32,
+}
+
+pub struct Pid {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub i: f32,
+    error: f32,
+    measured_rate: f32,
+    cycle_time: f32,
+    limit_i: f32,
+    limit_pid: Option<Limits>,
+    rate_lp: Option<Filter>,
+    d_lp: Option<Filter>,
+    kff: f32,
+    setpoint_weight: f32,
+    jitter: f32,
+    ff_lp: Option<Pt2>,
+    setpoint: f32,
+    windup: Windup,
+    /// Share of the last output the mixer passed on.
+    authority: f32,
+    /// Last output before and after the output limits.
+    raw: f32,
+    out: f32,
+    relax: Option<(ItermRelax, Pt1)>,
+    scale: GainScale,
+}
+
+impl Pid {
+    pub fn new(
+        kp: f32,
+        ki: f32,
+        kd: f32,
+        cycle_time: f32,
+        limit_pid: Option<Limits>,
+        rate_filter: Option<FilterSpec>,
+        d_filter: Option<FilterSpec>,
+    ) -> Pid {
+        let rate_lp = rate_filter.map(|spec| Filter::new(&spec, 1.0 / cycle_time));
+        let d_lp = d_filter.map(|spec| Filter::new(&spec, 1.0 / cycle_time));
+
+        Pid {
+            kp,
+            ki,
+            kd,
+            i: 0.0,
+            error: 0.0,
+            measured_rate: 0.0,
+            cycle_time,
+            limit_i: 0.5,
+            limit_pid,
+            rate_lp,
+            d_lp,
+            kff: 0.0,
+            setpoint_weight: 1.0,
+            jitter: 0.0,
+            ff_lp: None,
+            setpoint: 0.0,
+            windup: Windup::Clamp,
+            authority: 1.0,
+            raw: 0.0,
+            out: 0.0,
+            relax: None,
+            scale: GainScale::ONE,
+        }
+    }
+
+    pub fn with_windup(mut self, windup: Windup, limit_i: f32) -> Pid {
+        self.windup = windup;
+        self.limit_i = limit_i;
+        self
+    }
+
+    pub fn with_iterm_relax(mut self, relax: Option<ItermRelax>) -> Pid {
+        self.relax = relax.map(|relax| (relax, Pt1::new(relax.cutoff_hz, 1.0 / self.cycle_time)));
+        self
+    }
+
+    /// Scales the gains from the next update on, from a `GainSchedule`.
+    pub fn schedule(&mut self, scale: GainScale) {
+        self.scale = scale;
+    }
+
+    pub fn windup(&self) -> Windup {
+        self.windup
+    }
+
+    /// Feeds back the share of the last output the mixer could apply, 1 when it
+    /// wasn't saturated.
+    pub fn set_authority(&mut self, authority: f32) {
+        self.authority = authority;
+    }
+
+    pub fn with_feedforward(mut self, ff: Feedforward) -> Pid {
+        self.kff = ff.kff;
+        self.setpoint_weight = ff.setpoint_weight;
+        self.jitter = ff.jitter;
+        self.ff_lp = Some(Pt2::new(ff.lpf_hz, 1.0 / self.cycle_time));
+        self
+    }
+
+    /// Moves a setpoint step into the integrator so the output doesn't jump, for mode changes.
+    pub fn transfer(&mut self, from_rate: f32, to_rate: f32) {
+        let step = self.kp * self.scale.p * self.setpoint_weight * (from_rate - to_rate);
+        self.i = (self.i + step).clamp(-self.limit_i, self.limit_i);
+        // Not a stick move, feedforward shouldn't kick either
+        self.setpoint += to_rate - from_rate;
+    }
+
+    pub fn update(&mut self, desired_rate: f32, mut measured_rate: f32) -> f32 {
+        if let Some(filter) = &mut self.rate_lp {
+            measured_rate = filter.apply(measured_rate);
+        }
+
+        let error_rate = desired_rate - measured_rate;
+        // P term, on a weighted setpoint
+        let p = (self.setpoint_weight * desired_rate - measured_rate) * self.kp * self.scale.p;
+        // I term, relaxed while the setpoint moves fast
+        let mut i_error = error_rate;
+        if let Some((relax, lp)) = &mut self.relax {
+            let fast = (desired_rate - lp.apply(desired_rate)).abs();
+            i_error *= (1.0 - fast / relax.threshold).max(0.0);
+        }
+        let saturated = self.authority < 1.0 || self.out != self.raw;
+        let mut i = self.i;
+        if !(self.windup == Windup::Conditional && saturated && i_error * self.raw > 0.0) {
+            i += i_error * self.ki * self.scale.i * self.cycle_time;
+        }
+        i = i.clamp(-self.limit_i, self.limit_i);
+        // D term
+        let mut d =
+            -self.kd * self.scale.d * (measured_rate - self.measured_rate) / self.cycle_time;
+
+        if let Some(filter) = &mut self.d_lp {
+            d = filter.apply(d);
+        }
+        // Feedforward on setpoint changes, RC frames arrive in steps so it is smoothed
+        let mut ff = 0.0;
+        if let Some(filter) = &mut self.ff_lp {
+            let step = desired_rate - self.setpoint;
+            let jitter = if self.jitter > 0.0 {
+                (step.abs() / self.jitter).min(1.0)
+            } else {
+                1.0
+            };
+            ff = self.kff * filter.apply(step * jitter / self.cycle_time);
+        }
+
+        // state store
+        self.measured_rate = measured_rate;
+        self.setpoint = desired_rate;
+        self.error = error_rate;
+
+        let pid = p + i + d + ff;
+        let out = if let Some(limits) = self.limit_pid {
+            pid.clamp(limits.min, limits.max)
+        } else {
+            pid
+        };
+
+        // Integrator tracks what the motors can actually deliver
+        if let Windup::BackCalculation { gain } = self.windup {
+            let applied = out * self.authority;
+            i = (i + gain * (applied - pid) * self.cycle_time).clamp(-self.limit_i, self.limit_i);
+        }
+        self.i = i;
+        self.raw = pid;
+        self.out = out;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    #[test]
+    fn p_only_is_proportional_to_error() {
+        let mut pid = Pid::new(0.5, 0.0, 0.0, DT, None, None, None);
+        assert_eq!(pid.update(2.0, 0.0), 1.0);
+        assert_eq!(pid.update(0.0, 2.0), -1.0);
+    }
+
+    #[test]
+    fn i_term_is_clamped_against_windup() {
+        let mut pid = Pid::new(0.0, 10.0, 0.0, DT, None, None, None);
+        for _ in 0..10_000 {
+            pid.update(1.0, 0.0);
+        }
+        assert_eq!(pid.i, 0.5);
+
+        for _ in 0..10_000 {
+            pid.update(-1.0, 0.0);
+        }
+        assert_eq!(pid.i, -0.5);
+    }
+
+    #[test]
+    fn i_term_unwinds_as_soon_as_error_flips() {
+        let mut pid = Pid::new(0.0, 10.0, 0.0, DT, None, None, None);
+        for _ in 0..10_000 {
+            pid.update(1.0, 0.0);
+        }
+        let saturated = pid.i;
+        pid.update(-1.0, 0.0);
+        assert!(pid.i < saturated);
+    }
+
+    #[test]
+    fn d_term_acts_on_measurement_not_setpoint() {
+        let mut pid = Pid::new(0.0, 0.0, 1.0, DT, None, None, None);
+        assert_eq!(pid.update(5.0, 0.0), 0.0);
+        let out = pid.update(5.0, 0.001);
+        assert!((out + 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn transfer_keeps_output_continuous() {
+        let mut pid = Pid::new(0.5, 1.0, 0.0, DT, None, None, None);
+        pid.update(0.2, 0.1);
+        let before = pid.update(0.2, 0.1);
+        pid.transfer(0.2, 0.6);
+        let after = pid.update(0.6, 0.1);
+        assert!((after - before).abs() < 1e-3);
+    }
+
+    #[test]
+    fn output_respects_limits() {
+        let limits = Some(Limits {
+            min: -0.2,
+            max: 0.2,
+        });
+        let mut pid = Pid::new(1.0, 0.0, 0.0, DT, limits, None, None);
+        assert_eq!(pid.update(10.0, 0.0), 0.2);
+        assert_eq!(pid.update(-10.0, 0.0), -0.2);
+    }
+
+    #[test]
+    fn low_pass_settles_to_input() {
+        let mut lp = Filter::new(&FilterSpec::low_pass(0.0, 40.0), 1.0 / DT);
+        let first = lp.apply(1.0);
+        assert!(first > 0.0 && first < 0.1);
+        for _ in 0..1000 {
+            lp.apply(1.0);
+        }
+        assert!((lp.apply(1.0) - 1.0).abs() < 1e-4);
+    }
+
+    const FF: Feedforward = Feedforward {
+        kff: 0.01,
+        setpoint_weight: 1.0,
+        lpf_hz: 30.0,
+        jitter: 0.0,
+    };
+
+    #[test]
+    fn feedforward_follows_setpoint_changes() {
+        let mut pid = Pid::new(0.0, 0.0, 0.0, DT, None, None, None).with_feedforward(FF);
+        // RC frames every 10 ticks ramping the setpoint at 10 rad/s/s
+        let mut peak: f32 = 0.0;
+        let mut mean = 0.0;
+        for tick in 0..300 {
+            let setpoint = (tick / 10) as f32 * 0.1;
+            let out = pid.update(setpoint, 0.0);
+            peak = peak.max(out);
+            if tick >= 200 {
+                mean += out / 100.0;
+            }
+        }
+        assert!((mean - 0.1).abs() < 0.01, "{mean}");
+        assert!(peak < 0.2, "{peak}");
+
+        // A held stick gives none
+        let mut last = 0.0;
+        for _ in 0..500 {
+            last = pid.update(3.0, 0.0);
+        }
+        assert!(last.abs() < 1e-3);
+    }
+
+    #[test]
+    fn feedforward_ignores_jitter() {
+        let mut pid = Pid::new(0.0, 0.0, 0.0, DT, None, None, None)
+            .with_feedforward(Feedforward { jitter: 0.1, ..FF });
+        let mut smooth = Pid::new(0.0, 0.0, 0.0, DT, None, None, None).with_feedforward(FF);
+        let (mut noisy, mut clean) = (0.0f32, 0.0f32);
+        for tick in 0..200 {
+            let setpoint = if tick % 2 == 0 { 0.01 } else { 0.0 };
+            noisy = noisy.max(pid.update(setpoint, 0.0).abs());
+            clean = clean.max(smooth.update(setpoint, 0.0).abs());
+        }
+        assert!(noisy < clean * 0.2, "{noisy} {clean}");
+    }
+
+    #[test]
+    fn setpoint_weight_softens_p_on_steps() {
+        let weighted = Feedforward {
+            kff: 0.0,
+            setpoint_weight: 0.5,
+            ..FF
+        };
+        let mut pid = Pid::new(1.0, 0.0, 0.0, DT, None, None, None).with_feedforward(weighted);
+        assert_eq!(pid.update(2.0, 0.0), 1.0);
+        assert_eq!(pid.update(2.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn transfer_doesnt_kick_feedforward() {
+        let mut pid = Pid::new(0.5, 1.0, 0.0, DT, None, None, None).with_feedforward(FF);
+        for _ in 0..500 {
+            pid.update(0.2, 0.1);
+        }
+        let before = pid.update(0.2, 0.1);
+        pid.transfer(0.2, 0.6);
+        let after = pid.update(0.6, 0.1);
+        assert!((after - before).abs() < 1e-3);
+    }
+
+    #[test]
+    fn conditional_integration_holds_while_saturated() {
+        let limits = Some(Limits {
+            min: -0.2,
+            max: 0.2,
+        });
+        let mut pid =
+            Pid::new(1.0, 10.0, 0.0, DT, limits, None, None).with_windup(Windup::Conditional, 0.5);
+        for _ in 0..1000 {
+            pid.update(1.0, 0.0);
+        }
+        // Stops as soon as the output clamps instead of running to the limit
+        assert!(pid.i < 0.02, "{}", pid.i);
+
+        // Saturated in the mixer too, but unwinding is always allowed
+        pid.set_authority(0.5);
+        let held = pid.i;
+        pid.update(1.0, 0.0);
+        assert_eq!(pid.i, held);
+        pid.update(-1.0, 0.0);
+        assert!(pid.i < held);
+    }
+
+    #[test]
+    fn back_calculation_bleeds_what_the_mixer_drops() {
+        let windup = Windup::BackCalculation { gain: 10.0 };
+        let mut pid = Pid::new(0.0, 0.0, 0.0, DT, None, None, None).with_windup(windup, 0.5);
+        pid.i = 0.4;
+        pid.set_authority(0.0);
+        for _ in 0..500 {
+            pid.update(0.0, 0.0);
+        }
+        assert!(pid.i.abs() < 0.01, "{}", pid.i);
+
+        let mut pid = Pid::new(0.0, 0.0, 0.0, DT, None, None, None).with_windup(windup, 0.5);
+        pid.i = 0.4;
+        for _ in 0..500 {
+            pid.update(0.0, 0.0);
+        }
+        assert_eq!(pid.i, 0.4);
+    }
+
+    #[test]
+    fn iterm_relax_pauses_during_stick_moves() {
+        let relax = ItermRelax {
+            cutoff_hz: 15.0,
+            threshold: 0.5,
+        };
+        let mut relaxed =
+            Pid::new(0.0, 1.0, 0.0, DT, None, None, None).with_iterm_relax(Some(relax));
+        let mut plain = Pid::new(0.0, 1.0, 0.0, DT, None, None, None);
+        // A fast flip: setpoint ramps to 10 rad/s over 50 ms, the quad lags behind
+        for tick in 0..50 {
+            let setpoint = tick as f32 * 0.2;
+            relaxed.update(setpoint, setpoint * 0.5);
+            plain.update(setpoint, setpoint * 0.5);
+        }
+        assert!(relaxed.i < plain.i * 0.2, "{} {}", relaxed.i, plain.i);
+
+        // Held stick integrates normally again
+        let before = relaxed.i;
+        for _ in 0..500 {
+            relaxed.update(10.0, 9.0);
+        }
+        assert!(relaxed.i - before > 0.4);
+    }
+
+    #[test]
+    fn windup_param_picks_the_mode() {
+        assert_eq!(Windup::from_param(0.0, 5.0), Windup::Clamp);
+        assert_eq!(Windup::from_param(1.0, 5.0), Windup::Conditional);
+        assert_eq!(
+            Windup::from_param(2.0, 5.0),
+            Windup::BackCalculation { gain: 5.0 }
+        );
+    }
+
+    #[test]
+    fn schedule_scales_the_gains() {
+        let mut pid = Pid::new(1.0, 0.0, 1.0, DT, None, None, None);
+        pid.schedule(GainScale {
+            p: 0.5,
+            i: 1.0,
+            d: 0.25,
+        });
+        assert_eq!(pid.update(2.0, 0.0), 1.0);
+        assert_eq!(pid.kp, 1.0);
+        let out = pid.update(2.0, 0.001);
+        assert!((out - (0.5 * 1.999 - 0.25)).abs() < 1e-3, "{out}");
+    }
+}
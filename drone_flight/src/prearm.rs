@@ -0,0 +1,295 @@
+use crate::consts::{CYCLE_TIME, PREARM_GYRO_MAX, PREARM_LOOP_TOLERANCE, PREARM_RC_RECOVERY_TICKS};
+use crate::imu::ImuData;
+use crate::mixer::Mixer;
+use crate::params::Params;
+use crate::rc_protocol::Protocol;
+use portable_atomic::{AtomicU16, Ordering};
+
+/// Bits of the failing check mask, zero means clear to arm.
+pub mod checks {
+    pub const IMU_CALIBRATING: u16 = 1 << 0;
+    pub const BARO_CALIBRATING: u16 = 1 << 1;
+    pub const TILT: u16 = 1 << 2;
+    pub const GYRO_MOVING: u16 = 1 << 3;
+    pub const LOOP_TIMING: u16 = 1 << 4;
+    pub const RC_FAILSAFE: u16 = 1 << 5;
+    pub const ESC_TELEMETRY: u16 = 1 << 6;
+    pub const BATTERY: u16 = 1 << 7;
+    pub const MOTOR_PINS: u16 = 1 << 8;
+
+    pub const NAMES: [(u16, &str); 9] = [
+        (IMU_CALIBRATING, "IMU_CALIBRATING"),
+        (BARO_CALIBRATING, "BARO_CALIBRATING"),
+        (TILT, "TILT"),
+        (GYRO_MOVING, "GYRO_MOVING"),
+        (LOOP_TIMING, "LOOP_TIMING"),
+        (RC_FAILSAFE, "RC_FAILSAFE"),
+        (ESC_TELEMETRY, "ESC_TELEMETRY"),
+        (BATTERY, "BATTERY"),
+        (MOTOR_PINS, "MOTOR_PINS"),
+    ];
+}
+
+/// Failing checks of the last update, for the ground station protocols.
+pub static PREARM_FAILURES: AtomicU16 = AtomicU16::new(0);
+
+// Smoothing of the measured loop period, about 100 ticks
+const LOOP_DT_ALPHA: f32 = 0.01;
+
+// Motors 5-6 are driven by the second PIO, which times PPM instead
+const EXT_PIO_PINS: u8 = 0b11_0000;
+
+/// Conditions that have to hold before the arm switch is honored.
+///
+/// The firmware only publishes IMU and baro data once calibrated, so missing
+/// data counts as still calibrating.
+pub struct PreArm {
+    cos_max_tilt: f32,
+    loop_dt: f32,
+    rc_ok_ticks: u64,
+    ppm: bool,
+    motors_missing: bool,
+    failures: u16,
+}
+
+impl PreArm {
+    /// `params` are the ones booted with, the RC protocol isn't switched without a reboot.
+    pub fn new(params: &Params) -> PreArm {
+        let mut prearm = PreArm {
+            cos_max_tilt: 1.0,
+            loop_dt: CYCLE_TIME,
+            // No failsafe has happened yet at boot
+            rc_ok_ticks: PREARM_RC_RECOVERY_TICKS,
+            ppm: Protocol::from_param(params.rc_protocol) == Protocol::Ppm,
+            motors_missing: false,
+            failures: 0,
+        };
+        prearm.apply_params(params);
+        prearm
+    }
+
+    pub fn apply_params(&mut self, params: &Params) {
+        self.cos_max_tilt = libm::cosf(params.arm_max_tilt.to_radians());
+        self.motors_missing = self.ppm && Mixer::new(params).used_pins() & EXT_PIO_PINS != 0;
+    }
+
+    /// `att` is the last attitude estimate as roll, pitch, yaw in radians. `esc_valid`
+    /// is false while a motor's ESC doesn't answer with bidirectional DShot on,
+    /// `battery_valid` while there is no battery reading or it is low.
+    pub fn update(
+        &mut self,
+        imu: Option<&ImuData>,
+        rc_valid: bool,
+        baro_valid: bool,
+        esc_valid: bool,
+        battery_valid: bool,
+        att: &[f32; 3],
+    ) -> u16 {
+        let mut failures = 0;
+
+        self.rc_ok_ticks = if rc_valid {
+            self.rc_ok_ticks.saturating_add(1)
+        } else {
+            0
+        };
+        if self.rc_ok_ticks < PREARM_RC_RECOVERY_TICKS {
+            failures |= checks::RC_FAILSAFE;
+        }
+
+        if !baro_valid {
+            failures |= checks::BARO_CALIBRATING;
+        }
+
+        if !esc_valid {
+            failures |= checks::ESC_TELEMETRY;
+        }
+
+        if !battery_valid {
+            failures |= checks::BATTERY;
+        }
+
+        if self.motors_missing {
+            failures |= checks::MOTOR_PINS;
+        }
+
+        match imu {
+            Some(imu) => {
+                self.loop_dt += (imu.dt - self.loop_dt) * LOOP_DT_ALPHA;
+                if (self.loop_dt - CYCLE_TIME).abs() > CYCLE_TIME * PREARM_LOOP_TOLERANCE {
+                    failures |= checks::LOOP_TIMING;
+                }
+                if imu.gyro.norm() > PREARM_GYRO_MAX {
+                    failures |= checks::GYRO_MOVING;
+                }
+                // Cosine of the angle between body z and vertical, negative when upside down
+                if libm::cosf(att[0]) * libm::cosf(att[1]) < self.cos_max_tilt {
+                    failures |= checks::TILT;
+                }
+            }
+            None => failures |= checks::IMU_CALIBRATING,
+        }
+
+        if failures != self.failures {
+            if failures == 0 {
+                log::info!("[PREARM] all checks passed");
+            } else {
+                log::warn!("[PREARM] failing checks {:#06b}", failures);
+                for (_, name) in checks::NAMES.iter().filter(|(bit, _)| failures & bit != 0) {
+                    log::warn!("[PREARM]   {}", name);
+                }
+            }
+        }
+        self.failures = failures;
+        PREARM_FAILURES.store(failures, Ordering::Relaxed);
+        failures
+    }
+
+    pub fn failures(&self) -> u16 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mixer::Frame;
+    use nalgebra::Vector3;
+
+    fn imu(gyro: f32, dt: f32) -> ImuData {
+        ImuData {
+            gyro: Vector3::new(gyro, 0.0, 0.0),
+            acc: Vector3::new(0.0, 0.0, 9.81),
+            mag: Vector3::zeros(),
+            dt,
+        }
+    }
+
+    fn level() -> [f32; 3] {
+        [0.0; 3]
+    }
+
+    #[test]
+    fn clear_on_a_healthy_level_drone() {
+        let mut prearm = PreArm::new(&Params::defaults());
+        let still = imu(0.0, CYCLE_TIME);
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &level()),
+            0
+        );
+    }
+
+    #[test]
+    fn missing_sensors_count_as_calibrating() {
+        let mut prearm = PreArm::new(&Params::defaults());
+        let failures = prearm.update(None, true, false, true, true, &level());
+        assert_eq!(failures, checks::IMU_CALIBRATING | checks::BARO_CALIBRATING);
+    }
+
+    #[test]
+    fn silent_esc_fails() {
+        let mut prearm = PreArm::new(&Params::defaults());
+        let still = imu(0.0, CYCLE_TIME);
+        assert_eq!(
+            prearm.update(Some(&still), true, true, false, true, &level()),
+            checks::ESC_TELEMETRY
+        );
+    }
+
+    #[test]
+    fn low_battery_fails() {
+        let mut prearm = PreArm::new(&Params::defaults());
+        let still = imu(0.0, CYCLE_TIME);
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, false, &level()),
+            checks::BATTERY
+        );
+    }
+
+    #[test]
+    fn ppm_blocks_six_motor_frames() {
+        let still = imu(0.0, CYCLE_TIME);
+        let mut params = Params {
+            rc_protocol: Protocol::Ppm as u8 as f32,
+            ..Params::defaults()
+        };
+        let mut prearm = PreArm::new(&params);
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &level()),
+            0
+        );
+
+        params.mixer = Frame::HexX as u8 as f32;
+        prearm.apply_params(&params);
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &level()),
+            checks::MOTOR_PINS
+        );
+
+        // The RC protocol only changes at boot
+        params.rc_protocol = Protocol::Sbus as u8 as f32;
+        prearm.apply_params(&params);
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &level()),
+            checks::MOTOR_PINS
+        );
+        let mut prearm = PreArm::new(&params);
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &level()),
+            0
+        );
+    }
+
+    #[test]
+    fn tilt_and_upside_down_fail() {
+        let mut prearm = PreArm::new(&Params::defaults());
+        let still = imu(0.0, CYCLE_TIME);
+        let tilted = [0.0, 40f32.to_radians(), 0.0];
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &tilted),
+            checks::TILT
+        );
+        let inverted = [core::f32::consts::PI, 0.0, 1.0];
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &inverted),
+            checks::TILT
+        );
+        let yawed = [0.0, 0.0, 3.0];
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &yawed),
+            0
+        );
+    }
+
+    #[test]
+    fn moving_gyro_and_slow_loop_fail() {
+        let mut prearm = PreArm::new(&Params::defaults());
+        let moving = imu(PREARM_GYRO_MAX * 2.0, CYCLE_TIME);
+        assert_eq!(
+            prearm.update(Some(&moving), true, true, true, true, &level()),
+            checks::GYRO_MOVING
+        );
+
+        let slow = imu(0.0, CYCLE_TIME * 2.0);
+        let failures = (0..500)
+            .map(|_| prearm.update(Some(&slow), true, true, true, true, &level()))
+            .last();
+        assert_eq!(failures, Some(checks::LOOP_TIMING));
+    }
+
+    #[test]
+    fn rc_failsafe_blocks_for_recovery_time() {
+        let mut prearm = PreArm::new(&Params::defaults());
+        let still = imu(0.0, CYCLE_TIME);
+        prearm.update(Some(&still), false, true, true, true, &level());
+        for _ in 1..PREARM_RC_RECOVERY_TICKS {
+            assert_eq!(
+                prearm.update(Some(&still), true, true, true, true, &level()),
+                checks::RC_FAILSAFE
+            );
+        }
+        assert_eq!(
+            prearm.update(Some(&still), true, true, true, true, &level()),
+            0
+        );
+    }
+}
@@ -0,0 +1,624 @@
+use crate::params::{PARAM_COUNT, PARAM_INFO, PARAMS_VERSION, ParamError, Params};
+use crc::{CRC_16_IBM_3740, Crc};
+
+// Request:  SYNC, cmd, seq, len, payload[len], crc16
+// Response: SYNC, cmd | RESPONSE_FLAG, seq, status, len, payload[len], crc16
+// CRC-16/CCITT-FALSE little endian, covering everything between SYNC and the CRC.
+pub const SYNC: u8 = 0xa5;
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const RESPONSE_FLAG: u8 = 0x80;
+pub const MAX_PAYLOAD: usize = 48;
+pub const MAX_FRAME: usize = 5 + MAX_PAYLOAD + 2;
+
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Command {
+    Version = 0x01,
+    ParamInfo = 0x02,
+    ParamGet = 0x03,
+    ParamSet = 0x04,
+    ParamSave = 0x05,
+    Reboot = 0x06,
+    BlackboxInfo = 0x07,
+    BlackboxRead = 0x08,
+    BlackboxErase = 0x09,
+    Telemetry = 0x0a,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = Status;
+
+    fn try_from(value: u8) -> Result<Command, Status> {
+        match value {
+            0x01 => Ok(Command::Version),
+            0x02 => Ok(Command::ParamInfo),
+            0x03 => Ok(Command::ParamGet),
+            0x04 => Ok(Command::ParamSet),
+            0x05 => Ok(Command::ParamSave),
+            0x06 => Ok(Command::Reboot),
+            0x07 => Ok(Command::BlackboxInfo),
+            0x08 => Ok(Command::BlackboxRead),
+            0x09 => Ok(Command::BlackboxErase),
+            0x0a => Ok(Command::Telemetry),
+            _ => Err(Status::UnknownCommand),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Ok = 0,
+    BadChecksum = 1,
+    UnknownCommand = 2,
+    BadLength = 3,
+    UnknownParam = 4,
+    OutOfRange = 5,
+    Armed = 6,
+    Storage = 7,
+}
+
+impl TryFrom<u8> for Status {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Status, ()> {
+        match value {
+            0 => Ok(Status::Ok),
+            1 => Ok(Status::BadChecksum),
+            2 => Ok(Status::UnknownCommand),
+            3 => Ok(Status::BadLength),
+            4 => Ok(Status::UnknownParam),
+            5 => Ok(Status::OutOfRange),
+            6 => Ok(Status::Armed),
+            7 => Ok(Status::Storage),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ParamError> for Status {
+    fn from(e: ParamError) -> Status {
+        match e {
+            ParamError::UnknownParam => Status::UnknownParam,
+            ParamError::OutOfRange => Status::OutOfRange,
+            _ => Status::Storage,
+        }
+    }
+}
+
+/// What the caller has to do once the response is queued.
+/// Blackbox actions fill in the response payload before it is sent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Apply,
+    Save,
+    Reboot,
+    BlackboxInfo,
+    BlackboxRead { offset: u32, len: usize },
+    BlackboxErase,
+}
+
+pub struct Request {
+    pub cmd: u8,
+    pub seq: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Request {
+    /// Payloads longer than `MAX_PAYLOAD` are truncated.
+    pub fn new(cmd: u8, seq: u8, payload: &[u8]) -> Request {
+        let len = payload.len().min(MAX_PAYLOAD);
+        let mut buf = [0; MAX_PAYLOAD];
+        buf[..len].copy_from_slice(&payload[..len]);
+        Request {
+            cmd,
+            seq,
+            len,
+            payload: buf,
+        }
+    }
+
+    pub fn encode(&self, out: &mut [u8; MAX_FRAME]) -> usize {
+        out[0] = SYNC;
+        out[1] = self.cmd;
+        out[2] = self.seq;
+        out[3] = self.len as u8;
+        out[4..4 + self.len].copy_from_slice(self.payload());
+        let end = 4 + self.len;
+        let crc = CRC.checksum(&out[1..end]);
+        out[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+        end + 2
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+
+    fn index(&self) -> Result<usize, Status> {
+        match self.payload() {
+            [lo, hi, ..] => Ok(u16::from_le_bytes([*lo, *hi]) as usize),
+            _ => Err(Status::BadLength),
+        }
+    }
+
+    fn value(&self) -> Result<f32, Status> {
+        match self.payload() {
+            [_, _, a, b, c, d] => Ok(f32::from_le_bytes([*a, *b, *c, *d])),
+            _ => Err(Status::BadLength),
+        }
+    }
+
+    fn read_range(&self) -> Result<(u32, usize), Status> {
+        match self.payload() {
+            [a, b, c, d, len] if *len as usize <= MAX_PAYLOAD => {
+                Ok((u32::from_le_bytes([*a, *b, *c, *d]), *len as usize))
+            }
+            _ => Err(Status::BadLength),
+        }
+    }
+}
+
+pub struct Response {
+    pub cmd: u8,
+    pub seq: u8,
+    pub status: Status,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Response {
+    pub fn new(cmd: u8, seq: u8, status: Status) -> Response {
+        Response {
+            cmd,
+            seq,
+            status,
+            len: 0,
+            payload: [0; MAX_PAYLOAD],
+        }
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+
+    /// Appends to the payload, silently dropping whatever does not fit.
+    pub fn push(&mut self, bytes: &[u8]) -> &mut Response {
+        let n = bytes.len().min(MAX_PAYLOAD - self.len);
+        self.payload[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+        self
+    }
+
+    pub fn encode(&self, out: &mut [u8; MAX_FRAME]) -> usize {
+        out[0] = SYNC;
+        out[1] = self.cmd | RESPONSE_FLAG;
+        out[2] = self.seq;
+        out[3] = self.status as u8;
+        out[4] = self.len as u8;
+        out[5..5 + self.len].copy_from_slice(self.payload());
+        let end = 5 + self.len;
+        let crc = CRC.checksum(&out[1..end]);
+        out[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+        end + 2
+    }
+
+    /// Host side counterpart of `encode`, expects exactly one frame.
+    pub fn decode(frame: &[u8]) -> Option<Response> {
+        let [SYNC, cmd, seq, status, len, rest @ ..] = frame else {
+            return None;
+        };
+        let len = *len as usize;
+        if len > MAX_PAYLOAD || rest.len() != len + 2 {
+            return None;
+        }
+        let crc = u16::from_le_bytes([rest[len], rest[len + 1]]);
+        if CRC.checksum(&frame[1..5 + len]) != crc || cmd & RESPONSE_FLAG == 0 {
+            return None;
+        }
+        let status = Status::try_from(*status).ok()?;
+        let mut response = Response::new(cmd & !RESPONSE_FLAG, *seq, status);
+        response.push(&rest[..len]);
+        Some(response)
+    }
+}
+
+/// Byte-wise request decoder, resynchronizes on `SYNC` after any error.
+pub struct Parser {
+    buf: [u8; MAX_FRAME],
+    pos: usize,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            buf: [0; MAX_FRAME],
+            pos: 0,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// Returns a request, or an error response to send back, once a frame is complete.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Request, Response>> {
+        if self.pos == 0 && byte != SYNC {
+            return None;
+        }
+        self.buf[self.pos] = byte;
+        self.pos += 1;
+
+        if self.pos < 4 {
+            return None;
+        }
+        let (cmd, seq, len) = (self.buf[1], self.buf[2], self.buf[3] as usize);
+        if len > MAX_PAYLOAD {
+            self.pos = 0;
+            return Some(Err(Response::new(cmd, seq, Status::BadLength)));
+        }
+        let end = 4 + len;
+        if self.pos < end + 2 {
+            return None;
+        }
+        self.pos = 0;
+
+        let crc = u16::from_le_bytes([self.buf[end], self.buf[end + 1]]);
+        if CRC.checksum(&self.buf[1..end]) != crc {
+            return Some(Err(Response::new(cmd, seq, Status::BadChecksum)));
+        }
+
+        let mut payload = [0; MAX_PAYLOAD];
+        payload[..len].copy_from_slice(&self.buf[4..end]);
+        Some(Ok(Request {
+            cmd,
+            seq,
+            len,
+            payload,
+        }))
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+/// Parameter writes, saving, rebooting and blackbox erasing are refused while `armed`.
+pub fn handle(
+    request: &Request,
+    params: &mut Params,
+    armed: bool,
+    firmware_version: &str,
+) -> (Response, Action) {
+    let mut response = Response::new(request.cmd, request.seq, Status::Ok);
+    match execute(request, params, armed, firmware_version, &mut response) {
+        Ok(action) => (response, action),
+        Err(status) => (
+            Response::new(request.cmd, request.seq, status),
+            Action::None,
+        ),
+    }
+}
+
+fn execute(
+    request: &Request,
+    params: &mut Params,
+    armed: bool,
+    firmware_version: &str,
+    response: &mut Response,
+) -> Result<Action, Status> {
+    let command = Command::try_from(request.cmd)?;
+    let restricted = matches!(
+        command,
+        Command::ParamSet | Command::ParamSave | Command::Reboot | Command::BlackboxErase
+    );
+    if armed && restricted {
+        return Err(Status::Armed);
+    }
+
+    match command {
+        Command::Version => {
+            response
+                .push(&[PROTOCOL_VERSION])
+                .push(&PARAMS_VERSION.to_le_bytes())
+                .push(&(PARAM_COUNT as u16).to_le_bytes())
+                .push(firmware_version.as_bytes());
+            Ok(Action::None)
+        }
+        Command::ParamInfo => {
+            let index = request.index()?;
+            let info = PARAM_INFO.get(index).ok_or(Status::UnknownParam)?;
+            let value = params.get(index).ok_or(Status::UnknownParam)?;
+            response
+                .push(&(index as u16).to_le_bytes())
+                .push(&[info.kind as u8])
+                .push(&value.to_le_bytes())
+                .push(&info.default.to_le_bytes())
+                .push(&info.min.to_le_bytes())
+                .push(&info.max.to_le_bytes())
+                .push(info.name.as_bytes());
+            Ok(Action::None)
+        }
+        Command::ParamGet => {
+            let index = request.index()?;
+            let value = params.get(index).ok_or(Status::UnknownParam)?;
+            response
+                .push(&(index as u16).to_le_bytes())
+                .push(&value.to_le_bytes());
+            Ok(Action::None)
+        }
+        Command::ParamSet => {
+            let index = request.index()?;
+            params.set(index, request.value()?)?;
+            let value = params.get(index).ok_or(Status::UnknownParam)?;
+            response
+                .push(&(index as u16).to_le_bytes())
+                .push(&value.to_le_bytes());
+            Ok(Action::Apply)
+        }
+        Command::ParamSave => Ok(Action::Save),
+        Command::Reboot => Ok(Action::Reboot),
+        Command::BlackboxInfo => Ok(Action::BlackboxInfo),
+        Command::BlackboxRead => {
+            let (offset, len) = request.read_range()?;
+            Ok(Action::BlackboxRead { offset, len })
+        }
+        Command::BlackboxErase => Ok(Action::BlackboxErase),
+        Command::Telemetry => telemetry(request, response),
+    }
+}
+
+/// Payload: (category, divisor) pairs, divisor 0 unsubscribes. Replies with the subscription mask.
+#[cfg(feature = "telemetry")]
+fn telemetry(request: &Request, response: &mut Response) -> Result<Action, Status> {
+    let (pairs, rest) = request.payload().as_chunks::<2>();
+    if !rest.is_empty() {
+        return Err(Status::BadLength);
+    }
+    for &[category, divisor] in pairs {
+        if !crate::telemetry::subscribe(category, divisor) {
+            return Err(Status::OutOfRange);
+        }
+    }
+    response.push(&crate::telemetry::subscriptions().to_le_bytes());
+    Ok(Action::None)
+}
+
+#[cfg(not(feature = "telemetry"))]
+fn telemetry(_request: &Request, _response: &mut Response) -> Result<Action, Status> {
+    Err(Status::UnknownCommand)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(request: &Request) -> Vec<u8> {
+        let mut out = [0; MAX_FRAME];
+        let n = request.encode(&mut out);
+        out[..n].to_vec()
+    }
+
+    fn request(cmd: Command, payload: &[u8]) -> Request {
+        Request::new(cmd as u8, 7, payload)
+    }
+
+    fn set_payload(index: usize, value: f32) -> Vec<u8> {
+        let mut payload = (index as u16).to_le_bytes().to_vec();
+        payload.extend_from_slice(&value.to_le_bytes());
+        payload
+    }
+
+    #[test]
+    fn parses_frame_after_garbage() {
+        let mut bytes = vec![0x00, 0x13, 0x37];
+        bytes.extend(encoded(&Request::new(Command::ParamGet as u8, 42, &[1, 0])));
+        let mut parser = Parser::new();
+        let req = bytes
+            .iter()
+            .find_map(|&b| parser.push(b))
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert_eq!(req.cmd, Command::ParamGet as u8);
+        assert_eq!(req.seq, 42);
+        assert_eq!(req.payload(), &[1, 0]);
+        assert!(parser.is_idle());
+    }
+
+    #[test]
+    fn bad_checksum_is_reported_with_its_seq() {
+        // Unlike MSP the host gets an answer, so it can retry the exact request
+        let mut bytes = encoded(&Request::new(Command::Version as u8, 3, &[]));
+        *bytes.last_mut().unwrap() ^= 0xff;
+        bytes.extend(encoded(&Request::new(Command::Version as u8, 4, &[])));
+        let mut parser = Parser::new();
+        let results: Vec<_> = bytes.iter().filter_map(|&b| parser.push(b)).collect();
+        assert_eq!(results.len(), 2);
+        let err = results[0].as_ref().err().unwrap();
+        assert_eq!((err.cmd, err.seq), (Command::Version as u8, 3));
+        assert_eq!(err.status, Status::BadChecksum);
+        assert_eq!(results[1].as_ref().ok().unwrap().seq, 4);
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected_early() {
+        let mut parser = Parser::new();
+        let result = [SYNC, 1, 0, MAX_PAYLOAD as u8 + 1]
+            .iter()
+            .find_map(|&b| parser.push(b));
+        assert_eq!(result.unwrap().err().unwrap().status, Status::BadLength);
+        assert!(parser.is_idle());
+    }
+
+    #[test]
+    fn response_encoding_has_valid_crc() {
+        let (response, _) = handle(
+            &request(Command::Version, &[]),
+            &mut Params::defaults(),
+            false,
+            "1.2.3",
+        );
+        let mut out = [0; MAX_FRAME];
+        let n = response.encode(&mut out);
+        assert_eq!(out[0], SYNC);
+        assert_eq!(out[1], Command::Version as u8 | RESPONSE_FLAG);
+        assert_eq!(out[2], 7);
+        assert_eq!(out[3], Status::Ok as u8);
+        let crc = u16::from_le_bytes([out[n - 2], out[n - 1]]);
+        assert_eq!(CRC.checksum(&out[1..n - 2]), crc);
+        assert_eq!(&response.payload()[5..], b"1.2.3");
+    }
+
+    #[test]
+    fn param_info_describes_entry() {
+        let index = Params::find("max_power").unwrap();
+        let (response, action) = handle(
+            &request(Command::ParamInfo, &(index as u16).to_le_bytes()),
+            &mut Params::defaults(),
+            false,
+            "",
+        );
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(action, Action::None);
+        assert_eq!(&response.payload()[19..], b"max_power");
+    }
+
+    #[test]
+    fn set_updates_params_when_disarmed() {
+        let mut params = Params::defaults();
+        let index = Params::find("rate_kp").unwrap();
+        let (response, action) = handle(
+            &request(Command::ParamSet, &set_payload(index, 0.2)),
+            &mut params,
+            false,
+            "",
+        );
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(action, Action::Apply);
+        assert_eq!(params.rate_kp, 0.2);
+    }
+
+    #[test]
+    fn set_out_of_range_is_refused() {
+        let mut params = Params::defaults();
+        let index = Params::find("rate_kp").unwrap();
+        let (response, action) = handle(
+            &request(Command::ParamSet, &set_payload(index, 100.0)),
+            &mut params,
+            false,
+            "",
+        );
+        assert_eq!(response.status, Status::OutOfRange);
+        assert_eq!(action, Action::None);
+        assert_eq!(params, Params::defaults());
+    }
+
+    #[test]
+    fn writes_are_refused_while_armed() {
+        let mut params = Params::defaults();
+        let index = Params::find("rate_kp").unwrap();
+        let set = request(Command::ParamSet, &set_payload(index, 0.2));
+        for req in [
+            set,
+            request(Command::ParamSave, &[]),
+            request(Command::Reboot, &[]),
+            request(Command::BlackboxErase, &[]),
+        ] {
+            let (response, action) = handle(&req, &mut params, true, "");
+            assert_eq!(response.status, Status::Armed);
+            assert_eq!(action, Action::None);
+        }
+        assert_eq!(params, Params::defaults());
+
+        let (response, _) = handle(
+            &request(Command::ParamGet, &(index as u16).to_le_bytes()),
+            &mut params,
+            true,
+            "",
+        );
+        assert_eq!(response.status, Status::Ok);
+    }
+
+    #[test]
+    fn blackbox_read_is_bounded() {
+        let mut params = Params::defaults();
+        let mut payload = 8192u32.to_le_bytes().to_vec();
+        payload.push(MAX_PAYLOAD as u8);
+        let (response, action) = handle(
+            &request(Command::BlackboxRead, &payload),
+            &mut params,
+            true,
+            "",
+        );
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(
+            action,
+            Action::BlackboxRead {
+                offset: 8192,
+                len: MAX_PAYLOAD
+            }
+        );
+
+        *payload.last_mut().unwrap() += 1;
+        let (response, action) = handle(
+            &request(Command::BlackboxRead, &payload),
+            &mut params,
+            false,
+            "",
+        );
+        assert_eq!(response.status, Status::BadLength);
+        assert_eq!(action, Action::None);
+    }
+
+    #[test]
+    fn host_side_roundtrip() {
+        let mut parser = Parser::new();
+        let bytes = encoded(&Request::new(Command::ParamGet as u8, 9, &[2, 0]));
+        let req = bytes
+            .iter()
+            .find_map(|&b| parser.push(b))
+            .unwrap()
+            .ok()
+            .unwrap();
+        let (response, _) = handle(&req, &mut Params::defaults(), false, "");
+        let mut out = [0; MAX_FRAME];
+        let n = response.encode(&mut out);
+        let decoded = Response::decode(&out[..n]).unwrap();
+        assert_eq!((decoded.cmd, decoded.seq), (Command::ParamGet as u8, 9));
+        assert_eq!(decoded.status, Status::Ok);
+        assert_eq!(decoded.payload(), response.payload());
+
+        out[5] ^= 1;
+        assert!(Response::decode(&out[..n]).is_none());
+    }
+
+    #[test]
+    fn host_requests_are_truncated_to_fit() {
+        let req = Request::new(Command::ParamSet as u8, 0, &[1; MAX_PAYLOAD + 10]);
+        assert_eq!(req.payload().len(), MAX_PAYLOAD);
+        let mut parser = Parser::new();
+        let parsed = encoded(&req).iter().find_map(|&b| parser.push(b));
+        assert_eq!(parsed.unwrap().ok().unwrap().payload(), req.payload());
+    }
+
+    #[test]
+    fn unknown_command_and_param() {
+        let mut params = Params::defaults();
+        let req = Request::new(0x7f, 0, &[]);
+        assert_eq!(
+            handle(&req, &mut params, false, "").0.status,
+            Status::UnknownCommand
+        );
+        let req = request(Command::ParamGet, &(PARAM_COUNT as u16).to_le_bytes());
+        assert_eq!(
+            handle(&req, &mut params, false, "").0.status,
+            Status::UnknownParam
+        );
+    }
+}
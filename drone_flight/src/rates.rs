@@ -0,0 +1,165 @@
+use crate::params::Params;
+
+// Betaflight's extra gain per unit of RC rate above 2.0
+const RC_RATE_INCREMENTAL: f32 = 14.54;
+const RATE_LIMIT_DPS: f32 = 1998.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RatesType {
+    Betaflight = 0,
+    Actual = 1,
+}
+
+/// Stick to body rate curve for acro mode, same shapes as Betaflight's rate profiles.
+#[derive(Copy, Clone, Debug)]
+pub enum Rates {
+    Betaflight {
+        rc_rate: f32,
+        super_rate: f32,
+        expo: f32,
+    },
+    Actual {
+        center_dps: f32,
+        max_dps: f32,
+        expo: f32,
+    },
+}
+
+impl Rates {
+    pub fn roll_pitch(params: &Params) -> Rates {
+        match rates_type(params) {
+            RatesType::Betaflight => Rates::Betaflight {
+                rc_rate: params.rp_rc_rate,
+                super_rate: params.rp_super_rate,
+                expo: params.rp_expo,
+            },
+            RatesType::Actual => Rates::Actual {
+                center_dps: params.rp_center_dps,
+                max_dps: params.rp_max_dps,
+                expo: params.rp_expo,
+            },
+        }
+    }
+
+    pub fn yaw(params: &Params) -> Rates {
+        match rates_type(params) {
+            RatesType::Betaflight => Rates::Betaflight {
+                rc_rate: params.yaw_rc_rate,
+                super_rate: params.yaw_super_rate,
+                expo: params.yaw_expo,
+            },
+            RatesType::Actual => Rates::Actual {
+                center_dps: params.yaw_center_dps,
+                max_dps: params.yaw_max_dps,
+                expo: params.yaw_expo,
+            },
+        }
+    }
+
+    /// Body rate in rad/s for a stick deflection in -1..1.
+    pub fn rate(&self, stick: f32) -> f32 {
+        let stick = stick.clamp(-1.0, 1.0);
+        let abs = stick.abs();
+        let dps = match *self {
+            Rates::Betaflight {
+                rc_rate,
+                super_rate,
+                expo,
+            } => {
+                let curved = stick * abs * abs * abs * expo + stick * (1.0 - expo);
+                let rc_rate = if rc_rate > 2.0 {
+                    rc_rate + RC_RATE_INCREMENTAL * (rc_rate - 2.0)
+                } else {
+                    rc_rate
+                };
+                let super_factor = 1.0 / (1.0 - abs * super_rate).clamp(0.01, 1.0);
+                200.0 * rc_rate * curved * super_factor
+            }
+            Rates::Actual {
+                center_dps,
+                max_dps,
+                expo,
+            } => {
+                let stick5 = stick * abs * abs * abs * abs;
+                let curved = abs * (stick5 * expo + stick * (1.0 - expo));
+                stick * center_dps + (max_dps - center_dps).max(0.0) * curved
+            }
+        };
+        dps.clamp(-RATE_LIMIT_DPS, RATE_LIMIT_DPS).to_radians()
+    }
+}
+
+fn rates_type(params: &Params) -> RatesType {
+    if params.rates_type as u8 == RatesType::Betaflight as u8 {
+        RatesType::Betaflight
+    } else {
+        RatesType::Actual
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dps(rates: &Rates, stick: f32) -> f32 {
+        rates.rate(stick).to_degrees()
+    }
+
+    #[test]
+    fn betaflight_matches_configurator_numbers() {
+        let rates = Rates::Betaflight {
+            rc_rate: 1.0,
+            super_rate: 0.7,
+            expo: 0.0,
+        };
+        assert_eq!(dps(&rates, 0.0), 0.0);
+        assert!((dps(&rates, 1.0) - 666.67).abs() < 0.1);
+        assert!((dps(&rates, -1.0) + 666.67).abs() < 0.1);
+
+        let linear = Rates::Betaflight {
+            rc_rate: 1.0,
+            super_rate: 0.0,
+            expo: 0.0,
+        };
+        assert!((dps(&linear, 0.5) - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn actual_hits_center_and_max() {
+        let rates = Rates::Actual {
+            center_dps: 70.0,
+            max_dps: 670.0,
+            expo: 0.54,
+        };
+        // Slope at center is the center sensitivity
+        assert!((dps(&rates, 0.001) / 0.001 - 70.0).abs() < 1.0);
+        assert!((dps(&rates, 1.0) - 670.0).abs() < 0.1);
+        assert!((dps(&rates, -1.0) + 670.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn curves_are_monotonic_and_limited() {
+        let steep = Rates::Betaflight {
+            rc_rate: 2.55,
+            super_rate: 0.99,
+            expo: 0.5,
+        };
+        let mut last = f32::MIN;
+        for i in -100..=100 {
+            let rate = dps(&steep, i as f32 / 100.0);
+            assert!(rate >= last);
+            last = rate;
+        }
+        assert_eq!(last, RATE_LIMIT_DPS);
+    }
+
+    #[test]
+    fn rates_type_picks_the_param_set() {
+        let mut params = Params::defaults();
+        params.rates_type = RatesType::Betaflight as u8 as f32;
+        assert!(matches!(Rates::yaw(&params), Rates::Betaflight { .. }));
+        params.rates_type = RatesType::Actual as u8 as f32;
+        assert!(matches!(Rates::roll_pitch(&params), Rates::Actual { .. }));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::consts::{ALT_KD_MAX, ALT_KD_MIN, ALT_KP_MAX, ALT_KP_MIN, RC_MAX, RC_MIN};
+
+#[derive(Clone)]
+pub struct RcData([u16; 16]);
+
+impl RcData {
+    pub const fn from_channels(channels: [u16; 16]) -> RcData {
+        RcData(channels)
+    }
+
+    pub fn roll(&self) -> f32 {
+        Self::normalize(self.0[0], RC_MIN, RC_MAX, -1.0, 1.0)
+    }
+
+    pub fn pitch(&self) -> f32 {
+        Self::normalize(self.0[1], RC_MIN, RC_MAX, -1.0, 1.0)
+    }
+
+    pub fn throttle(&self) -> f32 {
+        Self::normalize(self.0[2], RC_MIN, RC_MAX, 0.0, 1.0)
+    }
+
+    pub fn yaw(&self) -> f32 {
+        Self::normalize(self.0[3], RC_MIN, RC_MAX, -1.0, 1.0)
+    }
+
+    pub fn kp_gain(&self) -> f32 {
+        Self::normalize(self.0[4], RC_MIN, RC_MAX, ALT_KP_MIN, ALT_KP_MAX)
+    }
+
+    pub fn kd_gain(&self) -> f32 {
+        Self::normalize(self.0[5], RC_MIN, RC_MAX, ALT_KD_MIN, ALT_KD_MAX)
+    }
+
+    pub fn arm_switch(&self) -> f32 {
+        Self::normalize(self.0[6], RC_MIN, RC_MAX, 0.0, 1.0)
+    }
+
+    pub fn altitude_switch(&self) -> f32 {
+        Self::normalize(self.0[7], RC_MIN, RC_MAX, 0.0, 1.0)
+    }
+
+    pub fn acro_switch(&self) -> f32 {
+        Self::normalize(self.0[8], RC_MIN, RC_MAX, 0.0, 1.0)
+    }
+
+    pub fn horizon_switch(&self) -> f32 {
+        Self::normalize(self.0[9], RC_MIN, RC_MAX, 0.0, 1.0)
+    }
+
+    /// Stand-in sticks for a lost link: level, no yaw, given throttle, altitude hold off.
+    /// Arm switch and gains stay as they were.
+    pub fn failsafe(&self, throttle: f32) -> RcData {
+        let center = (RC_MIN + RC_MAX) / 2;
+        let mut channels = self.0;
+        channels[0] = center;
+        channels[1] = center;
+        channels[2] = RC_MIN + (throttle.clamp(0.0, 1.0) * (RC_MAX - RC_MIN) as f32) as u16;
+        channels[3] = center;
+        channels[7] = RC_MIN;
+        RcData(channels)
+    }
+
+    /// Pulse widths in microseconds, as other tools expect RC channels.
+    pub fn channels_us(&self) -> [u16; 16] {
+        self.0.map(|raw| raw * 5 / 8 + 880)
+    }
+
+    fn normalize(
+        val: u16,
+        original_min: u16,
+        original_max: u16,
+        new_min: f32,
+        new_max: f32,
+    ) -> f32 {
+        new_min
+            + ((new_max - new_min)
+                * ((val as f32 - original_min as f32) / (original_max - original_min) as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_channel(index: usize, value: u16) -> RcData {
+        let mut channels = [RC_MIN; 16];
+        channels[index] = value;
+        RcData::from_channels(channels)
+    }
+
+    #[test]
+    fn sticks_span_full_range() {
+        let min = RcData::from_channels([RC_MIN; 16]);
+        let max = RcData::from_channels([RC_MAX; 16]);
+        assert_eq!(min.roll(), -1.0);
+        assert_eq!(max.roll(), 1.0);
+        assert_eq!(min.throttle(), 0.0);
+        assert_eq!(max.throttle(), 1.0);
+        assert_eq!(min.kp_gain(), ALT_KP_MIN);
+        assert_eq!(max.kp_gain(), ALT_KP_MAX);
+        assert_eq!(min.kd_gain(), ALT_KD_MIN);
+        assert_eq!(max.kd_gain(), ALT_KD_MAX);
+    }
+
+    #[test]
+    fn center_stick_is_zero() {
+        let center = (RC_MIN + RC_MAX) / 2;
+        assert!(with_channel(0, center).roll().abs() < 1e-3);
+        assert!(with_channel(1, center).pitch().abs() < 1e-3);
+        assert!(with_channel(3, center).yaw().abs() < 1e-3);
+        assert!((with_channel(2, center).throttle() - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn channels_map_to_their_own_axis() {
+        let rc = with_channel(6, RC_MAX);
+        assert_eq!(rc.arm_switch(), 1.0);
+        assert_eq!(rc.altitude_switch(), 0.0);
+        assert_eq!(rc.throttle(), 0.0);
+    }
+
+    #[test]
+    fn pulse_widths_follow_sbus_scale() {
+        let us = with_channel(1, 992).channels_us();
+        assert_eq!(us[1], 1500);
+        assert_eq!(us[0], RC_MIN * 5 / 8 + 880);
+    }
+
+    #[test]
+    fn out_of_range_values_are_not_clamped() {
+        assert!(with_channel(2, 0).throttle() < 0.0);
+        assert!(with_channel(2, 2000).throttle() > 1.0);
+    }
+}
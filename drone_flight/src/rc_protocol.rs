@@ -0,0 +1,614 @@
+use crate::consts::RC_MIN;
+
+const SBUS_HEADER: u8 = 0x0F;
+const SBUS_FRAME_LEN: usize = 25;
+const SBUS_FAILSAFE: u8 = 1 << 3;
+
+const CRSF_ADDRESSES: [u8; 2] = [0xC8, 0xEE];
+const CRSF_MAX_FRAME_LEN: usize = 64;
+const CRSF_RC_CHANNELS: u8 = 0x16;
+
+const IBUS_LEN: u8 = 0x20;
+const IBUS_SERVO: u8 = 0x40;
+const IBUS_CHANNELS: usize = 14;
+
+const SRXL2_ID: u8 = 0xA6;
+const SRXL2_MAX_FRAME_LEN: usize = 80;
+const SRXL2_CONTROL: u8 = 0xCD;
+const SRXL2_CHANNEL_DATA: u8 = 0x00;
+const SRXL2_FAILSAFE_DATA: u8 = 0x01;
+
+// Longer gaps end a PPM frame, shorter or longer pulses than a channel spoil it
+const PPM_SYNC_US: u32 = 2700;
+const PPM_MIN_US: u32 = 750;
+const PPM_MAX_US: u32 = 2250;
+const PPM_MIN_CHANNELS: usize = 4;
+
+/// Receiver protocol, `rc_protocol` picks it at boot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Protocol {
+    /// Inverted 100 kbaud, 8E2.
+    Sbus = 0,
+    /// 420 kbaud, 8N1.
+    Crsf = 1,
+    /// FlySky, 115200 baud, 8N1.
+    Ibus = 2,
+    /// Spektrum, 115200 baud, 8N1, listening only.
+    Srxl2 = 3,
+    /// Pulse train on one pin, timed by PIO.
+    Ppm = 4,
+}
+
+impl Protocol {
+    pub fn from_param(value: f32) -> Protocol {
+        match value as u8 {
+            1 => Protocol::Crsf,
+            2 => Protocol::Ibus,
+            3 => Protocol::Srxl2,
+            4 => Protocol::Ppm,
+            _ => Protocol::Sbus,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Sbus => "SBUS",
+            Protocol::Crsf => "CRSF",
+            Protocol::Ibus => "iBUS",
+            Protocol::Srxl2 => "SRXL2",
+            Protocol::Ppm => "PPM",
+        }
+    }
+}
+
+/// Channels of one received frame on the SBUS scale `RcData` expects.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RcFrame {
+    pub channels: [u16; 16],
+    /// The receiver lost the transmitter and sends its failsafe positions.
+    pub failsafe: bool,
+}
+
+/// Turns what comes off the receiver into channel frames.
+pub trait RcProtocol {
+    /// A byte off the UART, or a pulse width in us for PPM.
+    type Sample: Copy;
+
+    /// Returns a frame once one is complete and checks out.
+    fn push(&mut self, sample: Self::Sample) -> Option<RcFrame>;
+}
+
+/// Pulse width to the SBUS scale, inverse of `RcData::channels_us`.
+fn us_to_channel(us: u32) -> u16 {
+    (us.clamp(880, 2159) - 880) as u16 * 8 / 5
+}
+
+/// 16 channels of 11 bits, least significant bit first, as SBUS and CRSF pack them.
+fn unpack_11bit(data: &[u8]) -> [u16; 16] {
+    let mut channels = [0; 16];
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut index = 0;
+    for &byte in data {
+        bits |= (byte as u32) << count;
+        count += 8;
+        if count >= 11 && index < channels.len() {
+            channels[index] = (bits & 0x7FF) as u16;
+            index += 1;
+            bits >>= 11;
+            count -= 11;
+        }
+    }
+    channels
+}
+
+/// CRC-8/DVB-S2 over the CRSF type and payload.
+fn crc8_dvb_s2(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0xD5
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// CRC-16/XMODEM over the whole SRXL2 packet before the CRC.
+fn crc16_xmodem(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+enum Check {
+    /// Needs more bytes.
+    Incomplete,
+    /// Not a frame from the first byte on.
+    Bad,
+    /// A valid frame of this many bytes, with channels or something else.
+    Frame(usize, Option<RcFrame>),
+}
+
+/// Receive buffer that finds its way back to frame starts after garbage or a
+/// connection mid-frame.
+struct Buffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Buffer<N> {
+    const fn new() -> Self {
+        Buffer {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    fn push(
+        &mut self,
+        byte: u8,
+        is_start: fn(u8) -> bool,
+        mut check: impl FnMut(&[u8]) -> Check,
+    ) -> Option<RcFrame> {
+        if self.len == 0 && !is_start(byte) {
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+
+        loop {
+            match check(&self.bytes[..self.len]) {
+                Check::Incomplete if self.len < N => return None,
+                Check::Incomplete | Check::Bad => {
+                    // Start over at the next byte that could begin a frame
+                    let next = self.bytes[1..self.len]
+                        .iter()
+                        .position(|&b| is_start(b))
+                        .map_or(self.len, |i| i + 1);
+                    self.consume(next);
+                }
+                Check::Frame(len, frame) => {
+                    self.consume(len);
+                    if frame.is_some() {
+                        return frame;
+                    }
+                }
+            }
+            if self.len == 0 {
+                return None;
+            }
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.bytes.copy_within(len..self.len, 0);
+        self.len -= len;
+    }
+}
+
+/// Futaba SBUS, also SBUS2 footers.
+pub struct Sbus {
+    buffer: Buffer<SBUS_FRAME_LEN>,
+}
+
+impl Sbus {
+    pub const fn new() -> Sbus {
+        Sbus {
+            buffer: Buffer::new(),
+        }
+    }
+
+    fn check(bytes: &[u8]) -> Check {
+        if bytes.len() < SBUS_FRAME_LEN {
+            return Check::Incomplete;
+        }
+        let footer = bytes[24];
+        if footer != 0x00 && footer & 0x0F != 0x04 {
+            return Check::Bad;
+        }
+        let frame = RcFrame {
+            channels: unpack_11bit(&bytes[1..23]),
+            failsafe: bytes[23] & SBUS_FAILSAFE != 0,
+        };
+        Check::Frame(SBUS_FRAME_LEN, Some(frame))
+    }
+}
+
+impl Default for Sbus {
+    fn default() -> Sbus {
+        Sbus::new()
+    }
+}
+
+impl RcProtocol for Sbus {
+    type Sample = u8;
+
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        self.buffer.push(byte, |b| b == SBUS_HEADER, Sbus::check)
+    }
+}
+
+/// TBS Crossfire and ExpressLRS, RC_CHANNELS_PACKED frames. Link statistics and
+/// other frames pass by unused.
+pub struct Crsf {
+    buffer: Buffer<CRSF_MAX_FRAME_LEN>,
+}
+
+impl Crsf {
+    pub const fn new() -> Crsf {
+        Crsf {
+            buffer: Buffer::new(),
+        }
+    }
+
+    fn check(bytes: &[u8]) -> Check {
+        let Some(&len) = bytes.get(1) else {
+            return Check::Incomplete;
+        };
+        // Length counts type, payload and CRC
+        let len = len as usize;
+        if !(2..=CRSF_MAX_FRAME_LEN - 2).contains(&len) {
+            return Check::Bad;
+        }
+        let total = len + 2;
+        if bytes.len() < total {
+            return Check::Incomplete;
+        }
+        if crc8_dvb_s2(&bytes[2..total - 1]) != bytes[total - 1] {
+            return Check::Bad;
+        }
+        let frame = (bytes[2] == CRSF_RC_CHANNELS && len == 24).then(|| RcFrame {
+            channels: unpack_11bit(&bytes[3..25]),
+            failsafe: false,
+        });
+        Check::Frame(total, frame)
+    }
+}
+
+impl Default for Crsf {
+    fn default() -> Crsf {
+        Crsf::new()
+    }
+}
+
+impl RcProtocol for Crsf {
+    type Sample = u8;
+
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        self.buffer
+            .push(byte, |b| CRSF_ADDRESSES.contains(&b), Crsf::check)
+    }
+}
+
+/// FlySky iBUS servo frames, 14 channels in us.
+pub struct Ibus {
+    buffer: Buffer<{ IBUS_LEN as usize }>,
+}
+
+impl Ibus {
+    pub const fn new() -> Ibus {
+        Ibus {
+            buffer: Buffer::new(),
+        }
+    }
+
+    fn check(bytes: &[u8]) -> Check {
+        if bytes.get(1).is_some_and(|&command| command != IBUS_SERVO) {
+            return Check::Bad;
+        }
+        if bytes.len() < IBUS_LEN as usize {
+            return Check::Incomplete;
+        }
+        let sum = bytes[..30].iter().map(|&b| b as u16).sum::<u16>();
+        if 0xFFFF - sum != u16::from_le_bytes([bytes[30], bytes[31]]) {
+            return Check::Bad;
+        }
+        let mut channels = [RC_MIN; 16];
+        let values = bytes[2..2 + IBUS_CHANNELS * 2].chunks_exact(2);
+        for (channel, raw) in channels.iter_mut().zip(values) {
+            // The top nibble carries extra channels on some receivers
+            let us = u16::from_le_bytes([raw[0], raw[1]]) & 0x0FFF;
+            *channel = us_to_channel(us as u32);
+        }
+        let frame = RcFrame {
+            channels,
+            failsafe: false,
+        };
+        Check::Frame(IBUS_LEN as usize, Some(frame))
+    }
+}
+
+impl Default for Ibus {
+    fn default() -> Ibus {
+        Ibus::new()
+    }
+}
+
+impl RcProtocol for Ibus {
+    type Sample = u8;
+
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        self.buffer.push(byte, |b| b == IBUS_LEN, Ibus::check)
+    }
+}
+
+/// Spektrum SRXL2 control packets. Only listens, so receivers that wait for the
+/// flight controller to answer their handshake never send channels.
+pub struct Srxl2 {
+    buffer: Buffer<SRXL2_MAX_FRAME_LEN>,
+    /// Packets only carry the channels that changed.
+    channels: [u16; 16],
+}
+
+impl Srxl2 {
+    pub const fn new() -> Srxl2 {
+        Srxl2 {
+            buffer: Buffer::new(),
+            channels: [RC_MIN; 16],
+        }
+    }
+
+    fn check(bytes: &[u8]) -> Check {
+        let Some(&len) = bytes.get(2) else {
+            return Check::Incomplete;
+        };
+        let len = len as usize;
+        if !(5..=SRXL2_MAX_FRAME_LEN).contains(&len) {
+            return Check::Bad;
+        }
+        if bytes.len() < len {
+            return Check::Incomplete;
+        }
+        let crc = u16::from_be_bytes([bytes[len - 2], bytes[len - 1]]);
+        if crc16_xmodem(&bytes[..len - 2]) != crc {
+            return Check::Bad;
+        }
+        // The channels are filled in by `push`, which keeps the previous values
+        let control = bytes[1] == SRXL2_CONTROL
+            && len >= 14
+            && matches!(bytes[3], SRXL2_CHANNEL_DATA | SRXL2_FAILSAFE_DATA);
+        let frame = control.then(|| RcFrame {
+            channels: [0; 16],
+            failsafe: bytes[3] == SRXL2_FAILSAFE_DATA,
+        });
+        Check::Frame(len, frame)
+    }
+
+    /// Command, reply id, RSSI, frame losses, channel mask, then a value per mask bit.
+    fn update_channels(channels: &mut [u16; 16], packet: &[u8]) {
+        let mask = u32::from_le_bytes([packet[8], packet[9], packet[10], packet[11]]);
+        let mut values = packet[12..packet.len() - 2].chunks_exact(2);
+        for (index, channel) in channels.iter_mut().enumerate() {
+            if mask & 1 << index == 0 {
+                continue;
+            }
+            let Some(raw) = values.next() else {
+                break;
+            };
+            // 0 to 65535 across 1000 to 2000 us
+            let value = u16::from_le_bytes([raw[0], raw[1]]) as u32 >> 5;
+            *channel = us_to_channel(1000 + value * 1000 / 2048);
+        }
+    }
+}
+
+impl Default for Srxl2 {
+    fn default() -> Srxl2 {
+        Srxl2::new()
+    }
+}
+
+impl RcProtocol for Srxl2 {
+    type Sample = u8;
+
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        let channels = &mut self.channels;
+        let mut frame = self.buffer.push(
+            byte,
+            |b| b == SRXL2_ID,
+            |bytes| {
+                let check = Srxl2::check(bytes);
+                if let Check::Frame(len, Some(_)) = &check {
+                    Srxl2::update_channels(channels, &bytes[..*len]);
+                }
+                check
+            },
+        )?;
+        frame.channels = self.channels;
+        Some(frame)
+    }
+}
+
+/// PPM pulse train, the time between the starts of two pulses carries a channel and
+/// a long gap ends the frame.
+pub struct Ppm {
+    channels: [u16; 16],
+    count: usize,
+    /// Since the last gap, with every pulse in range.
+    valid: bool,
+}
+
+impl Ppm {
+    pub const fn new() -> Ppm {
+        Ppm {
+            channels: [RC_MIN; 16],
+            count: 0,
+            valid: false,
+        }
+    }
+}
+
+impl Default for Ppm {
+    fn default() -> Ppm {
+        Ppm::new()
+    }
+}
+
+impl RcProtocol for Ppm {
+    type Sample = u32;
+
+    fn push(&mut self, us: u32) -> Option<RcFrame> {
+        if us >= PPM_SYNC_US {
+            let complete = self.valid && self.count >= PPM_MIN_CHANNELS;
+            let count = self.count;
+            self.count = 0;
+            self.valid = true;
+            if !complete {
+                return None;
+            }
+            let mut channels = self.channels;
+            channels[count..].fill(RC_MIN);
+            return Some(RcFrame {
+                channels,
+                failsafe: false,
+            });
+        }
+        if (PPM_MIN_US..=PPM_MAX_US).contains(&us) && self.count < self.channels.len() {
+            self.channels[self.count] = us_to_channel(us);
+            self.count += 1;
+        } else {
+            self.valid = false;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Garbage with a false header, a frame, then one in failsafe
+    const SBUS_STREAM: &[u8] = &[
+        0x3C, 0x0F, 0x12, 0x0F, 0xAC, 0x98, 0x38, 0xF8, 0xC0, 0xC7, 0x8A, 0x89, 0x4F, 0x9C, 0x15,
+        0xE0, 0x03, 0x1F, 0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0x00, 0x00, 0x0F, 0xE0,
+        0x03, 0x1F, 0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xE0, 0x03, 0x1F, 0xF8, 0xC0,
+        0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0x0C, 0x00,
+    ];
+    // Noise, link statistics, RC channels with a flipped bit, then intact
+    const CRSF_STREAM: &[u8] = &[
+        0x00, 0xEE, 0xC8, 0x0C, 0x14, 0x64, 0x00, 0x64, 0x02, 0x05, 0x00, 0x01, 0x50, 0x0A, 0x08,
+        0x0B, 0xC8, 0x18, 0x16, 0xAC, 0x98, 0x38, 0xF8, 0xC0, 0xC7, 0x8A, 0xC9, 0x4F, 0x9C, 0x15,
+        0xE0, 0x03, 0x1F, 0xF8, 0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xDC, 0xC8, 0x18, 0x16,
+        0xAC, 0x98, 0x38, 0xF8, 0xC0, 0xC7, 0x8A, 0x89, 0x4F, 0x9C, 0x15, 0xE0, 0x03, 0x1F, 0xF8,
+        0xC0, 0x07, 0x3E, 0xF0, 0x81, 0x0F, 0x7C, 0xDC,
+    ];
+    // Tail of a previous frame, then a servo frame
+    const IBUS_STREAM: &[u8] = &[
+        0x40, 0x05, 0x20, 0x40, 0xE8, 0x03, 0xD0, 0x07, 0xDC, 0x05, 0xDC, 0x05, 0xE8, 0x03, 0xD0,
+        0x07, 0xD0, 0x07, 0xE8, 0x03, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05,
+        0xDC, 0x05, 0x51, 0xF3,
+    ];
+    // Handshake, all four sticks, throttle only, then failsafe positions
+    const SRXL2_STREAM: &[u8] = &[
+        0xA6, 0x21, 0x0E, 0x21, 0x10, 0x0A, 0x01, 0x00, 0x12, 0x34, 0x56, 0x78, 0xF7, 0x51, 0xA6,
+        0xCD, 0x16, 0x00, 0x00, 0xF0, 0x02, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF,
+        0x00, 0x80, 0x00, 0x80, 0x4A, 0x09, 0xA6, 0xCD, 0x10, 0x00, 0x00, 0xF0, 0x02, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0xFF, 0xFF, 0xD2, 0x03, 0xA6, 0xCD, 0x16, 0x01, 0x00, 0xF0, 0x02, 0x00,
+        0x0F, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x80, 0x00, 0x00, 0x00, 0x80, 0xE6, 0x88,
+    ];
+
+    const STICKS: [u16; 16] = [
+        172, 1811, 992, 992, 172, 1811, 1811, 172, 992, 992, 992, 992, 992, 992, 992, 992,
+    ];
+
+    fn frames<P: RcProtocol<Sample = u8>>(mut parser: P, stream: &[u8]) -> Vec<RcFrame> {
+        stream.iter().filter_map(|&b| parser.push(b)).collect()
+    }
+
+    #[test]
+    fn sbus_finds_frames_after_garbage() {
+        let frames = frames(Sbus::new(), SBUS_STREAM);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].channels, STICKS);
+        assert!(!frames[0].failsafe);
+        assert_eq!(frames[1].channels, [992; 16]);
+        assert!(frames[1].failsafe);
+    }
+
+    #[test]
+    fn crsf_skips_other_frames_and_bad_crc() {
+        let frames = frames(Crsf::new(), CRSF_STREAM);
+        assert_eq!(
+            frames,
+            [RcFrame {
+                channels: STICKS,
+                failsafe: false
+            }]
+        );
+    }
+
+    #[test]
+    fn ibus_converts_pulse_widths() {
+        let received = frames(Ibus::new(), IBUS_STREAM);
+        assert_eq!(received.len(), 1);
+        let channels = received[0].channels;
+        assert_eq!(channels[..4], [192, 1792, 992, 992]);
+        assert_eq!(channels[14..], [RC_MIN; 2]);
+
+        // Any byte off fails the checksum
+        let mut corrupt = IBUS_STREAM.to_vec();
+        corrupt[10] ^= 1;
+        assert!(frames(Ibus::new(), &corrupt).is_empty());
+    }
+
+    #[test]
+    fn srxl2_keeps_channels_packets_leave_out() {
+        let frames = frames(Srxl2::new(), SRXL2_STREAM);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].channels[..5], [192, 1790, 992, 992, RC_MIN]);
+        assert_eq!(frames[1].channels[..5], [192, 1790, 1790, 992, RC_MIN]);
+        assert!(!frames[1].failsafe);
+        assert_eq!(frames[2].channels[..4], [992, 992, 192, 992]);
+        assert!(frames[2].failsafe);
+    }
+
+    #[test]
+    fn ppm_needs_a_clean_frame_between_gaps() {
+        let mut ppm = Ppm::new();
+        let mut push = |pulses: &[u32]| -> Vec<RcFrame> {
+            pulses.iter().filter_map(|&us| ppm.push(us)).collect()
+        };
+
+        // Joined mid-frame
+        assert!(push(&[1500, 1500, 6000]).is_empty());
+
+        let frames = push(&[1000, 2000, 1500, 1500, 1000, 2000, 1000, 1000, 8000]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            frames[0].channels[..8],
+            [192, 1792, 992, 992, 192, 1792, 192, 192]
+        );
+        assert_eq!(frames[0].channels[8..], [RC_MIN; 8]);
+
+        // A glitch spoils the whole frame
+        assert!(push(&[1500, 300, 1500, 1500, 1500, 8000]).is_empty());
+        // Too few channels
+        assert!(push(&[1500, 1500, 1500, 8000]).is_empty());
+        assert_eq!(push(&[1500, 1500, 1500, 1500, 8000]).len(), 1);
+    }
+
+    #[test]
+    fn pulse_widths_survive_rc_data() {
+        for us in [1000, 1234, 1500, 2000] {
+            let channel = us_to_channel(us);
+            let rc = crate::rc::RcData::from_channels([channel; 16]);
+            assert!(us - rc.channels_us()[0] as u32 <= 1);
+        }
+    }
+
+    #[test]
+    fn unknown_protocol_falls_back_to_sbus() {
+        assert_eq!(Protocol::from_param(1.0), Protocol::Crsf);
+        assert_eq!(Protocol::from_param(4.0), Protocol::Ppm);
+        assert_eq!(Protocol::from_param(9.0), Protocol::Sbus);
+    }
+}
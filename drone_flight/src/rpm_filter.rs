@@ -0,0 +1,136 @@
+use crate::esc::Rpm;
+use crate::filter::{Biquad, BiquadCoeffs};
+use crate::mixer::MAX_MOTORS;
+use crate::params::Params;
+use nalgebra::Vector3;
+
+pub const RPM_MAX_HARMONICS: usize = 3;
+const NOTCHES: usize = MAX_MOTORS * RPM_MAX_HARMONICS;
+// Notches too close to Nyquist turn into wide cuts
+const MAX_NYQUIST_SHARE: f32 = 0.95;
+
+/// Gyro notches that follow each motor's rotation frequency and its harmonics, from
+/// the RPM the ESCs report over bidirectional DShot.
+pub struct RpmFilter {
+    harmonics: usize,
+    q: f32,
+    min_hz: f32,
+    sample_hz: f32,
+    /// `None` while the motor is stopped, silent or the tone out of range.
+    coeffs: [Option<BiquadCoeffs>; NOTCHES],
+    axes: [[Biquad; 3]; NOTCHES],
+}
+
+impl RpmFilter {
+    pub fn new(params: &Params, sample_hz: f32) -> RpmFilter {
+        RpmFilter {
+            harmonics: (params.rpm_harmonics as usize).min(RPM_MAX_HARMONICS),
+            q: params.rpm_notch_q,
+            min_hz: params.rpm_min_hz,
+            sample_hz,
+            coeffs: [None; NOTCHES],
+            axes: [[Biquad::default(); 3]; NOTCHES],
+        }
+    }
+
+    /// Moves the notches onto the latest motor RPM.
+    pub fn update(&mut self, rpm: &Rpm) {
+        let max_hz = self.sample_hz / 2.0 * MAX_NYQUIST_SHARE;
+        for (motor, rpm) in rpm.iter().enumerate() {
+            for harmonic in 0..RPM_MAX_HARMONICS {
+                let notch = motor * RPM_MAX_HARMONICS + harmonic;
+                let hz = rpm.map(|rpm| rpm / 60.0 * (harmonic + 1) as f32);
+                let coeffs = match hz {
+                    Some(hz)
+                        if harmonic < self.harmonics && (self.min_hz..max_hz).contains(&hz) =>
+                    {
+                        Some(BiquadCoeffs::notch(hz, self.q, self.sample_hz))
+                    }
+                    _ => None,
+                };
+                // A notch coming back starts from rest, not from where it left off
+                if self.coeffs[notch].is_none() && coeffs.is_some() {
+                    self.axes[notch] = [Biquad::default(); 3];
+                }
+                self.coeffs[notch] = coeffs;
+            }
+        }
+    }
+
+    /// Whether any notch is following a motor.
+    pub fn is_active(&self) -> bool {
+        self.coeffs.iter().any(Option::is_some)
+    }
+
+    pub fn apply(&mut self, gyro: Vector3<f32>) -> Vector3<f32> {
+        let mut out = gyro;
+        for (coeffs, axes) in self.coeffs.iter().zip(self.axes.iter_mut()) {
+            if let Some(coeffs) = coeffs {
+                for (value, axis) in out.iter_mut().zip(axes.iter_mut()) {
+                    *value = axis.apply(coeffs, *value);
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::gain;
+
+    const SAMPLE_HZ: f32 = 1000.0;
+
+    fn rpm_filter(harmonics: f32) -> RpmFilter {
+        let params = Params {
+            rpm_harmonics: harmonics,
+            ..Params::defaults()
+        };
+        RpmFilter::new(&params, SAMPLE_HZ)
+    }
+
+    fn quad(rpm: f32) -> Rpm {
+        [Some(rpm), Some(rpm), Some(rpm), Some(rpm), None, None]
+    }
+
+    fn roll_gain(filter: &mut RpmFilter, hz: f32) -> f32 {
+        gain(hz, SAMPLE_HZ, 2000, |x| {
+            filter.apply(Vector3::new(x, 0.0, 0.0)).x
+        })
+    }
+
+    #[test]
+    fn notches_follow_motor_rpm() {
+        let mut filter = rpm_filter(2.0);
+        filter.update(&quad(12_000.0));
+        // 200 Hz fundamental and its 400 Hz harmonic
+        assert!(roll_gain(&mut filter, 200.0) < 0.01);
+        assert!(roll_gain(&mut filter, 400.0) < 0.01);
+        assert!(roll_gain(&mut filter, 20.0) > 0.95);
+
+        filter.update(&quad(9_000.0));
+        assert!(roll_gain(&mut filter, 150.0) < 0.01);
+    }
+
+    #[test]
+    fn harmonics_above_the_count_or_nyquist_are_left_alone() {
+        let mut filter = rpm_filter(1.0);
+        filter.update(&quad(12_000.0));
+        assert!(roll_gain(&mut filter, 400.0) > 0.9);
+
+        let mut filter = rpm_filter(3.0);
+        filter.update(&quad(12_000.0));
+        assert!(filter.coeffs[1].is_some());
+        assert!(filter.coeffs[2].is_none()); // 600 Hz
+    }
+
+    #[test]
+    fn stopped_or_silent_motors_pass_through() {
+        let mut filter = rpm_filter(3.0);
+        filter.update(&[Some(0.0), None, Some(1_000.0), None, None, None]);
+        assert!(!filter.is_active());
+        let gyro = Vector3::new(0.1, -0.2, 0.3);
+        assert_eq!(filter.apply(gyro), gyro);
+    }
+}
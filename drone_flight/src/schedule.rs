@@ -0,0 +1,157 @@
+use crate::params::Params;
+
+/// Multipliers on a PID's gains.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GainScale {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+}
+
+impl GainScale {
+    pub const ONE: GainScale = GainScale {
+        p: 1.0,
+        i: 1.0,
+        d: 1.0,
+    };
+
+    /// Both scales applied at once, for several schedules on one PID.
+    pub fn combine(self, other: GainScale) -> GainScale {
+        GainScale {
+            p: self.p * other.p,
+            i: self.i * other.i,
+            d: self.d * other.d,
+        }
+    }
+}
+
+/// Gains as a function of one flight variable, throttle, battery voltage or airspeed.
+pub trait GainSchedule {
+    fn scale(&self, input: f32) -> GainScale;
+}
+
+/// Throttle PID attenuation: full gains up to `breakpoint` throttle, falling to
+/// `1 - rate` at full throttle along `t^curve`. P and D always, I with `include_i`.
+#[derive(Copy, Clone, Debug)]
+pub struct Tpa {
+    breakpoint: f32,
+    rate: f32,
+    curve: f32,
+    include_i: bool,
+}
+
+impl Tpa {
+    pub fn new(params: &Params) -> Tpa {
+        Tpa {
+            breakpoint: params.tpa_breakpoint,
+            rate: params.tpa_rate,
+            curve: params.tpa_curve,
+            include_i: params.tpa_mode != 0.0,
+        }
+    }
+}
+
+impl GainSchedule for Tpa {
+    /// `input` is throttle in 0..1 of full.
+    fn scale(&self, input: f32) -> GainScale {
+        if input <= self.breakpoint || self.breakpoint >= 1.0 {
+            return GainScale::ONE;
+        }
+        let t = ((input - self.breakpoint) / (1.0 - self.breakpoint)).min(1.0);
+        let factor = 1.0 - self.rate * libm::powf(t, self.curve);
+        GainScale {
+            p: factor,
+            i: if self.include_i { factor } else { 1.0 },
+            d: factor,
+        }
+    }
+}
+
+/// Scales linearly interpolated between `N` points sorted by input, held flat
+/// outside them.
+#[derive(Copy, Clone, Debug)]
+pub struct Lookup<const N: usize> {
+    pub points: [(f32, GainScale); N],
+}
+
+impl<const N: usize> GainSchedule for Lookup<N> {
+    fn scale(&self, input: f32) -> GainScale {
+        let Some(upper) = self.points.iter().position(|(x, _)| *x > input) else {
+            return self.points.last().map_or(GainScale::ONE, |(_, s)| *s);
+        };
+        if upper == 0 {
+            return self.points[0].1;
+        }
+        let ((x0, a), (x1, b)) = (self.points[upper - 1], self.points[upper]);
+        let t = (input - x0) / (x1 - x0);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        GainScale {
+            p: lerp(a.p, b.p),
+            i: lerp(a.i, b.i),
+            d: lerp(a.d, b.d),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tpa(mode: f32, curve: f32) -> Tpa {
+        Tpa::new(&Params {
+            tpa_breakpoint: 0.5,
+            tpa_rate: 0.6,
+            tpa_curve: curve,
+            tpa_mode: mode,
+            ..Params::defaults()
+        })
+    }
+
+    #[test]
+    fn tpa_is_off_by_default() {
+        let tpa = Tpa::new(&Params::defaults());
+        for step in 0..=20 {
+            assert_eq!(tpa.scale(step as f32 / 10.0), GainScale::ONE);
+        }
+    }
+
+    #[test]
+    fn tpa_attenuates_above_the_breakpoint() {
+        let tpa = tpa(0.0, 1.0);
+        assert_eq!(tpa.scale(0.2), GainScale::ONE);
+        assert_eq!(tpa.scale(0.5), GainScale::ONE);
+        let half = tpa.scale(0.75);
+        assert!((half.p - 0.7).abs() < 1e-6);
+        assert_eq!(half.p, half.d);
+        assert_eq!(half.i, 1.0);
+        let full = tpa.scale(1.0);
+        assert!((full.p - 0.4).abs() < 1e-6);
+        assert_eq!(tpa.scale(1.5), full);
+    }
+
+    #[test]
+    fn tpa_curve_and_iterm() {
+        let curved = tpa(1.0, 2.0).scale(0.75);
+        assert!((curved.p - 0.85).abs() < 1e-6);
+        assert_eq!(curved.i, curved.p);
+    }
+
+    #[test]
+    fn lookup_interpolates_and_holds_ends() {
+        let half = GainScale {
+            p: 0.5,
+            i: 1.0,
+            d: 0.5,
+        };
+        // More gain on a sagging pack
+        let lookup = Lookup {
+            points: [(3.4, GainScale::ONE), (4.2, half)],
+        };
+        assert_eq!(lookup.scale(3.0), GainScale::ONE);
+        assert_eq!(lookup.scale(4.5), half);
+        let mid = lookup.scale(3.8);
+        assert!((mid.p - 0.75).abs() < 1e-6);
+        assert_eq!(mid.i, 1.0);
+        assert_eq!(mid.combine(half).p, mid.p * 0.5);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::rc::RcData;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use portable_atomic::{AtomicBool, Ordering};
+
+pub trait SwitchingPolicy {
+    type SafetyContext;
+
+    fn want_on(rc: &RcData) -> bool;
+    fn want_off(rc: &RcData) -> bool;
+    fn force_off(rc: &RcData, ctx: Self::SafetyContext) -> bool;
+
+    /// Position of the switch channel alone, without the other conditions of `want_on`.
+    fn switch_on(rc: &RcData) -> bool {
+        Self::want_on(rc)
+    }
+
+    /// Conditions besides the sticks that have to hold to switch on, e.g. pre-arm checks.
+    /// Asking for on while they fail needs the switch lowered once before it counts again.
+    fn may_on(_ctx: &Self::SafetyContext) -> bool {
+        true
+    }
+
+    const ON_TICKS: u64;
+    const OFF_TICKS: u64;
+
+    const NAME: &'static str;
+
+    const ON_SIGNAL: Option<&'static Signal<CriticalSectionRawMutex, ()>> = None;
+    const OFF_SIGNAL: Option<&'static Signal<CriticalSectionRawMutex, ()>> = None;
+
+    const ACTIVE_FLAG: Option<&'static AtomicBool> = None;
+}
+use core::marker::PhantomData;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SwitchState {
+    Inactive,
+    Active,
+}
+
+pub struct Switch<P: SwitchingPolicy> {
+    state: SwitchState,
+    ticks: u64,
+    held_off: bool,
+    _policy: PhantomData<P>,
+}
+
+impl<P: SwitchingPolicy> Switch<P> {
+    pub const fn new() -> Self {
+        Self {
+            state: SwitchState::Inactive,
+            ticks: 0,
+            held_off: false,
+            _policy: PhantomData,
+        }
+    }
+
+    pub fn update(&mut self, rc: &RcData, ctx: P::SafetyContext) -> SwitchState {
+        let may_on = P::may_on(&ctx);
+        if P::force_off(rc, ctx) {
+            self.ticks = 0;
+            if self.state == SwitchState::Active {
+                self.transition_to(SwitchState::Inactive, true);
+            }
+            return self.state;
+        }
+
+        if !P::switch_on(rc) {
+            self.held_off = false;
+        } else if P::want_on(rc) && !may_on && self.state == SwitchState::Inactive {
+            self.held_off = true;
+        }
+
+        let target_condition = match self.state {
+            SwitchState::Inactive => P::want_on(rc) && !self.held_off,
+            SwitchState::Active => P::want_off(rc),
+        };
+
+        if target_condition {
+            self.ticks += 1;
+            let threshold = match self.state {
+                SwitchState::Inactive => P::ON_TICKS,
+                SwitchState::Active => P::OFF_TICKS,
+            };
+
+            if self.ticks >= threshold {
+                let next_state = match self.state {
+                    SwitchState::Inactive => SwitchState::Active,
+                    SwitchState::Active => SwitchState::Inactive,
+                };
+                self.transition_to(next_state, false);
+            }
+        } else {
+            self.ticks = 0;
+        }
+
+        self.state
+    }
+
+    /// Switches right away on an external request, skipping the hold time but not the checks.
+    /// After a commanded off, the switch has to be lowered once before it can go on again.
+    /// Returns whether the switch ended up in the requested state.
+    pub fn command(&mut self, on: bool, rc: &RcData, ctx: P::SafetyContext) -> bool {
+        match (on, self.state) {
+            (true, SwitchState::Active) | (false, SwitchState::Inactive) => true,
+            (true, SwitchState::Inactive) => {
+                if self.held_off || !P::may_on(&ctx) || P::force_off(rc, ctx) || !P::want_on(rc) {
+                    return false;
+                }
+                self.transition_to(SwitchState::Active, false);
+                true
+            }
+            (false, SwitchState::Active) => {
+                self.transition_to(SwitchState::Inactive, false);
+                self.held_off = true;
+                true
+            }
+        }
+    }
+
+    fn transition_to(&mut self, next_state: SwitchState, forced: bool) {
+        self.state = next_state;
+        self.ticks = 0;
+
+        if let Some(flag) = P::ACTIVE_FLAG {
+            flag.store(next_state == SwitchState::Active, Ordering::Relaxed);
+        }
+
+        match next_state {
+            SwitchState::Active => {
+                log::info!("[MODE] {} ENABLED", P::NAME);
+                if let Some(signal) = P::ON_SIGNAL {
+                    signal.signal(());
+                }
+            }
+            SwitchState::Inactive => {
+                if forced {
+                    log::warn!("[MODE] {} FORCE DISENGAGED (Failsafe)", P::NAME);
+                } else {
+                    log::info!("[MODE] {} DISABLED", P::NAME);
+                }
+                if let Some(signal) = P::OFF_SIGNAL {
+                    signal.signal(());
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn state(&self) -> SwitchState {
+        self.state
+    }
+}
+
+impl<P: SwitchingPolicy> Default for Switch<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arming::{Arming, ArmingContext},
+        consts::{ARM_HOLD_TICKS, DISARM_HOLD_TICKS, RC_MAX, RC_MIN},
+    };
+
+    struct Momentary;
+
+    impl SwitchingPolicy for Momentary {
+        type SafetyContext = bool;
+
+        const NAME: &'static str = "TEST";
+        const ON_TICKS: u64 = 3;
+        const OFF_TICKS: u64 = 2;
+
+        fn want_on(rc: &RcData) -> bool {
+            rc.arm_switch() > 0.5
+        }
+
+        fn want_off(rc: &RcData) -> bool {
+            rc.arm_switch() < 0.5
+        }
+
+        fn force_off(_: &RcData, kill: bool) -> bool {
+            kill
+        }
+    }
+
+    const OK: ArmingContext = ArmingContext {
+        rc_valid: true,
+        prearm_failures: 0,
+    };
+    const RC_LOST: ArmingContext = ArmingContext {
+        rc_valid: false,
+        prearm_failures: 0,
+    };
+
+    fn rc(arm: bool, throttle: f32) -> RcData {
+        let mut channels = [RC_MIN; 16];
+        channels[2] = RC_MIN + (throttle * (RC_MAX - RC_MIN) as f32) as u16;
+        channels[6] = if arm { RC_MAX } else { RC_MIN };
+        RcData::from_channels(channels)
+    }
+
+    #[test]
+    fn switches_on_after_on_ticks() {
+        let mut switch = Switch::<Momentary>::new();
+        let on = rc(true, 0.0);
+        assert_eq!(switch.update(&on, false), SwitchState::Inactive);
+        assert_eq!(switch.update(&on, false), SwitchState::Inactive);
+        assert_eq!(switch.update(&on, false), SwitchState::Active);
+    }
+
+    #[test]
+    fn released_request_resets_debounce() {
+        let mut switch = Switch::<Momentary>::new();
+        let on = rc(true, 0.0);
+        let off = rc(false, 0.0);
+        switch.update(&on, false);
+        switch.update(&on, false);
+        switch.update(&off, false);
+        switch.update(&on, false);
+        assert_eq!(switch.update(&on, false), SwitchState::Inactive);
+        assert_eq!(switch.update(&on, false), SwitchState::Active);
+    }
+
+    #[test]
+    fn switches_off_after_off_ticks() {
+        let mut switch = Switch::<Momentary>::new();
+        let on = rc(true, 0.0);
+        let off = rc(false, 0.0);
+        for _ in 0..3 {
+            switch.update(&on, false);
+        }
+        assert_eq!(switch.update(&off, false), SwitchState::Active);
+        assert_eq!(switch.update(&off, false), SwitchState::Inactive);
+    }
+
+    #[test]
+    fn force_off_is_immediate() {
+        let mut switch = Switch::<Momentary>::new();
+        let on = rc(true, 0.0);
+        for _ in 0..3 {
+            switch.update(&on, false);
+        }
+        assert_eq!(switch.update(&on, true), SwitchState::Inactive);
+        assert_eq!(switch.update(&on, false), SwitchState::Inactive);
+    }
+
+    #[test]
+    fn command_skips_hold_but_not_checks() {
+        let mut switch = Switch::<Momentary>::new();
+        assert!(!switch.command(true, &rc(false, 0.0), false));
+        assert!(!switch.command(true, &rc(true, 0.0), true));
+        assert!(switch.command(true, &rc(true, 0.0), false));
+        assert_eq!(switch.state(), SwitchState::Active);
+    }
+
+    #[test]
+    fn commanded_off_holds_until_released() {
+        let mut switch = Switch::<Momentary>::new();
+        let on = rc(true, 0.0);
+        assert!(switch.command(true, &on, false));
+        assert!(switch.command(false, &on, false));
+        for _ in 0..10 {
+            assert_eq!(switch.update(&on, false), SwitchState::Inactive);
+        }
+        assert!(!switch.command(true, &on, false));
+
+        switch.update(&rc(false, 0.0), false);
+        for _ in 0..3 {
+            switch.update(&on, false);
+        }
+        assert_eq!(switch.state(), SwitchState::Active);
+    }
+
+    #[test]
+    fn arming_needs_low_throttle_and_full_hold() {
+        let mut arming = Switch::<Arming>::new();
+        let high_throttle = rc(true, 0.5);
+        for _ in 0..ARM_HOLD_TICKS * 2 {
+            assert_eq!(arming.update(&high_throttle, OK), SwitchState::Inactive);
+        }
+
+        let low_throttle = rc(true, 0.0);
+        for _ in 1..ARM_HOLD_TICKS {
+            assert_eq!(arming.update(&low_throttle, OK), SwitchState::Inactive);
+        }
+        assert_eq!(arming.update(&low_throttle, OK), SwitchState::Active);
+
+        let disarm = rc(false, 0.0);
+        for _ in 1..DISARM_HOLD_TICKS {
+            assert_eq!(arming.update(&disarm, OK), SwitchState::Active);
+        }
+        assert_eq!(arming.update(&disarm, OK), SwitchState::Inactive);
+    }
+
+    #[test]
+    fn arming_drops_on_rc_loss() {
+        let mut arming = Switch::<Arming>::new();
+        let on = rc(true, 0.0);
+        for _ in 0..ARM_HOLD_TICKS {
+            arming.update(&on, OK);
+        }
+        assert_eq!(arming.state(), SwitchState::Active);
+        assert_eq!(arming.update(&on, RC_LOST), SwitchState::Inactive);
+    }
+
+    #[test]
+    fn failing_prearm_blocks_until_switch_cycled() {
+        let mut arming = Switch::<Arming>::new();
+        let on = rc(true, 0.0);
+        let blocked = ArmingContext {
+            prearm_failures: 1,
+            ..OK
+        };
+        for _ in 0..ARM_HOLD_TICKS * 2 {
+            assert_eq!(arming.update(&on, blocked), SwitchState::Inactive);
+        }
+        assert!(!arming.command(true, &on, blocked));
+
+        // Checks passing while the switch is still up must not arm
+        for _ in 0..ARM_HOLD_TICKS * 2 {
+            assert_eq!(arming.update(&on, OK), SwitchState::Inactive);
+        }
+
+        arming.update(&rc(false, 0.0), OK);
+        for _ in 0..ARM_HOLD_TICKS {
+            arming.update(&on, OK);
+        }
+        assert_eq!(arming.state(), SwitchState::Active);
+
+        // Failing checks never disarm in flight
+        assert_eq!(arming.update(&on, blocked), SwitchState::Active);
+    }
+
+    #[test]
+    fn throttle_blip_keeps_the_prearm_latch() {
+        let mut arming = Switch::<Arming>::new();
+        let on = rc(true, 0.0);
+        let blocked = ArmingContext {
+            prearm_failures: 1,
+            ..OK
+        };
+        arming.update(&on, blocked);
+
+        // Throttle up and back down with the switch held up
+        arming.update(&rc(true, 0.5), OK);
+        for _ in 0..ARM_HOLD_TICKS * 2 {
+            assert_eq!(arming.update(&on, OK), SwitchState::Inactive);
+        }
+
+        // Same after a ground station disarm
+        arming.update(&rc(false, 0.0), OK);
+        assert!(arming.command(true, &on, OK));
+        assert!(arming.command(false, &on, OK));
+        arming.update(&rc(true, 0.5), OK);
+        for _ in 0..ARM_HOLD_TICKS * 2 {
+            assert_eq!(arming.update(&on, OK), SwitchState::Inactive);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// Frame: TELE_SYNC, TELE_VERSION, category u8, value count u8, seq u16, time_us u32,
+//        values f32 * count, crc16, little endian.
+// CRC-16/CCITT-FALSE over everything between TELE_SYNC and the CRC, as in `protocol`.
+// `seq` counts every frame produced, so gaps show frames dropped on a full channel.
+// Each category streams only while subscribed, every `divisor`-th sample.
+
+#[cfg(feature = "telemetry")]
+use crate::consts::{
+    TELE_CATEGORIES, TELE_CRC_SIZE, TELE_FRAME_SIZE, TELE_HEADER_SIZE, TELE_MAX_VALUES, TELE_SYNC,
+    TELE_VERSION,
+};
+#[cfg(feature = "telemetry")]
+use crc::{CRC_16_IBM_3740, Crc};
+#[cfg(feature = "telemetry")]
+use portable_atomic::{AtomicU8, AtomicU16, AtomicU32, Ordering};
+
+#[cfg(feature = "telemetry")]
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+#[cfg(feature = "telemetry")]
+pub type TeleChannel = embassy_sync::channel::Channel<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    [u8; TELE_FRAME_SIZE],
+    32,
+>;
+
+#[cfg(feature = "telemetry")]
+pub static TELE_CHANNEL: TeleChannel = TeleChannel::new();
+
+/// Bit per category id, set while subscribed.
+#[cfg(feature = "telemetry")]
+static TELE_MASK: AtomicU32 = AtomicU32::new(0);
+#[cfg(feature = "telemetry")]
+static TELE_DIVISORS: [AtomicU8; TELE_CATEGORIES] = [const { AtomicU8::new(1) }; TELE_CATEGORIES];
+#[cfg(feature = "telemetry")]
+static TELE_COUNTERS: [AtomicU8; TELE_CATEGORIES] = [const { AtomicU8::new(0) }; TELE_CATEGORIES];
+#[cfg(feature = "telemetry")]
+static TELE_SEQ: AtomicU16 = AtomicU16::new(0);
+
+/// A `divisor` of 0 unsubscribes. Returns false for category ids out of range.
+#[cfg(feature = "telemetry")]
+pub fn subscribe(category: u8, divisor: u8) -> bool {
+    let index = category as usize;
+    if index == 0 || index >= TELE_CATEGORIES {
+        return false;
+    }
+    let bit = 1 << index;
+    if divisor == 0 {
+        TELE_MASK.fetch_and(!bit, Ordering::Relaxed);
+    } else {
+        TELE_DIVISORS[index].store(divisor, Ordering::Relaxed);
+        TELE_COUNTERS[index].store(0, Ordering::Relaxed);
+        TELE_MASK.fetch_or(bit, Ordering::Relaxed);
+    }
+    true
+}
+
+#[cfg(feature = "telemetry")]
+pub fn unsubscribe_all() {
+    TELE_MASK.store(0, Ordering::Relaxed);
+}
+
+#[cfg(feature = "telemetry")]
+pub fn subscriptions() -> u32 {
+    TELE_MASK.load(Ordering::Relaxed)
+}
+
+/// Bytes in use of a frame taken from `TELE_CHANNEL`.
+#[cfg(feature = "telemetry")]
+pub fn frame_len(frame: &[u8; TELE_FRAME_SIZE]) -> usize {
+    TELE_HEADER_SIZE + frame[3] as usize * 4 + TELE_CRC_SIZE
+}
+
+/// Whether this sample of `category` is due, each category has a single producer.
+#[cfg(feature = "telemetry")]
+#[doc(hidden)]
+pub fn due(category: u8) -> bool {
+    let index = category as usize;
+    if index >= TELE_CATEGORIES || TELE_MASK.load(Ordering::Relaxed) & (1 << index) == 0 {
+        return false;
+    }
+    let count = TELE_COUNTERS[index].load(Ordering::Relaxed) + 1;
+    let due = count >= TELE_DIVISORS[index].load(Ordering::Relaxed);
+    TELE_COUNTERS[index].store(if due { 0 } else { count }, Ordering::Relaxed);
+    due
+}
+
+#[cfg(feature = "telemetry")]
+#[doc(hidden)]
+pub fn send(category: u8, values: &[f32]) {
+    let n = values.len().min(TELE_MAX_VALUES);
+    let seq = TELE_SEQ.fetch_add(1, Ordering::Relaxed);
+    let time_us = embassy_time::Instant::now().as_micros() as u32;
+    let mut frame = [0u8; TELE_FRAME_SIZE];
+    frame[0] = TELE_SYNC;
+    frame[1] = TELE_VERSION;
+    frame[2] = category;
+    frame[3] = n as u8;
+    frame[4..6].copy_from_slice(&seq.to_le_bytes());
+    frame[6..10].copy_from_slice(&time_us.to_le_bytes());
+    for (i, v) in values.iter().take(n).enumerate() {
+        let at = TELE_HEADER_SIZE + i * 4;
+        frame[at..at + 4].copy_from_slice(&v.to_le_bytes());
+    }
+    let end = TELE_HEADER_SIZE + n * 4;
+    let crc = CRC.checksum(&frame[1..end]);
+    frame[end..end + TELE_CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+    let _ = TELE_CHANNEL.try_send(frame);
+}
+
+#[macro_export]
+macro_rules! tele {
+    ($cat:path, $($v:expr),+ $(,)?) => {
+        #[cfg(feature = "telemetry")]
+        {
+            if $crate::telemetry::due($cat as u8) {
+                $crate::telemetry::send($cat as u8, &[$($v as f32),+]);
+            }
+        }
+        #[cfg(not(feature = "telemetry"))]
+        {
+            let _ = $cat;
+            $(let _ = $v;)+
+        }
+    };
+}
+
+#[cfg(all(test, feature = "telemetry"))]
+mod tests {
+    use super::*;
+
+    // Subscriptions are global, so everything runs in one test
+    #[test]
+    fn subscriptions_decimate_and_frame() {
+        assert!(!subscribe(0, 1));
+        assert!(!subscribe(TELE_CATEGORIES as u8, 1));
+
+        assert!(subscribe(3, 1));
+        assert!(subscribe(5, 4));
+        assert_eq!(subscriptions(), (1 << 3) | (1 << 5));
+
+        assert!(!due(2));
+        assert!((0..8).all(|_| due(3)));
+        let fives: Vec<_> = (0..8).map(|_| due(5)).collect();
+        assert_eq!(fives.iter().filter(|d| **d).count(), 2);
+
+        while TELE_CHANNEL.try_receive().is_ok() {}
+        send(5, &[1.5, -2.0]);
+        send(3, &[0.0]);
+        let frame = TELE_CHANNEL.try_receive().unwrap();
+        assert_eq!(&frame[..4], &[TELE_SYNC, TELE_VERSION, 5, 2]);
+        let len = frame_len(&frame);
+        assert_eq!(len, TELE_HEADER_SIZE + 8 + TELE_CRC_SIZE);
+        assert_eq!(
+            &frame[TELE_HEADER_SIZE..TELE_HEADER_SIZE + 4],
+            &1.5f32.to_le_bytes()
+        );
+        let crc = u16::from_le_bytes([frame[len - 2], frame[len - 1]]);
+        assert_eq!(CRC.checksum(&frame[1..len - 2]), crc);
+
+        let next = TELE_CHANNEL.try_receive().unwrap();
+        let seq = |f: &[u8]| u16::from_le_bytes([f[4], f[5]]);
+        assert_eq!(seq(&next), seq(&frame).wrapping_add(1));
+        let time = |f: &[u8]| u32::from_le_bytes([f[6], f[7], f[8], f[9]]);
+        assert!(time(&next) >= time(&frame));
+
+        assert!(subscribe(5, 0));
+        assert!(!due(5));
+
+        // The firmware-only categories above the shared ones
+        assert!(subscribe(crate::consts::TELE_ESC_RPM, 1));
+        assert!(subscribe(crate::consts::TELE_BATTERY, 1));
+        assert!(due(crate::consts::TELE_BATTERY));
+
+        unsubscribe_all();
+        assert_eq!(subscriptions(), 0);
+    }
+}
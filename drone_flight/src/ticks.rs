@@ -0,0 +1,61 @@
+/// Counts loop periods that passed without a tick, e.g. while a flash write held the core.
+#[derive(Clone, Debug)]
+pub struct TickMonitor {
+    period_us: u64,
+    last_us: Option<u64>,
+    missed: u32,
+}
+
+impl TickMonitor {
+    pub fn new(tick_hz: u64) -> TickMonitor {
+        TickMonitor {
+            period_us: 1_000_000 / tick_hz,
+            last_us: None,
+            missed: 0,
+        }
+    }
+
+    /// Periods missed since the previous tick, a late tick that still lands
+    /// within one period of it counts as none.
+    pub fn tick(&mut self, now_us: u64) -> u32 {
+        let missed = match self.last_us {
+            Some(last) => (now_us.saturating_sub(last) / self.period_us).saturating_sub(1) as u32,
+            None => 0,
+        };
+        self.last_us = Some(now_us);
+        self.missed = self.missed.saturating_add(missed);
+        missed
+    }
+
+    /// Missed periods since boot.
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_ticks_within_a_period_are_not_missed() {
+        let mut ticks = TickMonitor::new(1000);
+        assert_eq!(ticks.tick(5_000), 0);
+        assert_eq!(ticks.tick(6_000), 0);
+        // 0.4 ms late, then caught up
+        assert_eq!(ticks.tick(7_400), 0);
+        assert_eq!(ticks.tick(8_000), 0);
+        assert_eq!(ticks.missed(), 0);
+    }
+
+    #[test]
+    fn stalls_count_the_skipped_periods() {
+        let mut ticks = TickMonitor::new(1000);
+        ticks.tick(0);
+        assert_eq!(ticks.tick(3_200), 2);
+        assert_eq!(ticks.tick(3_300), 0);
+        assert_eq!(ticks.tick(5_000), 0);
+        assert_eq!(ticks.tick(7_000), 1);
+        assert_eq!(ticks.missed(), 3);
+    }
+}
@@ -0,0 +1,59 @@
+mod pilot;
+mod quad;
+mod sensors;
+
+use drone_flight::{
+    battery::BatteryMonitor,
+    consts::{CYCLE_TIME, TICK_HZ},
+    flight::FlightController,
+    params::Params,
+};
+use pilot::Pilot;
+use quad::{Quad, QuadParams};
+use sensors::Sensors;
+
+const LOG_EVERY_TICKS: u64 = 10;
+
+fn main() {
+    let pilot = match std::env::args().nth(1).as_deref() {
+        Some("rc_loss") => Pilot::rc_loss(),
+        _ => Pilot::hover(),
+    };
+    let mut quad = Quad::new(QuadParams::default());
+    let mut sensors = Sensors::new(0xC0FFEE);
+    let mut flight = FlightController::new(&Params::defaults());
+    let mut battery_monitor = BatteryMonitor::new(&Params::defaults(), TICK_HZ as f32);
+
+    println!("t,x,y,z,roll,pitch,yaw,m1,m2,m3,m4,armed");
+
+    let ticks = (pilot.duration() / CYCLE_TIME) as u64;
+    for tick in 0..=ticks {
+        let t = tick as f32 * CYCLE_TIME;
+        let sticks = pilot.sticks(t);
+        let rc = (!sticks.rc_lost).then(|| sticks.to_rc());
+        let imu = sensors.imu(&quad);
+        let baro_alt = sensors.baro(&quad);
+        let (voltage, current) = quad.battery();
+        let battery = battery_monitor.update(voltage, Some(current));
+
+        let outputs = flight.update(Some(imu), rc, Some(baro_alt), &quad.erpm(), Some(battery));
+        quad.step(outputs.map(|o| o.motors), CYCLE_TIME);
+
+        if tick.is_multiple_of(LOG_EVERY_TICKS) {
+            let (roll, pitch, yaw) = quad.attitude.euler_angles();
+            let p = quad.position;
+            let m = outputs.unwrap_or_default().motors;
+            println!(
+                "{t:.3},{:.3},{:.3},{:.3},{roll:.4},{pitch:.4},{yaw:.4},{},{},{},{},{}",
+                p.x,
+                p.y,
+                p.z,
+                m[0],
+                m[1],
+                m[2],
+                m[3],
+                flight.is_armed() as u8,
+            );
+        }
+    }
+}
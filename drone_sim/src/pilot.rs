@@ -0,0 +1,151 @@
+use drone_flight::{
+    consts::{RC_MAX, RC_MIN},
+    rc::RcData,
+};
+
+/// Stick and switch positions in the units `RcData` hands back.
+#[derive(Copy, Clone, Default)]
+pub struct Sticks {
+    pub roll: f32,
+    pub pitch: f32,
+    pub throttle: f32,
+    pub yaw: f32,
+    pub alt_kp: f32,
+    pub alt_kd: f32,
+    pub arm: bool,
+    pub alt_hold: bool,
+    pub acro: bool,
+    pub horizon: bool,
+    /// Receiver lost, the flight loop gets no RC data.
+    pub rc_lost: bool,
+}
+
+impl Sticks {
+    fn lerp(&self, to: &Sticks, k: f32) -> Sticks {
+        let mix = |a: f32, b: f32| a + (b - a) * k;
+        Sticks {
+            roll: mix(self.roll, to.roll),
+            pitch: mix(self.pitch, to.pitch),
+            throttle: mix(self.throttle, to.throttle),
+            yaw: mix(self.yaw, to.yaw),
+            alt_kp: mix(self.alt_kp, to.alt_kp),
+            alt_kd: mix(self.alt_kd, to.alt_kd),
+            arm: self.arm,
+            alt_hold: self.alt_hold,
+            acro: self.acro,
+            horizon: self.horizon,
+            rc_lost: self.rc_lost,
+        }
+    }
+
+    pub fn to_rc(self) -> RcData {
+        let mut channels = [RC_MIN; 16];
+        channels[0] = channel(self.roll, -1.0, 1.0);
+        channels[1] = channel(self.pitch, -1.0, 1.0);
+        channels[2] = channel(self.throttle, 0.0, 1.0);
+        channels[3] = channel(self.yaw, -1.0, 1.0);
+        channels[4] = channel(self.alt_kp, 0.0, 1.0);
+        channels[5] = channel(self.alt_kd, 0.0, 1.0);
+        channels[6] = channel(self.arm as u8 as f32, 0.0, 1.0);
+        channels[7] = channel(self.alt_hold as u8 as f32, 0.0, 1.0);
+        channels[8] = channel(self.acro as u8 as f32, 0.0, 1.0);
+        channels[9] = channel(self.horizon as u8 as f32, 0.0, 1.0);
+        RcData::from_channels(channels)
+    }
+}
+
+fn channel(value: f32, min: f32, max: f32) -> u16 {
+    let k = ((value - min) / (max - min)).clamp(0.0, 1.0);
+    (RC_MIN as f32 + k * (RC_MAX - RC_MIN) as f32).round() as u16
+}
+
+/// Scripted pilot: sticks are interpolated between keyframes, switches jump.
+pub struct Pilot {
+    keyframes: Vec<(f32, Sticks)>,
+}
+
+impl Pilot {
+    pub fn new(keyframes: Vec<(f32, Sticks)>) -> Pilot {
+        Pilot { keyframes }
+    }
+
+    /// Arm on the ground, climb, engage altitude hold, then land and disarm.
+    pub fn hover() -> Pilot {
+        let idle = Sticks {
+            alt_kp: 0.5,
+            alt_kd: 0.5,
+            ..Default::default()
+        };
+        let armed = Sticks { arm: true, ..idle };
+        let climb = Sticks {
+            throttle: 0.33,
+            ..armed
+        };
+        let hold = Sticks {
+            alt_hold: true,
+            ..climb
+        };
+
+        Pilot::new(vec![
+            (0.0, idle),
+            (0.5, armed),
+            (2.0, armed),
+            (4.0, climb),
+            (6.0, hold),
+            (15.0, hold),
+            (15.0, climb),
+            (17.0, armed),
+            (19.0, armed),
+            (19.0, idle),
+            (19.5, idle),
+        ])
+    }
+
+    /// Climb into altitude hold, then lose the receiver and let the failsafe land.
+    pub fn rc_loss() -> Pilot {
+        let idle = Sticks {
+            alt_kp: 0.5,
+            alt_kd: 0.5,
+            ..Default::default()
+        };
+        let armed = Sticks { arm: true, ..idle };
+        let climb = Sticks {
+            throttle: 0.33,
+            ..armed
+        };
+        let hold = Sticks {
+            alt_hold: true,
+            ..climb
+        };
+        let lost = Sticks {
+            rc_lost: true,
+            ..hold
+        };
+
+        Pilot::new(vec![
+            (0.0, idle),
+            (0.5, armed),
+            (2.0, armed),
+            (4.0, climb),
+            (6.0, hold),
+            (8.0, hold),
+            (8.0, lost),
+            (40.0, lost),
+        ])
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|(t, _)| *t).unwrap_or_default()
+    }
+
+    pub fn sticks(&self, t: f32) -> Sticks {
+        let next = self.keyframes.partition_point(|(at, _)| *at <= t);
+        let prev = next.checked_sub(1).and_then(|i| self.keyframes.get(i));
+        match (prev, self.keyframes.get(next)) {
+            (Some((t0, from)), Some((t1, to))) => from.lerp(to, (t - t0) / (t1 - t0)),
+            (Some((_, last)), None) => *last,
+            (None, Some((_, first))) => *first,
+            (None, None) => Sticks::default(),
+        }
+    }
+}
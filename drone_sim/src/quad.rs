@@ -0,0 +1,147 @@
+use drone_flight::consts::{SLOPE, THROTTLE_MIN};
+use drone_flight::esc::Erpm;
+use drone_flight::mixer::MAX_MOTORS;
+use nalgebra::{UnitQuaternion, Vector3};
+
+pub const GRAVITY: f32 = 9.81;
+
+pub struct QuadParams {
+    pub mass: f32,
+    pub inertia: Vector3<f32>,
+    pub arm_length: f32,
+    pub max_thrust: f32,
+    pub yaw_coeff: f32,
+    pub motor_tau: f32,
+    pub drag: f32,
+    /// Electrical RPM at full throttle, as bidirectional DShot reports it.
+    pub max_erpm: f32,
+    /// Open circuit pack voltage, 4S full.
+    pub battery_v: f32,
+    pub battery_resistance: f32,
+    /// Current per motor at full throttle, it goes with the cube of the speed.
+    pub max_current: f32,
+}
+
+impl Default for QuadParams {
+    fn default() -> QuadParams {
+        QuadParams {
+            mass: 0.6,
+            inertia: Vector3::new(0.006, 0.006, 0.011),
+            arm_length: 0.11,
+            max_thrust: 16.0,
+            yaw_coeff: 0.016,
+            motor_tau: 0.02,
+            drag: 0.3,
+            max_erpm: 210_000.0,
+            battery_v: 16.8,
+            battery_resistance: 0.02,
+            max_current: 20.0,
+        }
+    }
+}
+
+// Body frame matches the IMU: x forward, y left, z up.
+// Motor order follows the quad X mixer preset, the only frame modeled here.
+// (x, y, yaw torque sign), the sign being opposite to the prop spin.
+const MOTOR_LAYOUT: [(f32, f32, f32); 4] = [
+    (1.0, -1.0, -1.0), // Front Right, CCW
+    (-1.0, 1.0, -1.0), // Back Left, CCW
+    (1.0, 1.0, 1.0),   // Front Left, CW
+    (-1.0, -1.0, 1.0), // Back Right, CW
+];
+
+pub struct Quad {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub attitude: UnitQuaternion<f32>,
+    pub rates: Vector3<f32>,
+    pub motors: [f32; 4],
+    specific_force: Vector3<f32>,
+    params: QuadParams,
+}
+
+impl Quad {
+    pub fn new(params: QuadParams) -> Quad {
+        Quad {
+            position: Vector3::zeros(),
+            velocity: Vector3::zeros(),
+            attitude: UnitQuaternion::identity(),
+            rates: Vector3::zeros(),
+            motors: [0.0; 4],
+            specific_force: Vector3::new(0.0, 0.0, GRAVITY),
+            params,
+        }
+    }
+
+    /// What an ideal accelerometer would read, in the body frame.
+    pub fn specific_force(&self) -> Vector3<f32> {
+        self.specific_force
+    }
+
+    /// What the ESCs would report for the current motor speeds.
+    pub fn erpm(&self) -> Erpm {
+        let mut erpm = [None; MAX_MOTORS];
+        for (out, motor) in erpm.iter_mut().zip(self.motors) {
+            *out = Some((motor * self.params.max_erpm) as u32);
+        }
+        erpm
+    }
+
+    /// Pack voltage and current drawn at the current motor speeds.
+    pub fn battery(&self) -> (f32, f32) {
+        let p = &self.params;
+        let current: f32 = self.motors.iter().map(|m| p.max_current * m * m * m).sum();
+        (p.battery_v - current * p.battery_resistance, current)
+    }
+
+    pub fn step(&mut self, dshot: Option<[u16; MAX_MOTORS]>, dt: f32) {
+        let p = &self.params;
+        let commands = dshot
+            .map(|d| d.map(dshot_to_fraction))
+            .unwrap_or([0.0; MAX_MOTORS]);
+
+        let mut thrust = 0.0;
+        let mut torque = Vector3::zeros();
+        let offset = p.arm_length * core::f32::consts::FRAC_1_SQRT_2;
+
+        for ((motor, command), (x, y, spin)) in
+            self.motors.iter_mut().zip(commands).zip(MOTOR_LAYOUT)
+        {
+            *motor += (command - *motor) * (dt / p.motor_tau).min(1.0);
+            let f = p.max_thrust * *motor * *motor;
+            thrust += f;
+            torque += Vector3::new(y * offset * f, -x * offset * f, spin * p.yaw_coeff * f);
+        }
+
+        let gyroscopic = self.rates.cross(&p.inertia.component_mul(&self.rates));
+        let angular_acc = (torque - gyroscopic).component_div(&p.inertia);
+
+        let gravity = Vector3::new(0.0, 0.0, -GRAVITY);
+        let body_force = Vector3::new(0.0, 0.0, thrust / p.mass);
+        let mut acc = self.attitude * body_force + gravity - self.velocity * p.drag;
+
+        self.rates += angular_acc * dt;
+        self.attitude *= UnitQuaternion::from_scaled_axis(self.rates * dt);
+        self.velocity += acc * dt;
+        self.position += self.velocity * dt;
+
+        if self.position.z <= 0.0 && self.velocity.z <= 0.0 {
+            self.position.z = 0.0;
+            self.velocity = Vector3::zeros();
+            self.rates = Vector3::zeros();
+            self.attitude =
+                UnitQuaternion::from_euler_angles(0.0, 0.0, self.attitude.euler_angles().2);
+            acc = Vector3::zeros();
+        }
+
+        self.specific_force = self.attitude.inverse() * (acc - gravity);
+    }
+}
+
+fn dshot_to_fraction(value: u16) -> f32 {
+    if (value as f32) < THROTTLE_MIN {
+        0.0
+    } else {
+        ((value as f32 - THROTTLE_MIN) / SLOPE).clamp(0.0, 1.0)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::quad::Quad;
+use drone_flight::{
+    consts::{BARO_HZ, CYCLE_TIME, TICK_HZ},
+    imu::ImuData,
+};
+use nalgebra::Vector3;
+
+const GYRO_NOISE: f32 = 0.01;
+const ACC_NOISE: f32 = 0.1;
+const BARO_NOISE: f32 = 0.1;
+
+/// Small deterministic xorshift so runs are reproducible without pulling in `rand`.
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    fn vector(&mut self, amplitude: f32) -> Vector3<f32> {
+        Vector3::new(self.next(), self.next(), self.next()) * amplitude
+    }
+}
+
+pub struct Sensors {
+    noise: Noise,
+    ticks: u64,
+    baro_alt: f32,
+}
+
+impl Sensors {
+    pub fn new(seed: u32) -> Sensors {
+        Sensors {
+            noise: Noise(seed.max(1)),
+            ticks: 0,
+            baro_alt: 0.0,
+        }
+    }
+
+    /// Same shape as what `imu_task` publishes after calibration: bias-free
+    /// gyro/acc, no magnetometer and a fixed loop period.
+    pub fn imu(&mut self, quad: &Quad) -> ImuData {
+        ImuData {
+            gyro: quad.rates + self.noise.vector(GYRO_NOISE),
+            acc: quad.specific_force() + self.noise.vector(ACC_NOISE),
+            mag: Vector3::zeros(),
+            dt: CYCLE_TIME,
+        }
+    }
+
+    /// Baro altitude relative to the ground, refreshed at `BARO_HZ` like `baro_task`.
+    pub fn baro(&mut self, quad: &Quad) -> f32 {
+        if self.ticks.is_multiple_of(TICK_HZ / BARO_HZ) {
+            self.baro_alt = quad.position.z + self.noise.next() * BARO_NOISE;
+        }
+        self.ticks += 1;
+        self.baro_alt
+    }
+}
@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 1024K - 4K
+    /* Last 4K sector is reserved for persisted params */
+    /* and the 1024K below it for the blackbox ring    */
 
     /* Pick one of the two options for RAM layout     */
 
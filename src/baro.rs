@@ -30,6 +30,7 @@ pub async fn baro_task(mut baro: setup::BaroReader) -> ! {
             continue;
         }
 
+        // Nothing is published before the ground level is known, pre-arm checks rely on that
         let relative_alt = match ground_pa {
             Some(base) => (base - current_pa) * 0.0843,
             None => {
@@ -44,7 +45,8 @@ pub async fn baro_task(mut baro: setup::BaroReader) -> ! {
                         avg_base
                     );
                 }
-                0.0
+                loop_ticker.next().await;
+                continue;
             }
         };
 
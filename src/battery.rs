@@ -0,0 +1,50 @@
+use crate::consts::{BATTERY_HZ, TELE_BATTERY};
+use crate::device::{self, CURRENT_SCALE, VBAT_SCALE};
+use drone_flight::battery::{BatteryMonitor, BatteryState};
+use embassy_rp::adc::{self, Adc, Channel};
+use embassy_rp::gpio::Pull;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Watch};
+use embassy_time::{Duration, Ticker};
+
+// 12 bit ADC on the 3.3 V reference
+const ADC_VOLTS: f32 = 3.3 / 4096.0;
+
+pub static BATTERY: Watch<CriticalSectionRawMutex, BatteryState, 1> = Watch::new();
+
+#[embassy_executor::task]
+pub async fn battery_task(battery: device::Battery, mut monitor: BatteryMonitor) -> ! {
+    let mut adc = Adc::new(battery.adc, device::Irqs, adc::Config::default());
+    let mut vbat = Channel::new_pin(battery.vbat, Pull::None);
+    let mut current = Channel::new_pin(battery.current, Pull::None);
+    let mut loop_ticker = Ticker::every(Duration::from_hz(BATTERY_HZ));
+    let battery_sender = BATTERY.sender();
+
+    loop {
+        let Ok(raw) = adc.read(&mut vbat).await else {
+            log::error!("Failed to read battery voltage");
+            loop_ticker.next().await;
+            continue;
+        };
+        let amps = match CURRENT_SCALE {
+            Some(scale) => adc
+                .read(&mut current)
+                .await
+                .ok()
+                .map(|raw| raw as f32 * ADC_VOLTS * scale),
+            None => None,
+        };
+
+        let state = monitor.update(raw as f32 * ADC_VOLTS * VBAT_SCALE, amps);
+        tele!(
+            TELE_BATTERY,
+            state.voltage,
+            state.compensated,
+            state.current.unwrap_or(-1.0),
+            state.mah,
+            state.cells,
+            state.level as u8
+        );
+        battery_sender.send(*state);
+        loop_ticker.next().await;
+    }
+}
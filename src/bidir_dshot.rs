@@ -0,0 +1,207 @@
+use crate::consts::SYSTEM_FREQ;
+use drone_flight::esc::dshot_speed;
+use dshot_frame::{BidirectionalDshot, Frame};
+use embassy_dshot::{
+    Command, ExtendedTelemetry, decode_extended_telemetry, gcr_decode, verify_telemetry_crc,
+};
+use embassy_rp::gpio::Pull;
+use embassy_rp::pio::program::pio_asm;
+use embassy_rp::pio::{
+    Common, Config, Direction, FifoJoin, Instance, Pin, Pio, ShiftConfig, ShiftDirection,
+    StateMachine,
+};
+use fixed::FixedU32;
+use fixed::types::extra::U8;
+
+// 40 PIO cycles per DShot600 bit on TX, the RX loops count pulse widths at the same rate
+const PIO_HZ: u64 = 40 * 600_000;
+// MOV ISR, NULL
+const CLEAR_ISR: u16 = 0xA0C3;
+
+/// Bidirectional DShot600 on one state machine per ESC, up to four on a PIO.
+///
+/// Each ESC answers a frame with its eRPM on the same wire about 30 us later. Nothing
+/// waits for it, the reply is picked up with the next frame a tick later.
+/// Same PIO program as `embassy_dshot::rp::BidirDshotPio`, which only drives one ESC per PIO.
+pub struct BidirDshot<'d, PIO: Instance, const N: usize> {
+    _common: Common<'d, PIO>,
+    sm0: StateMachine<'d, PIO, 0>,
+    sm1: StateMachine<'d, PIO, 1>,
+    sm2: StateMachine<'d, PIO, 2>,
+    sm3: StateMachine<'d, PIO, 3>,
+    origin: u8,
+}
+
+impl<'d, PIO: Instance, const N: usize> BidirDshot<'d, PIO, N> {
+    /// `pins` come from `pio.common.make_pio_pin`, in motor order.
+    pub fn new(pio: Pio<'d, PIO>, mut pins: [Pin<'d, PIO>; N]) -> Self {
+        assert!(N <= 4, "a PIO has four state machines");
+        let Pio {
+            mut common,
+            mut sm0,
+            mut sm1,
+            mut sm2,
+            mut sm3,
+            ..
+        } = pio;
+
+        // push the last reply, wait for a frame at origin + 2, send it inverted,
+        // then turn the pin around and measure the 21 GCR bits of the reply
+        let prg = pio_asm!(
+            ".wrap_target"
+            "push block"
+            "set pindirs, 1"
+            "pull block"
+            "out null, 16"
+            "tx_bit:"
+            "set pins, 0 [13]"
+            "out pins, 1 [13]"
+            "set pins, 1 [10]"
+            "jmp !osre, tx_bit"
+            "set x, 20"
+            "mov osr, ~null"
+            "set pindirs, 0"
+            "wait_for_pin:"
+            "jmp pin, wait_for_pin [1]"
+            "new_zero:"
+            "set y, 6"
+            "jmp meas_zero"
+            "another_zero:"
+            "set y, 13 [1]"
+            "meas_zero:"
+            "jmp pin, new_one"
+            "jmp y--, meas_zero"
+            "in null, 1"
+            "jmp x--, another_zero"
+            "jmp done"
+            "new_one:"
+            "set y, 6 [1]"
+            "jmp meas_one"
+            "another_one:"
+            "set y, 13 [1]"
+            "meas_one:"
+            "jmp pin, cont_one"
+            "jmp new_zero"
+            "cont_one:"
+            "jmp y--, meas_one"
+            "in osr, 1"
+            "jmp x--, another_one"
+            "done:"
+            ".wrap"
+        );
+        let loaded = common.load_program(&prg.program);
+        let divider = FixedU32::<U8>::from_bits((((SYSTEM_FREQ as u64) << 8) / PIO_HZ) as u32);
+
+        for pin in pins.iter_mut() {
+            // The ESC pulls the line low to answer
+            pin.set_pull(Pull::Up);
+        }
+        for (i, pin) in pins.iter().enumerate() {
+            let mut cfg = Config::default();
+            cfg.use_program(&loaded, &[]);
+            cfg.clock_divider = divider;
+            let shift = ShiftConfig {
+                auto_fill: false,
+                direction: ShiftDirection::Left,
+                threshold: 32,
+            };
+            cfg.shift_out = shift;
+            cfg.shift_in = shift;
+            cfg.fifo_join = FifoJoin::Duplex;
+            cfg.set_jmp_pin(pin);
+            cfg.set_set_pins(&[pin]);
+            cfg.set_out_pins(&[pin]);
+            cfg.set_in_pins(&[pin]);
+            match i {
+                0 => start(&mut sm0, &cfg, pin),
+                1 => start(&mut sm1, &cfg, pin),
+                2 => start(&mut sm2, &cfg, pin),
+                _ => start(&mut sm3, &cfg, pin),
+            }
+        }
+
+        BidirDshot {
+            _common: common,
+            sm0,
+            sm1,
+            sm2,
+            sm3,
+            origin: loaded.origin,
+        }
+    }
+
+    /// Sends a motor output, 48-2047 or 0 to stop, to each ESC and returns the eRPM
+    /// they answered the previous frame with.
+    pub fn throttle_clamp(&mut self, throttle: [u16; N]) -> [Option<u32>; N] {
+        self.exchange(throttle.map(|t| {
+            let frame = match dshot_speed(t) {
+                Some(speed) => Frame::<BidirectionalDshot>::new(speed, false),
+                None => Some(Frame::<BidirectionalDshot>::command(
+                    Command::MotorStop,
+                    false,
+                )),
+            };
+            frame.map_or(0, |frame| frame.inner())
+        }))
+    }
+
+    /// Sends `cmd` to every ESC, returns the eRPM as `throttle_clamp`.
+    pub fn send_command(&mut self, cmd: Command) -> [Option<u32>; N] {
+        self.exchange([Frame::<BidirectionalDshot>::command(cmd, false).inner(); N])
+    }
+
+    fn exchange(&mut self, frames: [u16; N]) -> [Option<u32>; N] {
+        let origin = self.origin;
+        core::array::from_fn(|i| match i {
+            0 => exchange(&mut self.sm0, origin, frames[i]),
+            1 => exchange(&mut self.sm1, origin, frames[i]),
+            2 => exchange(&mut self.sm2, origin, frames[i]),
+            _ => exchange(&mut self.sm3, origin, frames[i]),
+        })
+    }
+}
+
+fn start<'d, PIO: Instance, const SM: usize>(
+    sm: &mut StateMachine<'d, PIO, SM>,
+    cfg: &Config<'d, PIO>,
+    pin: &Pin<'d, PIO>,
+) {
+    sm.set_config(cfg);
+    sm.set_pin_dirs(Direction::Out, &[pin]);
+    sm.restart();
+    sm.set_enable(true);
+}
+
+/// Takes the reply to the last frame and sends the next one.
+fn exchange<PIO: Instance, const SM: usize>(
+    sm: &mut StateMachine<'_, PIO, SM>,
+    origin: u8,
+    frame: u16,
+) -> Option<u32> {
+    let reply = sm.rx().try_pull();
+    while sm.rx().try_pull().is_some() {}
+
+    // Without a reply the program still waits for the line to go low, send it back
+    // to waiting for a frame and drop the partial bits
+    if sm.get_addr() != origin + 2 {
+        unsafe {
+            sm.exec_instr(CLEAR_ISR);
+            sm.exec_instr((origin + 1) as u16 & 0x1f);
+        }
+    }
+    // Bidirectional DShot idles high, frames go out inverted
+    sm.tx().try_push(!frame as u32);
+
+    reply.and_then(decode_erpm)
+}
+
+fn decode_erpm(raw: u32) -> Option<u32> {
+    let value = gcr_decode(raw)?;
+    if !verify_telemetry_crc(value) {
+        return None;
+    }
+    match decode_extended_telemetry(value >> 4) {
+        ExtendedTelemetry::Erpm { erpm, .. } => Some(erpm),
+        _ => None,
+    }
+}
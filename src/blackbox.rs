@@ -0,0 +1,157 @@
+use crate::consts::{BLACKBOX_ERASE_AHEAD, BLACKBOX_SECTORS, TICK_HZ};
+use crate::storage::FlashBlackboxStorage;
+use drone_flight::blackbox::{BlackboxError, Event, Recorder, Snapshot, modes};
+use drone_flight::params::Params;
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
+};
+use embassy_time::{Instant, Timer};
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+
+const PREPARE_INTERVAL_MS: u64 = 100;
+
+enum Entry {
+    Start(u32, u16),
+    Frame(u32, Snapshot),
+    Event(u32, Event),
+    Stop,
+}
+
+static ENTRIES: Channel<CriticalSectionRawMutex, Entry, 16> = Channel::new();
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+pub static ERASE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+pub static RECORDING: AtomicBool = AtomicBool::new(false);
+
+/// Flight loop side, samples snapshots without ever waiting on flash.
+pub struct Logger {
+    rate_div: u32,
+    tick: u32,
+    modes: u8,
+}
+
+impl Logger {
+    pub fn new(params: &Params) -> Logger {
+        Logger {
+            rate_div: params.bb_rate_div as u32,
+            tick: 0,
+            modes: 0,
+        }
+    }
+
+    pub fn apply_params(&mut self, params: &Params) {
+        self.rate_div = params.bb_rate_div as u32;
+    }
+
+    pub fn log(&mut self, snapshot: &Snapshot) {
+        let time_ms = Instant::now().as_millis() as u32;
+        let armed = snapshot.modes & modes::ARMED != 0;
+
+        if armed && self.modes & modes::ARMED == 0 {
+            self.tick = 0;
+            send(Entry::Start(
+                time_ms,
+                (TICK_HZ / self.rate_div as u64) as u16,
+            ));
+        }
+        for event in Event::from_modes(self.modes, snapshot.modes) {
+            send(Entry::Event(time_ms, event));
+        }
+        if !armed && self.modes & modes::ARMED != 0 {
+            send(Entry::Stop);
+        }
+        self.modes = snapshot.modes;
+
+        if armed {
+            if self.tick == 0 {
+                send(Entry::Frame(time_ms, snapshot.clone()));
+            }
+            self.tick = (self.tick + 1) % self.rate_div;
+        }
+    }
+
+    /// Marks a flight loop tick that came a period or more late, only while armed.
+    pub fn overrun(&mut self) {
+        if self.modes & modes::ARMED != 0 {
+            send(Entry::Event(
+                Instant::now().as_millis() as u32,
+                Event::LoopOverrun,
+            ));
+        }
+    }
+}
+
+fn send(entry: Entry) {
+    if ENTRIES.try_send(entry).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn record(
+    recorder: &mut Recorder<FlashBlackboxStorage>,
+    entry: Entry,
+) -> Result<(), BlackboxError> {
+    match entry {
+        Entry::Start(time_ms, rate_hz) => {
+            DROPPED.store(0, Ordering::Relaxed);
+            recorder.start(time_ms, rate_hz)
+        }
+        Entry::Frame(time_ms, snapshot) => {
+            if DROPPED.swap(0, Ordering::Relaxed) > 0 {
+                recorder.event(time_ms, Event::FramesDropped)?;
+            }
+            recorder.frame(time_ms, &snapshot)
+        }
+        Entry::Event(time_ms, event) => recorder.event(time_ms, event),
+        Entry::Stop => recorder.stop(),
+    }
+}
+
+/// Writes sessions to flash while armed and keeps sectors erased ahead while disarmed.
+/// Shares the flight loop's executor, so a page program (~0.4 ms, pausing core1 too)
+/// starts right after a tick and normally ends before the next one.
+/// A full log stays stopped until disarm, erasing in flight would stall the loops.
+#[embassy_executor::task]
+pub async fn blackbox_task() -> ! {
+    let mut recorder = Recorder::new(FlashBlackboxStorage);
+    let mut ahead = BLACKBOX_ERASE_AHEAD;
+    let mut armed = false;
+
+    loop {
+        let idle = !armed && !recorder.is_recording();
+        let prepare = async {
+            if idle {
+                Timer::after_millis(PREPARE_INTERVAL_MS).await
+            } else {
+                core::future::pending().await
+            }
+        };
+
+        match select3(ENTRIES.receive(), ERASE.wait(), prepare).await {
+            Either3::First(entry) => {
+                match entry {
+                    Entry::Start(..) => armed = true,
+                    Entry::Stop => armed = false,
+                    _ => {}
+                }
+                match record(&mut recorder, entry) {
+                    Ok(()) => {}
+                    Err(BlackboxError::Full) => log::warn!("Blackbox full, stopped until disarm"),
+                    Err(e) => log::warn!("Blackbox stopped: {:?}", e),
+                }
+            }
+            Either3::Second(()) => {
+                recorder.clear().ok();
+                ahead = BLACKBOX_SECTORS;
+                log::info!("Blackbox erase requested");
+            }
+            Either3::Third(()) => match recorder.prepare(ahead) {
+                Ok(true) => {}
+                Ok(false) => ahead = BLACKBOX_ERASE_AHEAD,
+                Err(e) => log::error!("Blackbox erase failed: {:?}", e),
+            },
+        }
+
+        RECORDING.store(recorder.is_recording(), Ordering::Relaxed);
+    }
+}
@@ -1,15 +1,17 @@
 use embassy_rp::{
-    Peri, bind_interrupts, i2c::InterruptHandler as I2CHandler, peripherals,
-    pio::InterruptHandler as PioHandler, uart::InterruptHandler as UartHandler,
+    Peri, adc::InterruptHandler as AdcHandler, bind_interrupts,
+    i2c::InterruptHandler as I2CHandler, peripherals, pio::InterruptHandler as PioHandler,
+    uart::BufferedInterruptHandler as BufferedUartHandler,
 };
 
 #[cfg(feature = "feather")]
 pub mod device_impl {
     pub type Core1Peripheral = super::peripherals::CORE1;
+    pub type FlashPeripheral = super::peripherals::FLASH;
 
-    pub type SbusUartPeripheral = super::peripherals::UART1;
-    pub type SbusUartPin = super::peripherals::PIN_9;
-    pub type SbusDmaChannel = super::peripherals::DMA_CH1;
+    // UART RX, or the PPM input on the second PIO
+    pub type RcUartPeripheral = super::peripherals::UART1;
+    pub type RcPin = super::peripherals::PIN_9;
 
     pub type I2cPeripheral = super::peripherals::I2C1;
     pub type I2cSdaPin = super::peripherals::PIN_2;
@@ -20,13 +22,29 @@ pub mod device_impl {
     pub type DshotPioM2Pin = super::peripherals::PIN_13;
     pub type DshotPioM3Pin = super::peripherals::PIN_12;
     pub type DshotPioM4Pin = super::peripherals::PIN_11;
+    pub type DshotExtPioPeripheral = super::peripherals::PIO1;
+    pub type DshotPioM5Pin = super::peripherals::PIN_24;
+    pub type DshotPioM6Pin = super::peripherals::PIN_25;
+
+    pub type ServoPwmSlice = super::peripherals::PWM_SLICE3;
+    pub type ServoPin = super::peripherals::PIN_6;
+
+    pub type AdcPeripheral = super::peripherals::ADC;
+    pub type VbatPin = super::peripherals::PIN_26;
+    pub type CurrentPin = super::peripherals::PIN_27;
+    // Pack volts per ADC volt, 10k/1k divider on A0
+    pub const VBAT_SCALE: f32 = 11.0;
+    // Amps per ADC volt, no current sensor
+    pub const CURRENT_SCALE: Option<f32> = None;
 
     #[cfg(feature = "logging")]
     pub type USBPeripheral = super::peripherals::USB;
 
     super::bind_interrupts!(pub struct Irqs {
-        UART1_IRQ => super::UartHandler<SbusUartPeripheral>;
+        UART1_IRQ => super::BufferedUartHandler<RcUartPeripheral>;
         PIO0_IRQ_0 => super::PioHandler<DshotPioPeripheral>;
+        PIO1_IRQ_0 => super::PioHandler<DshotExtPioPeripheral>;
+        ADC_IRQ_FIFO => super::AdcHandler;
         I2C1_IRQ => super::I2CHandler<I2cPeripheral>;
     });
 }
@@ -34,10 +52,11 @@ pub mod device_impl {
 #[cfg(not(feature = "feather"))]
 mod device_impl {
     pub type Core1Peripheral = super::peripherals::CORE1;
+    pub type FlashPeripheral = super::peripherals::FLASH;
 
-    pub type SbusUartPeripheral = super::peripherals::UART1;
-    pub type SbusUartPin = super::peripherals::PIN_5;
-    pub type SbusDmaChannel = super::peripherals::DMA_CH1;
+    // UART RX, or the PPM input on the second PIO
+    pub type RcUartPeripheral = super::peripherals::UART1;
+    pub type RcPin = super::peripherals::PIN_5;
 
     pub type I2cPeripheral = super::peripherals::I2C0;
     pub type I2cSdaPin = super::peripherals::PIN_0;
@@ -48,23 +67,38 @@ mod device_impl {
     pub type DshotPioM2Pin = super::peripherals::PIN_20;
     pub type DshotPioM3Pin = super::peripherals::PIN_21;
     pub type DshotPioM4Pin = super::peripherals::PIN_11;
+    pub type DshotExtPioPeripheral = super::peripherals::PIO1;
+    pub type DshotPioM5Pin = super::peripherals::PIN_14;
+    pub type DshotPioM6Pin = super::peripherals::PIN_15;
+
+    pub type ServoPwmSlice = super::peripherals::PWM_SLICE0;
+    pub type ServoPin = super::peripherals::PIN_16;
+
+    pub type AdcPeripheral = super::peripherals::ADC;
+    pub type VbatPin = super::peripherals::PIN_26;
+    pub type CurrentPin = super::peripherals::PIN_27;
+    // Pack volts per ADC volt, 10k/1k divider on GP26
+    pub const VBAT_SCALE: f32 = 11.0;
+    // Amps per ADC volt of the current sensor on GP27, 25 mV/A
+    pub const CURRENT_SCALE: Option<f32> = Some(40.0);
 
     #[cfg(feature = "logging")]
     pub type USBPeripheral = super::peripherals::USB;
 
     super::bind_interrupts!(pub struct Irqs {
-        UART1_IRQ => super::UartHandler<SbusUartPeripheral>;
+        UART1_IRQ => super::BufferedUartHandler<RcUartPeripheral>;
         PIO0_IRQ_0 => super::PioHandler<DshotPioPeripheral>;
+        PIO1_IRQ_0 => super::PioHandler<DshotExtPioPeripheral>;
+        ADC_IRQ_FIFO => super::AdcHandler;
         I2C0_IRQ => super::I2CHandler<I2cPeripheral>;
     });
 }
 
 pub use device_impl::*;
 
-pub struct Sbus {
-    pub uart: Peri<'static, SbusUartPeripheral>,
-    pub rx: Peri<'static, SbusUartPin>,
-    pub dma: Peri<'static, SbusDmaChannel>,
+pub struct Rc {
+    pub uart: Peri<'static, RcUartPeripheral>,
+    pub rx: Peri<'static, RcPin>,
 }
 
 pub struct I2c {
@@ -79,13 +113,30 @@ pub struct Dshot {
     pub m2: Peri<'static, DshotPioM2Pin>,
     pub m3: Peri<'static, DshotPioM3Pin>,
     pub m4: Peri<'static, DshotPioM4Pin>,
+    pub ext_pio: Peri<'static, DshotExtPioPeripheral>,
+    pub m5: Peri<'static, DshotPioM5Pin>,
+    pub m6: Peri<'static, DshotPioM6Pin>,
+}
+
+pub struct Servo {
+    pub slice: Peri<'static, ServoPwmSlice>,
+    pub pin: Peri<'static, ServoPin>,
+}
+
+pub struct Battery {
+    pub adc: Peri<'static, AdcPeripheral>,
+    pub vbat: Peri<'static, VbatPin>,
+    pub current: Peri<'static, CurrentPin>,
 }
 
 pub struct Device {
     pub core1: Peri<'static, Core1Peripheral>,
-    pub rc: Sbus,
+    pub flash: Peri<'static, FlashPeripheral>,
+    pub rc: Rc,
     pub imu: I2c,
     pub motors: Dshot,
+    pub servo: Servo,
+    pub battery: Battery,
     #[cfg(feature = "logging")]
     pub usb: Peri<'static, USBPeripheral>,
 }
@@ -95,10 +146,10 @@ impl Device {
     pub fn new(p: embassy_rp::Peripherals) -> Device {
         Device {
             core1: p.CORE1,
-            rc: Sbus {
+            flash: p.FLASH,
+            rc: Rc {
                 uart: p.UART1,
                 rx: p.PIN_9,
-                dma: p.DMA_CH1,
             },
             imu: I2c {
                 i2c: p.I2C1,
@@ -111,6 +162,18 @@ impl Device {
                 m2: p.PIN_13,
                 m3: p.PIN_12,
                 m4: p.PIN_11,
+                ext_pio: p.PIO1,
+                m5: p.PIN_24,
+                m6: p.PIN_25,
+            },
+            servo: Servo {
+                slice: p.PWM_SLICE3,
+                pin: p.PIN_6,
+            },
+            battery: Battery {
+                adc: p.ADC,
+                vbat: p.PIN_26,
+                current: p.PIN_27,
             },
 
             #[cfg(feature = "logging")]
@@ -121,10 +184,10 @@ impl Device {
     pub fn new(p: embassy_rp::Peripherals) -> Device {
         Device {
             core1: p.CORE1,
-            rc: Sbus {
+            flash: p.FLASH,
+            rc: Rc {
                 uart: p.UART1,
                 rx: p.PIN_5,
-                dma: p.DMA_CH1,
             },
             imu: I2c {
                 i2c: p.I2C0,
@@ -137,6 +200,18 @@ impl Device {
                 m2: p.PIN_20,
                 m3: p.PIN_21,
                 m4: p.PIN_11,
+                ext_pio: p.PIO1,
+                m5: p.PIN_14,
+                m6: p.PIN_15,
+            },
+            servo: Servo {
+                slice: p.PWM_SLICE0,
+                pin: p.PIN_16,
+            },
+            battery: Battery {
+                adc: p.ADC,
+                vbat: p.PIN_26,
+                current: p.PIN_27,
             },
 
             #[cfg(feature = "logging")]
@@ -1,22 +1,29 @@
-use crate::consts::{ACC_OFFSET, ACC_SCALE, CALIBRATION_TICKS, TICK_HZ};
-use crate::{arming::DISARMED, setup};
+use crate::consts::{CALIBRATION_TICKS, TICK_HZ};
+use crate::motors::MOTOR_RPM;
+use crate::{rl_log, setup};
 use drone_consts::telemetry::Category;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Watch};
+use drone_flight::arming::DISARMED;
+use drone_flight::dyn_notch::{Analyser, DynNotch, GyroWindow, Peaks, Window};
+pub use drone_flight::imu::ImuData;
+use drone_flight::rpm_filter::RpmFilter;
+use drone_flight::ticks::TickMonitor;
+use embassy_futures::yield_now;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal, watch::Watch};
 use embassy_time::{Duration, Instant, Ticker, Timer};
 use nalgebra::Vector3;
 
-#[derive(Clone)]
-pub struct ImuData {
-    pub gyro: Vector3<f32>,
-    pub acc: Vector3<f32>,
-    pub mag: Vector3<f32>,
-    pub dt: f32,
-}
-
 pub static IMU_DATA: Watch<CriticalSectionRawMutex, ImuData, 1> = Watch::new();
+static GYRO_WINDOW: Signal<CriticalSectionRawMutex, Window> = Signal::new();
+static GYRO_PEAKS: Signal<CriticalSectionRawMutex, Peaks> = Signal::new();
 
 #[embassy_executor::task]
-pub async fn imu_task(mut imu: setup::ImuReader) -> ! {
+pub async fn imu_task(
+    mut imu: setup::ImuReader,
+    acc_offset: Vector3<f32>,
+    acc_scale: Vector3<f32>,
+    mut rpm_filter: RpmFilter,
+    mut dyn_notch: DynNotch,
+) -> ! {
     Timer::after_secs(3).await;
 
     let mut loop_ticker = Ticker::every(Duration::from_hz(TICK_HZ));
@@ -25,7 +32,10 @@ pub async fn imu_task(mut imu: setup::ImuReader) -> ! {
     let mut gyr_bias: Vector3<f32> = Vector3::zeros();
 
     let imu_sender = IMU_DATA.sender();
+    let mut rpm_reader = MOTOR_RPM.receiver().unwrap();
+    let mut gyro_window = GyroWindow::new();
     let mut last_time = Instant::now();
+    let mut ticks = TickMonitor::new(TICK_HZ);
 
     loop {
         let Ok(imudata) = imu.read_6dof().await else {
@@ -37,6 +47,9 @@ pub async fn imu_task(mut imu: setup::ImuReader) -> ! {
         let elapsed = now.duration_since(last_time);
         last_time = now;
         let dt = elapsed.as_micros() as f32 / 1_000_000.0;
+        if ticks.tick(now.as_micros()) > 0 {
+            rl_log!(100, "IMU loop missed {} ticks", ticks.missed());
+        }
 
         if DISARMED.try_take().is_some() {
             log::info!("Calibration reset requested");
@@ -70,8 +83,24 @@ pub async fn imu_task(mut imu: setup::ImuReader) -> ! {
                 Vector3::<f32>::zeros()
             };
 
-            let corrected_gyr = Vector3::from(imudata.gyr) - gyr_bias;
-            let corrected_acc = (Vector3::from(imudata.acc) - ACC_OFFSET).component_mul(&ACC_SCALE);
+            if let Some(rpm) = rpm_reader.try_changed() {
+                rpm_filter.update(&rpm);
+            }
+            // Motor noise comes out before anything downstream sees the gyro, from
+            // the RPM telemetry when the ESCs send it, the gyro spectrum otherwise
+            let gyr = Vector3::from(imudata.gyr) - gyr_bias;
+            let corrected_gyr = if rpm_filter.is_active() {
+                rpm_filter.apply(gyr)
+            } else {
+                if let Some(window) = gyro_window.push(&gyr) {
+                    GYRO_WINDOW.signal(window);
+                }
+                if let Some(peaks) = GYRO_PEAKS.try_take() {
+                    dyn_notch.update(&peaks);
+                }
+                dyn_notch.apply(gyr)
+            };
+            let corrected_acc = (Vector3::from(imudata.acc) - acc_offset).component_mul(&acc_scale);
 
             #[rustfmt::skip]
             tele!(Category::Imu,
@@ -90,3 +119,16 @@ pub async fn imu_task(mut imu: setup::ImuReader) -> ! {
         loop_ticker.next().await;
     }
 }
+
+/// Finds the gyro noise peaks for the dynamic notch, a slice at a time so the IMU
+/// task on the same core keeps its timing.
+#[embassy_executor::task]
+pub async fn spectrum_task(mut analyser: Analyser) -> ! {
+    loop {
+        analyser.start(&GYRO_WINDOW.wait().await);
+        while !analyser.step() {
+            yield_now().await;
+        }
+        GYRO_PEAKS.signal(*analyser.peaks());
+    }
+}
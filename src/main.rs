@@ -2,90 +2,96 @@
 #![no_main]
 
 #[macro_use]
-mod telemetry;
+extern crate drone_flight;
 
-mod alt_estimator;
-mod alt_hold;
-mod arming;
-mod attitude;
 mod baro;
+mod battery;
+mod bidir_dshot;
+mod blackbox;
 mod consts;
 mod device;
 mod imu;
 mod logs;
-mod motor;
-mod pid;
+#[cfg(feature = "logging")]
+mod mavlink;
+mod motors;
+#[cfg(feature = "logging")]
+mod msp;
+mod params;
 mod rc;
 mod setup;
-mod switch;
+mod storage;
 
 #[cfg(feature = "logging")]
 mod usb;
 
-use alt_estimator::AltitudeEstimator;
-use alt_hold::AltHold;
-use arming::Arming;
-use attitude::Attitude;
-use consts::{CYCLE_TIME, TICK_HZ};
-use drone_consts::telemetry::Category;
-use embassy_dshot::{Command, DshotPioTrait};
+use consts::TICK_HZ;
+use drone_flight::{flight::FlightController, mixer::Outputs, ticks::TickMonitor};
 use embassy_executor::Spawner;
-use embassy_time::{Duration, Ticker};
+use embassy_time::{Duration, Instant, Ticker};
 use panic_probe as _;
-use rc::RcData;
-use switch::{Switch, SwitchState};
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
-    let mut dshot = setup::connect(spawner).await;
+    let (mut motors, params) = setup::connect(spawner).await;
 
     let mut loop_ticker = Ticker::every(Duration::from_hz(TICK_HZ));
-    let mut motor = motor::MotorInput::new(CYCLE_TIME);
-    let mut arming = Switch::<Arming>::new();
-    let mut alt_hold = Switch::<AltHold>::new();
+    let mut ticks = TickMonitor::new(TICK_HZ);
+    let mut flight = FlightController::new(&params);
+    let mut logger = blackbox::Logger::new(&params);
+    #[cfg(feature = "logging")]
+    let snapshot_sender = usb::SNAPSHOT.sender();
     let mut rc_reader = rc::RC_DATA.receiver().unwrap();
     let mut imu_reader = imu::IMU_DATA.receiver().unwrap();
     let mut alt_reader = baro::ALT_DATA.receiver().unwrap();
-    let mut att_transformer = Attitude::new();
-    let mut alt_estimator = AltitudeEstimator::new();
-
-    const ZERO_RC: RcData = RcData::from_channels([0; 16]);
+    let mut battery_reader = battery::BATTERY.receiver().unwrap();
+    let rpm_sender = motors::MOTOR_RPM.sender();
 
     loop {
+        if ticks.tick(Instant::now().as_micros()) > 0 {
+            logger.overrun();
+            rl_log!(100, "Flight loop missed {} ticks", ticks.missed());
+        }
+
         let imu = imu_reader.try_get();
         let rc = rc_reader.try_get();
         let baro_alt = alt_reader.try_get();
+        let battery = battery_reader.try_get();
 
-        let rc_ref = rc.as_ref().unwrap_or(&ZERO_RC);
-        arming.update(rc_ref, rc.is_some());
-        alt_hold.update(rc_ref, arming.state() == SwitchState::Active);
-
-        let throttle = if let (Some(imu), Some(rc), Some(baro_alt)) = (imu, rc, baro_alt) {
-            att_transformer
-                .update(&imu.gyro, &imu.acc, &imu.mag, imu.dt)
-                .map(|quat| {
-                    let alt = alt_estimator.update(&quat, &imu, baro_alt);
-                    let att: [f32; 3] = quat.euler_angles().into();
-                    tele!(Category::Attitude, att[0], att[1], att[2], alt);
-
-                    motor.update(
-                        &rc,
-                        &imu,
-                        &att,
-                        alt,
-                        arming.state() == SwitchState::Active,
-                        alt_hold.state() == SwitchState::Active,
-                    )
-                })
-        } else {
-            None
-        };
+        if !flight.is_armed() && params::PARAMS_CHANGED.try_take().is_some() {
+            let params = params::current();
+            flight.apply_params(&params);
+            logger.apply_params(&params);
+            log::info!("Params applied");
+        }
 
-        match (throttle, arming.state()) {
-            (Some(t), SwitchState::Active) => dshot.throttle_clamp(t).unwrap_or_default(),
-            _ => dshot.send_command(Command::MotorStop),
+        match flight.update(imu, rc, baro_alt, motors.erpm(), battery.as_ref()) {
+            Some(outputs) => motors.output(&outputs),
+            None => match bench_motors(&flight) {
+                Some(outputs) => motors.output(&outputs),
+                None => motors.stop(),
+            },
         }
+        rpm_sender.send(*flight.rpm());
+        logger.log(flight.snapshot());
+        #[cfg(feature = "logging")]
+        snapshot_sender.send(flight.snapshot().clone());
 
         loop_ticker.next().await;
     }
 }
+
+/// Motor test values from the configurator, never while armed.
+#[cfg(feature = "logging")]
+fn bench_motors(flight: &FlightController) -> Option<Outputs> {
+    if flight.is_armed() {
+        return None;
+    }
+    let motors = msp::motor_test()?;
+    Some(Outputs { motors, servo: 0 })
+}
+
+#[cfg(not(feature = "logging"))]
+fn bench_motors(_flight: &FlightController) -> Option<Outputs> {
+    None
+}
@@ -0,0 +1,198 @@
+#![cfg(feature = "logging")]
+
+use crate::motors::MOTOR_RPM;
+use crate::params::{self, PARAMS, PARAMS_CHANGED};
+use crate::storage::FlashParamStorage;
+use crate::usb::{self, SNAPSHOT};
+use crate::{baro::ALT_DATA, battery::BATTERY, imu::IMU_DATA, rc::RC_DATA};
+use drone_flight::arming::{ARM_REQUEST, ARM_RESULT, ARMED};
+use drone_flight::battery::Level;
+use drone_flight::blackbox::modes;
+use drone_flight::mavlink::{
+    self, Action, MAV_CMD_COMPONENT_ARM_DISARM, MAV_CMD_PREFLIGHT_STORAGE, MAV_RESULT_ACCEPTED,
+    MAV_RESULT_DENIED, MAV_RESULT_FAILED, MAV_RESULT_TEMPORARILY_REJECTED, MAX_FRAME, Message,
+    Outgoing, sensors,
+};
+use drone_flight::motor::dshot_to_us;
+use drone_flight::params::PARAM_COUNT;
+use drone_flight::prearm::PREARM_FAILURES;
+use embassy_time::{Duration, Instant, Ticker, with_timeout};
+use portable_atomic::{AtomicBool, AtomicU8, Ordering};
+
+const STREAM_HZ: u64 = 10;
+const ARM_TIMEOUT_MS: u64 = 100;
+
+const SENSORS_PRESENT: u32 = sensors::GYRO
+    | sensors::ACCEL
+    | sensors::MAG
+    | sensors::ABSOLUTE_PRESSURE
+    | sensors::ANGULAR_RATE_CONTROL
+    | sensors::ATTITUDE_STABILIZATION
+    | sensors::Z_ALTITUDE_CONTROL
+    | sensors::MOTOR_OUTPUTS
+    | sensors::RC_RECEIVER
+    | sensors::PREARM_CHECK
+    | sensors::BATTERY;
+
+// Streaming starts with the first MAVLink frame from the host
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static SEQ: AtomicU8 = AtomicU8::new(0);
+
+async fn send(message: &Outgoing<'_>) {
+    let mut frame = [0u8; MAX_FRAME];
+    let len = message.encode(SEQ.fetch_add(1, Ordering::Relaxed), &mut frame);
+    usb::send_frame(&frame[..len]).await;
+}
+
+async fn send_param(index: usize) {
+    if let Some(value) = PARAMS.lock(|params| params.borrow().get(index)) {
+        send(&Outgoing::ParamValue { index, value }).await;
+    }
+}
+
+async fn ack(command: u16, result: u8) {
+    send(&Outgoing::CommandAck { command, result }).await;
+}
+
+pub async fn handle_message(message: Message) {
+    ACTIVE.store(true, Ordering::Relaxed);
+    let armed = ARMED.load(Ordering::Relaxed);
+    let action = PARAMS.lock(|params| mavlink::handle(&message, &mut params.borrow_mut(), armed));
+
+    match action {
+        Action::None => {}
+        Action::SendParam(index) => send_param(index).await,
+        Action::SendAllParams => {
+            for index in 0..PARAM_COUNT {
+                send_param(index).await;
+            }
+        }
+        Action::Apply(index) => {
+            PARAMS_CHANGED.signal(());
+            send_param(index).await;
+        }
+        Action::Save => {
+            let result = match params::current().save(&mut FlashParamStorage) {
+                Ok(()) => MAV_RESULT_ACCEPTED,
+                Err(e) => {
+                    log::error!("Params save failed: {:?}", e);
+                    MAV_RESULT_FAILED
+                }
+            };
+            ack(MAV_CMD_PREFLIGHT_STORAGE, result).await;
+        }
+        Action::Arm(on) => {
+            ARM_RESULT.reset();
+            ARM_REQUEST.signal(on);
+            let timeout = Duration::from_millis(ARM_TIMEOUT_MS);
+            let result = match with_timeout(timeout, ARM_RESULT.wait()).await {
+                Ok(true) => MAV_RESULT_ACCEPTED,
+                Ok(false) => MAV_RESULT_DENIED,
+                Err(_) => MAV_RESULT_TEMPORARILY_REJECTED,
+            };
+            ack(MAV_CMD_COMPONENT_ARM_DISARM, result).await;
+        }
+        Action::Ack(command, result) => ack(command, result).await,
+    }
+}
+
+pub async fn stream_task() {
+    let mut ticker = Ticker::every(Duration::from_hz(STREAM_HZ));
+    let mut tick: u64 = 0;
+
+    loop {
+        ticker.next().await;
+        if !ACTIVE.load(Ordering::Relaxed) {
+            continue;
+        }
+        let Some(snapshot) = SNAPSHOT.try_get() else {
+            continue;
+        };
+        let now = Instant::now();
+        let time_ms = now.as_millis() as u32;
+        let imu = IMU_DATA.try_get();
+        let rc = RC_DATA.try_get();
+
+        let rates = imu.as_ref().map_or([0.0; 3], |imu| imu.gyro.into());
+        send(&Outgoing::Attitude {
+            time_ms,
+            att: snapshot.att,
+            rates,
+        })
+        .await;
+
+        if tick.is_multiple_of(2) {
+            let channels = rc.as_ref().map(|rc| rc.channels_us());
+            let channels_us: &[u16] = match &channels {
+                Some(channels) => channels,
+                None => &[],
+            };
+            send(&Outgoing::RcChannels {
+                time_ms,
+                channels_us,
+                rssi: if rc.is_some() { 255 } else { 0 },
+            })
+            .await;
+            send(&Outgoing::ServoOutputRaw {
+                time_us: now.as_micros() as u32,
+                outputs_us: snapshot.motors.map(dshot_to_us),
+            })
+            .await;
+            let rpm = MOTOR_RPM.try_get().unwrap_or_default();
+            for (index, rpm) in rpm.iter().enumerate() {
+                if let Some(rpm) = *rpm {
+                    send(&Outgoing::RawRpm {
+                        index: index as u8,
+                        rpm,
+                    })
+                    .await;
+                }
+            }
+            let yaw = snapshot.att[2].to_degrees();
+            let heading = if yaw < 0.0 { yaw + 360.0 } else { yaw };
+            send(&Outgoing::VfrHud {
+                alt: ALT_DATA.try_get().unwrap_or(snapshot.alt),
+                climb: 0.0,
+                heading: heading as i16,
+                throttle: (snapshot.rc[2] * 100.0) as u16,
+            })
+            .await;
+        }
+
+        if tick.is_multiple_of(STREAM_HZ) {
+            send(&Outgoing::Heartbeat {
+                armed: snapshot.modes & modes::ARMED != 0,
+                custom_mode: snapshot.modes as u32,
+            })
+            .await;
+            let mut healthy = SENSORS_PRESENT;
+            if rc.is_none() {
+                healthy &= !sensors::RC_RECEIVER;
+            }
+            if imu.is_none() {
+                healthy &= !(sensors::GYRO | sensors::ACCEL | sensors::MAG);
+            }
+            if PREARM_FAILURES.load(Ordering::Relaxed) != 0 {
+                healthy &= !sensors::PREARM_CHECK;
+            }
+            if snapshot.modes & modes::MOTOR_FAULT != 0 {
+                healthy &= !sensors::MOTOR_OUTPUTS;
+            }
+            let battery = BATTERY.try_get();
+            if battery.is_none_or(|b| b.level != Level::Ok) {
+                healthy &= !sensors::BATTERY;
+            }
+            send(&Outgoing::SysStatus {
+                present: SENSORS_PRESENT,
+                healthy,
+                voltage_mv: battery.map_or(u16::MAX, |b| (b.voltage * 1000.0) as u16),
+                current_ca: battery
+                    .and_then(|b| b.current)
+                    .map_or(-1, |amps| (amps * 100.0) as i16),
+            })
+            .await;
+        }
+
+        tick += 1;
+    }
+}
@@ -0,0 +1,101 @@
+use crate::bidir_dshot::BidirDshot;
+use crate::device::{DshotExtPioPeripheral, DshotPioPeripheral};
+use drone_flight::esc::{Erpm, Rpm};
+use drone_flight::mixer::{MAX_MOTORS, Outputs};
+use embassy_dshot::{Command, DshotPioTrait, rp::DshotPio};
+use embassy_rp::pwm::{self, Pwm};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Watch};
+
+// 1 us per PWM count at SYSTEM_FREQ, 50 Hz frames
+const SERVO_DIVIDER: u8 = (crate::consts::SYSTEM_FREQ / 1_000_000) as u8;
+const SERVO_PERIOD_US: u16 = 20_000;
+
+/// Motor RPM from bidirectional DShot, published by the flight loop every tick.
+pub static MOTOR_RPM: Watch<CriticalSectionRawMutex, Rpm, 1> = Watch::new();
+
+/// Motor pins 1-4 on one PIO, 5-6 on the other unless PPM has it, one-way or bidirectional.
+pub enum Escs {
+    Dshot {
+        dshot: DshotPio<'static, 4, DshotPioPeripheral>,
+        dshot_ext: Option<DshotPio<'static, 2, DshotExtPioPeripheral>>,
+    },
+    Bidir {
+        dshot: BidirDshot<'static, DshotPioPeripheral, 4>,
+        dshot_ext: Option<BidirDshot<'static, DshotExtPioPeripheral, 2>>,
+    },
+}
+
+/// The ESCs and the tricopter yaw servo.
+pub struct Motors {
+    escs: Escs,
+    erpm: Erpm,
+    servo: Pwm<'static>,
+    servo_config: pwm::Config,
+}
+
+impl Motors {
+    pub fn new(escs: Escs, servo: crate::device::Servo) -> Motors {
+        let mut servo_config = pwm::Config::default();
+        servo_config.divider = SERVO_DIVIDER.into();
+        servo_config.top = SERVO_PERIOD_US - 1;
+        servo_config.compare_a = 0;
+        Motors {
+            escs,
+            erpm: [None; MAX_MOTORS],
+            servo: Pwm::new_output_a(servo.slice, servo.pin, servo_config.clone()),
+            servo_config,
+        }
+    }
+
+    pub fn output(&mut self, outputs: &Outputs) {
+        let [m1, m2, m3, m4, m5, m6] = outputs.motors;
+        match &mut self.escs {
+            Escs::Dshot { dshot, dshot_ext } => {
+                dshot.throttle_clamp([m1, m2, m3, m4]).unwrap_or_default();
+                if let Some(dshot_ext) = dshot_ext {
+                    dshot_ext.throttle_clamp([m5, m6]).unwrap_or_default();
+                }
+            }
+            Escs::Bidir { dshot, dshot_ext } => {
+                let [e1, e2, e3, e4] = dshot.throttle_clamp([m1, m2, m3, m4]);
+                let [e5, e6] = dshot_ext
+                    .as_mut()
+                    .map_or([None; 2], |ext| ext.throttle_clamp([m5, m6]));
+                self.erpm = [e1, e2, e3, e4, e5, e6];
+            }
+        }
+        self.set_servo(outputs.servo);
+    }
+
+    /// Stops every motor and the servo pulses.
+    pub fn stop(&mut self) {
+        match &mut self.escs {
+            Escs::Dshot { dshot, dshot_ext } => {
+                dshot.send_command(Command::MotorStop);
+                if let Some(dshot_ext) = dshot_ext {
+                    dshot_ext.send_command(Command::MotorStop);
+                }
+            }
+            Escs::Bidir { dshot, dshot_ext } => {
+                let [e1, e2, e3, e4] = dshot.send_command(Command::MotorStop);
+                let [e5, e6] = dshot_ext
+                    .as_mut()
+                    .map_or([None; 2], |ext| ext.send_command(Command::MotorStop));
+                self.erpm = [e1, e2, e3, e4, e5, e6];
+            }
+        }
+        self.set_servo(0);
+    }
+
+    /// eRPM the ESCs answered the last frames with, all `None` on one-way DShot.
+    pub fn erpm(&self) -> &Erpm {
+        &self.erpm
+    }
+
+    fn set_servo(&mut self, us: u16) {
+        if self.servo_config.compare_a != us {
+            self.servo_config.compare_a = us;
+            self.servo.set_config(&self.servo_config);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+#![cfg(feature = "logging")]
+
+use crate::usb::{self, SNAPSHOT};
+use crate::{baro::ALT_DATA, imu::IMU_DATA, rc::RC_DATA};
+use core::cell::Cell;
+use drone_flight::arming::ARMED;
+use drone_flight::mixer::MAX_MOTORS;
+use drone_flight::msp::{self, Action, MAX_FRAME, Request, Response, State};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant};
+use portable_atomic::Ordering;
+
+// Motors stop on their own if the configurator stops refreshing the test values
+const MOTOR_TEST_TIMEOUT_MS: u64 = 500;
+
+static MOTOR_TEST: Mutex<CriticalSectionRawMutex, Cell<Option<([u16; MAX_MOTORS], Instant)>>> =
+    Mutex::new(Cell::new(None));
+
+/// DShot values of a running bench motor test.
+pub fn motor_test() -> Option<[u16; MAX_MOTORS]> {
+    let (motors, at) = MOTOR_TEST.lock(|test| test.get())?;
+    if at.elapsed() > Duration::from_millis(MOTOR_TEST_TIMEOUT_MS) {
+        MOTOR_TEST.lock(|test| test.set(None));
+        return None;
+    }
+    Some(motors)
+}
+
+pub async fn send_response(response: &Response) {
+    let mut frame = [0u8; MAX_FRAME];
+    let len = response.encode(&mut frame);
+    usb::send_frame(&frame[..len]).await;
+}
+
+pub async fn handle_request(request: Request) {
+    let armed = ARMED.load(Ordering::Relaxed);
+    let snapshot = SNAPSHOT.try_get().unwrap_or_default();
+    let imu = IMU_DATA.try_get();
+    let rc = RC_DATA.try_get();
+    let state = State {
+        imu: imu.as_ref(),
+        rc: rc.as_ref(),
+        alt: ALT_DATA.try_get(),
+        snapshot: &snapshot,
+    };
+
+    let (response, action) = msp::handle(&request, &state, armed);
+    if let Action::SetMotors(motors) = action {
+        let test = motors
+            .iter()
+            .any(|m| *m > 0)
+            .then(|| (motors, Instant::now()));
+        MOTOR_TEST.lock(|cell| cell.set(test));
+    }
+    send_response(&response).await;
+}
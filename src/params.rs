@@ -0,0 +1,19 @@
+use core::cell::RefCell;
+use drone_flight::params::Params;
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+
+/// Live copy of the params, edited over USB and picked up by the flight loop while disarmed.
+pub static PARAMS: Mutex<CriticalSectionRawMutex, RefCell<Params>> =
+    Mutex::new(RefCell::new(Params::defaults()));
+pub static PARAMS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn current() -> Params {
+    PARAMS.lock(|params| params.borrow().clone())
+}
+
+pub fn replace(new_params: &Params) {
+    PARAMS.lock(|params| *params.borrow_mut() = new_params.clone());
+}
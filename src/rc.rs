@@ -1,124 +1,201 @@
-use crate::consts::{ALT_KD_MAX, ALT_KD_MIN, ALT_KP_MAX, ALT_KP_MIN, RC_MAX, RC_MIN};
+use crate::consts::SYSTEM_FREQ;
 use crate::setup;
 use drone_consts::telemetry::Category;
+pub use drone_flight::rc::RcData;
+use drone_flight::rc_protocol::{Crsf, Ibus, Ppm, Protocol, RcFrame, RcProtocol, Sbus, Srxl2};
+use embassy_rp::gpio::Pull;
+use embassy_rp::pio::program::pio_asm;
+use embassy_rp::pio::{
+    Common, Config, Direction, Instance, Pin, Pio, ShiftConfig, ShiftDirection, StateMachine,
+};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::watch::Watch;
-use embassy_time::{Duration, with_timeout};
+use embassy_sync::watch::{Sender, Watch};
+use embassy_time::{Duration, Instant, with_timeout};
+use embedded_io_async::Read;
+use fixed::FixedU32;
+use fixed::types::extra::U8;
 
-pub static RC_DATA: Watch<CriticalSectionRawMutex, RcData, 1> = Watch::new();
-
-#[derive(Clone)]
-pub struct RcData([u16; 16]);
+// PPM loops take 2 PIO cycles per count
+const PPM_PIO_HZ: u64 = 10_000_000;
+const PPM_COUNTS_PER_US: u32 = (PPM_PIO_HZ / 2 / 1_000_000) as u32;
 
-impl RcData {
-    pub const fn from_channels(channels: [u16; 16]) -> RcData {
-        RcData(channels)
-    }
+const RC_TIMEOUT: Duration = Duration::from_millis(100);
 
-    pub fn roll(&self) -> f32 {
-        Self::normalize(self.0[0], RC_MIN, RC_MAX, -1.0, 1.0)
-    }
-
-    pub fn pitch(&self) -> f32 {
-        Self::normalize(self.0[1], RC_MIN, RC_MAX, -1.0, 1.0)
-    }
+pub static RC_DATA: Watch<CriticalSectionRawMutex, RcData, 1> = Watch::new();
 
-    pub fn throttle(&self) -> f32 {
-        Self::normalize(self.0[2], RC_MIN, RC_MAX, 0.0, 1.0)
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RcError {
+    None,
+    Failsafe,
+    ReadError,
+    Timeout,
+}
 
-    pub fn yaw(&self) -> f32 {
-        Self::normalize(self.0[3], RC_MIN, RC_MAX, -1.0, 1.0)
+fn change_state(state: &mut RcError, new_state: RcError) {
+    if *state != new_state {
+        log::info!("RC error state changed from {:?} to {:?}", state, new_state);
+        *state = new_state;
     }
+}
 
-    pub fn kp_gain(&self) -> f32 {
-        Self::normalize(self.0[4], RC_MIN, RC_MAX, ALT_KP_MIN, ALT_KP_MAX)
-    }
+/// Publishes frames from any protocol on `RC_DATA`, cleared while the link is down.
+struct RcLink {
+    sender: Sender<'static, CriticalSectionRawMutex, RcData, 1>,
+    state: RcError,
+    last_frame: Instant,
+}
 
-    pub fn kd_gain(&self) -> f32 {
-        Self::normalize(self.0[5], RC_MIN, RC_MAX, ALT_KD_MIN, ALT_KD_MAX)
+impl RcLink {
+    fn new() -> RcLink {
+        RcLink {
+            sender: RC_DATA.sender(),
+            state: RcError::None,
+            last_frame: Instant::now(),
+        }
     }
 
-    pub fn arm_switch(&self) -> f32 {
-        Self::normalize(self.0[6], RC_MIN, RC_MAX, 0.0, 1.0)
+    fn frame(&mut self, frame: RcFrame) {
+        if frame.failsafe {
+            self.lost(RcError::Failsafe);
+            return;
+        }
+        change_state(&mut self.state, RcError::None);
+        self.last_frame = Instant::now();
+        let rc_data = RcData::from_channels(frame.channels);
+
+        #[rustfmt::skip]
+        tele!(
+            Category::Rc,
+            rc_data.roll(), rc_data.pitch(), rc_data.throttle(),
+            rc_data.yaw(), rc_data.kp_gain(), rc_data.kd_gain(),
+            rc_data.arm_switch(), rc_data.altitude_switch(), rc_data.acro_switch());
+
+        self.sender.send(rc_data);
     }
 
-    pub fn altitude_switch(&self) -> f32 {
-        Self::normalize(self.0[7], RC_MIN, RC_MAX, 0.0, 1.0)
+    fn lost(&mut self, error: RcError) {
+        change_state(&mut self.state, error);
+        self.sender.clear();
     }
 
-    pub fn unused(&self) -> f32 {
-        Self::normalize(self.0[8], RC_MIN, RC_MAX, 0.0, 1.0)
+    /// Bytes that never make a frame count as a lost link too.
+    fn check_timeout(&mut self) {
+        if self.last_frame.elapsed() > RC_TIMEOUT {
+            self.lost(RcError::Timeout);
+        }
     }
+}
 
-    fn normalize(
-        val: u16,
-        original_min: u16,
-        original_max: u16,
-        new_min: f32,
-        new_max: f32,
-    ) -> f32 {
-        new_min
-            + ((new_max - new_min)
-                * ((val as f32 - original_min as f32) / (original_max - original_min) as f32))
+#[embassy_executor::task]
+pub async fn rc_task(uart: setup::UartReader, protocol: Protocol) -> ! {
+    match protocol {
+        Protocol::Crsf => read_uart(uart, Crsf::new()).await,
+        Protocol::Ibus => read_uart(uart, Ibus::new()).await,
+        Protocol::Srxl2 => read_uart(uart, Srxl2::new()).await,
+        // PPM has `ppm_task`
+        Protocol::Sbus | Protocol::Ppm => read_uart(uart, Sbus::new()).await,
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum RcError {
-    None,
-    Failsafe,
-    ReadError,
-    Timeout,
-}
+async fn read_uart<P: RcProtocol<Sample = u8>>(mut uart: setup::UartReader, mut parser: P) -> ! {
+    let mut link = RcLink::new();
+    let mut read_buffer = [0u8; 64];
 
-fn change_state(state: &mut RcError, new_state: RcError) {
-    if *state != new_state {
-        log::info!("RC error state changed from {:?} to {:?}", state, new_state);
-        *state = new_state;
+    loop {
+        match with_timeout(RC_TIMEOUT, uart.read(&mut read_buffer)).await {
+            Ok(Ok(len)) => {
+                for &byte in &read_buffer[..len] {
+                    if let Some(frame) = parser.push(byte) {
+                        link.frame(frame);
+                    }
+                }
+            }
+            Ok(Err(_e)) => link.lost(RcError::ReadError),
+            Err(_) => link.lost(RcError::Timeout),
+        }
+        link.check_timeout();
     }
 }
 
 #[embassy_executor::task]
-pub async fn rc_task(mut uart: setup::UartReader) -> ! {
-    let rc_timeout = Duration::from_millis(100);
-    let mut read_buffer = [0u8; 25];
-    let mut sbusparser = sbus::SBusPacketParser::new();
-    let rc_sender = RC_DATA.sender();
-    let mut state = RcError::None;
+pub async fn ppm_task(mut reader: PpmReader<'static, crate::device::DshotExtPioPeripheral>) -> ! {
+    let mut link = RcLink::new();
+    let mut parser = Ppm::new();
 
     loop {
-        let read_result = with_timeout(rc_timeout, uart.read(&mut read_buffer)).await;
-        match read_result {
-            Ok(Ok(())) => {
-                sbusparser.push_bytes(&read_buffer);
-                if let Some(packet) = sbusparser.try_parse() {
-                    match packet.failsafe {
-                        false => {
-                            change_state(&mut state, RcError::None);
-                            let rc_data = RcData::from_channels(packet.channels);
-
-                            #[rustfmt::skip]
-                            tele!(
-                                Category::Rc,
-                                rc_data.roll(), rc_data.pitch(), rc_data.throttle(),
-                                rc_data.yaw(), rc_data.kp_gain(), rc_data.kd_gain(),
-                                rc_data.arm_switch(), rc_data.altitude_switch(), rc_data.unused());
-
-                            rc_sender.send(rc_data);
-                            continue;
-                        }
-                        true => change_state(&mut state, RcError::Failsafe),
-                    }
+        match with_timeout(RC_TIMEOUT, reader.pulse()).await {
+            Ok(us) => {
+                if let Some(frame) = parser.push(us) {
+                    link.frame(frame);
                 }
             }
-            Ok(Err(_e)) => {
-                change_state(&mut state, RcError::ReadError);
-            }
-            Err(_) => {
-                change_state(&mut state, RcError::Timeout);
-            }
+            Err(_) => link.lost(RcError::Timeout),
         }
-        rc_sender.clear();
+        link.check_timeout();
+    }
+}
+
+/// Times PPM on one state machine, from each rising edge to the next.
+pub struct PpmReader<'d, PIO: Instance> {
+    _common: Common<'d, PIO>,
+    sm: StateMachine<'d, PIO, 0>,
+}
+
+impl<'d, PIO: Instance> PpmReader<'d, PIO> {
+    /// `pin` comes from `pio.common.make_pio_pin`.
+    pub fn new(pio: Pio<'d, PIO>, mut pin: Pin<'d, PIO>) -> Self {
+        let Pio {
+            mut common,
+            mut sm0,
+            ..
+        } = pio;
+
+        // count down through the high and the low part of the pulse, push the
+        // count at the next rising edge and start over
+        let prg = pio_asm!(
+            "wait 1 pin 0"
+            ".wrap_target"
+            "mov x, ~null"
+            "high:"
+            "jmp pin, high_count"
+            "jmp low"
+            "high_count:"
+            "jmp x--, high"
+            "low:"
+            "jmp pin, edge"
+            "jmp x--, low"
+            "edge:"
+            "mov isr, ~x"
+            "push noblock"
+            ".wrap"
+        );
+        let loaded = common.load_program(&prg.program);
+        let divider = FixedU32::<U8>::from_bits((((SYSTEM_FREQ as u64) << 8) / PPM_PIO_HZ) as u32);
+
+        pin.set_pull(Pull::None);
+        let mut cfg = Config::default();
+        cfg.use_program(&loaded, &[]);
+        cfg.clock_divider = divider;
+        cfg.shift_in = ShiftConfig {
+            auto_fill: false,
+            direction: ShiftDirection::Left,
+            threshold: 32,
+        };
+        cfg.set_jmp_pin(&pin);
+        cfg.set_in_pins(&[&pin]);
+        sm0.set_config(&cfg);
+        sm0.set_pin_dirs(Direction::In, &[&pin]);
+        sm0.restart();
+        sm0.set_enable(true);
+
+        PpmReader {
+            _common: common,
+            sm: sm0,
+        }
+    }
+
+    /// Time from one rising edge to the next in us.
+    pub async fn pulse(&mut self) -> u32 {
+        self.sm.rx().wait_pull().await / PPM_COUNTS_PER_US
     }
 }
@@ -1,9 +1,22 @@
-use crate::consts::{I2C_FREQ, IMU_I2C_ADDR, SBUS_BAUD, SYSTEM_FREQ};
-use crate::{baro, device::I2cPeripheral, imu, log_and_panic, rc};
+use crate::bidir_dshot::BidirDshot;
+use crate::consts::{
+    BATTERY_HZ, CRSF_BAUD, I2C_FREQ, IBUS_BAUD, IMU_I2C_ADDR, SBUS_BAUD, SRXL2_BAUD, SYSTEM_FREQ,
+    TICK_HZ,
+};
+use crate::device::{Dshot, I2cPeripheral};
+use crate::motors::{Escs, Motors};
+use crate::rc::{self, PpmReader};
+use crate::storage::{self, FlashDriver, FlashParamStorage};
+use crate::{baro, battery, blackbox, imu, log_and_panic};
 use bmp388_embedded::{
     Address, IirFilter, OutputDataRate, Oversampling, PowerMode, SensorConfig, r#async::Bmp388Async,
 };
-use embassy_dshot::{DshotPioTrait, DshotSpeed, rp::DshotPio};
+use drone_flight::battery::BatteryMonitor;
+use drone_flight::dyn_notch::{Analyser, DynNotch};
+use drone_flight::params::Params;
+use drone_flight::rc_protocol::Protocol;
+use drone_flight::rpm_filter::RpmFilter;
+use embassy_dshot::{DshotSpeed, rp::DshotPio};
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_executor::{Executor, Spawner};
 use embassy_rp::{
@@ -11,7 +24,8 @@ use embassy_rp::{
     config::Config,
     i2c,
     multicore::Stack,
-    uart::{self, DataBits, Parity, StopBits, UartRx},
+    pio::Pio,
+    uart::{self, BufferedUartRx, DataBits, Parity, StopBits},
 };
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use embassy_time::{Delay, Timer};
@@ -28,9 +42,9 @@ pub type SharedI2cBus = Mutex<CriticalSectionRawMutex, I2cHw>;
 pub type SharedI2cDevice = I2cDevice<'static, CriticalSectionRawMutex, I2cHw>;
 pub type ImuReader = Icm20948<BusI2c<SharedI2cDevice>, icm20948_async::MagEnabled>;
 pub type BaroReader = Bmp388Async<SharedI2cDevice, Delay>;
-pub type UartReader = UartRx<'static, uart::Async>;
+pub type UartReader = BufferedUartRx;
 
-pub async fn connect(spawner: Spawner) -> impl DshotPioTrait<4> {
+pub async fn connect(spawner: Spawner) -> (Motors, Params) {
     let mut clock_cfg = ClockConfig::system_freq(SYSTEM_FREQ).unwrap();
     clock_cfg.core_voltage = CoreVoltage::V1_15;
     let mut config = Config::default();
@@ -38,27 +52,51 @@ pub async fn connect(spawner: Spawner) -> impl DshotPioTrait<4> {
     let peripherals = embassy_rp::init(config);
     let device = crate::device::Device::new(peripherals);
 
+    // Params from flash //
+    storage::init(FlashDriver::new_blocking(device.flash));
+    let params = Params::load(&mut FlashParamStorage);
+    crate::params::replace(&params);
+    spawner.spawn(blackbox::blackbox_task().unwrap());
+
     #[cfg(feature = "logging")]
     spawner.spawn(usb::usb_setup(device.usb).unwrap());
 
-    // RC via SBUS setup //
-    log::info!("// RC via SBUS setup //");
-
-    let mut sbus_uart_config = uart::Config::default();
-    sbus_uart_config.baudrate = SBUS_BAUD;
-    sbus_uart_config.data_bits = DataBits::DataBits8;
-    sbus_uart_config.stop_bits = StopBits::STOP2;
-    sbus_uart_config.parity = Parity::ParityEven;
-    sbus_uart_config.invert_rx = true;
+    // RC setup //
+    let protocol = Protocol::from_param(params.rc_protocol);
+    log::info!("// RC via {} setup //", protocol.name());
+
+    let Dshot {
+        pio,
+        m1,
+        m2,
+        m3,
+        m4,
+        ext_pio,
+        m5,
+        m6,
+    } = device.motors;
+    // PPM is timed on the second PIO, which leaves motors 5-6 without one
+    let ext_pio = if protocol == Protocol::Ppm {
+        let mut ppm_pio = Pio::new(ext_pio, crate::device::Irqs);
+        let pin = ppm_pio.common.make_pio_pin(device.rc.rx);
+        spawner.spawn(rc::ppm_task(PpmReader::new(ppm_pio, pin)).unwrap());
+        None
+    } else {
+        static RC_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+        let uart_rx = BufferedUartRx::new(
+            device.rc.uart,
+            crate::device::Irqs,
+            device.rc.rx,
+            RC_BUFFER.init([0; 256]),
+            rc_uart_config(protocol),
+        );
+        spawner.spawn(rc::rc_task(uart_rx, protocol).unwrap());
+        Some(ext_pio)
+    };
 
-    let uart_rx = embassy_rp::uart::UartRx::new(
-        device.rc.uart,
-        device.rc.rx,
-        crate::device::Irqs,
-        device.rc.dma,
-        sbus_uart_config,
-    );
-    spawner.spawn(rc::rc_task(uart_rx).unwrap());
+    // Battery via ADC setup //
+    let battery_monitor = BatteryMonitor::new(&params, BATTERY_HZ as f32);
+    spawner.spawn(battery::battery_task(device.battery, battery_monitor).unwrap());
 
     // IMU via UART setup //
     log::info!("// IMU via UART setup //");
@@ -118,6 +156,12 @@ pub async fn connect(spawner: Spawner) -> impl DshotPioTrait<4> {
 
     Timer::after_millis(10).await;
 
+    let acc_offset = params.acc_offset();
+    let acc_scale = params.acc_scale();
+    let rpm_filter = RpmFilter::new(&params, TICK_HZ as f32);
+    let dyn_notch = DynNotch::new(&params, TICK_HZ as f32);
+    let analyser = Analyser::new(&params, TICK_HZ as f32);
+
     static CORE_EXECUTOR: StaticCell<Executor> = StaticCell::new();
     static CORE_STACK: StaticCell<Stack<16384>> = StaticCell::new();
 
@@ -125,21 +169,76 @@ pub async fn connect(spawner: Spawner) -> impl DshotPioTrait<4> {
         let executor = CORE_EXECUTOR.init(Executor::new());
         executor.run(|spawner| {
             spawner.spawn(baro::baro_task(baro).unwrap());
-            spawner.spawn(imu::imu_task(imu).unwrap());
+            spawner
+                .spawn(imu::imu_task(imu, acc_offset, acc_scale, rpm_filter, dyn_notch).unwrap());
+            spawner.spawn(imu::spectrum_task(analyser).unwrap());
         })
     });
 
     // Motors via DSHOT setup //
     log::info!("// Motors via DSHOT setup //");
 
-    DshotPio::<4, _>::new(
-        device.motors.pio,
-        crate::device::Irqs,
-        //                // My ECS    // 'X' in PX4   // Place
-        device.motors.m1, // M4        // M1           // Front Right
-        device.motors.m2, // M1        // M2           // Back Left
-        device.motors.m3, // M2        // M3           // Front Left
-        device.motors.m4, // M3        // M4           // Back Right
-        DshotSpeed::DShot600,
-    )
+    // Pins in mixer order, which motor sits where depends on the `mixer` param
+    //                 My ECS    'X' in PX4   Place
+    // m1              M4        M1           Front Right
+    // m2              M1        M2           Back Left
+    // m3              M2        M3           Front Left
+    // m4              M3        M4           Back Right
+    let escs = if params.dshot_bidir != 0.0 {
+        log::info!("Bidirectional DShot");
+        let mut pio = Pio::new(pio, crate::device::Irqs);
+        let pins = [
+            pio.common.make_pio_pin(m1),
+            pio.common.make_pio_pin(m2),
+            pio.common.make_pio_pin(m3),
+            pio.common.make_pio_pin(m4),
+        ];
+        Escs::Bidir {
+            dshot: BidirDshot::new(pio, pins),
+            dshot_ext: ext_pio.map(|ext_pio| {
+                let mut ext_pio = Pio::new(ext_pio, crate::device::Irqs);
+                let ext_pins = [
+                    ext_pio.common.make_pio_pin(m5),
+                    ext_pio.common.make_pio_pin(m6),
+                ];
+                BidirDshot::new(ext_pio, ext_pins)
+            }),
+        }
+    } else {
+        Escs::Dshot {
+            dshot: DshotPio::<4, _>::new(
+                pio,
+                crate::device::Irqs,
+                m1,
+                m2,
+                m3,
+                m4,
+                DshotSpeed::DShot600,
+            ),
+            dshot_ext: ext_pio.map(|ext_pio| {
+                DshotPio::<2, _>::new(ext_pio, crate::device::Irqs, m5, m6, DshotSpeed::DShot600)
+            }),
+        }
+    };
+
+    (Motors::new(escs, device.servo), params)
+}
+
+fn rc_uart_config(protocol: Protocol) -> uart::Config {
+    let mut config = uart::Config::default();
+    config.data_bits = DataBits::DataBits8;
+    config.stop_bits = StopBits::STOP1;
+    config.parity = Parity::ParityNone;
+    match protocol {
+        Protocol::Crsf => config.baudrate = CRSF_BAUD,
+        Protocol::Ibus => config.baudrate = IBUS_BAUD,
+        Protocol::Srxl2 => config.baudrate = SRXL2_BAUD,
+        Protocol::Sbus | Protocol::Ppm => {
+            config.baudrate = SBUS_BAUD;
+            config.stop_bits = StopBits::STOP2;
+            config.parity = Parity::ParityEven;
+            config.invert_rx = true;
+        }
+    }
+    config
 }
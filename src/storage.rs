@@ -0,0 +1,64 @@
+use crate::consts::{BLACKBOX_OFFSET, BLACKBOX_SECTORS, FLASH_SIZE, PARAMS_OFFSET};
+use crate::device::FlashPeripheral;
+use core::cell::RefCell;
+use drone_flight::blackbox::{BlackboxError, BlackboxStorage, SECTOR_SIZE};
+use drone_flight::params::{ParamError, ParamStorage};
+use embassy_rp::flash::{Blocking, ERASE_SIZE, Error, Flash};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+pub type FlashDriver = Flash<'static, FlashPeripheral, Blocking, FLASH_SIZE>;
+
+/// One driver shared by the params and the blackbox, set up once by `init`.
+static FLASH: Mutex<CriticalSectionRawMutex, RefCell<Option<FlashDriver>>> =
+    Mutex::new(RefCell::new(None));
+
+pub fn init(flash: FlashDriver) {
+    FLASH.lock(|cell| *cell.borrow_mut() = Some(flash));
+}
+
+fn with_flash<R>(f: impl FnOnce(&mut FlashDriver) -> Result<R, Error>) -> Result<R, Error> {
+    FLASH.lock(|cell| match cell.borrow_mut().as_mut() {
+        Some(flash) => f(flash),
+        None => Err(Error::Other),
+    })
+}
+
+pub struct FlashParamStorage;
+
+impl ParamStorage for FlashParamStorage {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), ParamError> {
+        with_flash(|flash| flash.blocking_read(PARAMS_OFFSET, buf)).map_err(|_| ParamError::Storage)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ParamError> {
+        with_flash(|flash| {
+            flash.blocking_erase(PARAMS_OFFSET, PARAMS_OFFSET + ERASE_SIZE as u32)?;
+            flash.blocking_write(PARAMS_OFFSET, data)
+        })
+        .map_err(|_| ParamError::Storage)
+    }
+}
+
+pub struct FlashBlackboxStorage;
+
+impl BlackboxStorage for FlashBlackboxStorage {
+    fn sectors(&self) -> usize {
+        BLACKBOX_SECTORS
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), BlackboxError> {
+        with_flash(|flash| flash.blocking_read(BLACKBOX_OFFSET + offset, buf))
+            .map_err(|_| BlackboxError::Storage)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), BlackboxError> {
+        with_flash(|flash| flash.blocking_write(BLACKBOX_OFFSET + offset, data))
+            .map_err(|_| BlackboxError::Storage)
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), BlackboxError> {
+        let from = BLACKBOX_OFFSET + (sector * SECTOR_SIZE) as u32;
+        with_flash(|flash| flash.blocking_erase(from, from + SECTOR_SIZE as u32))
+            .map_err(|_| BlackboxError::Storage)
+    }
+}
@@ -1,11 +1,22 @@
 #![cfg(feature = "logging")]
 
-use crate::consts::{USB_PID, USB_VID};
+use crate::blackbox::{ERASE, RECORDING};
+use crate::consts::{BLACKBOX_SECTORS, USB_PID, USB_VID};
+use crate::params::{self, PARAMS, PARAMS_CHANGED};
+use crate::storage::{FlashBlackboxStorage, FlashParamStorage};
 use drone_consts::telemetry::Category;
-use embassy_futures::join::{join, join3};
+use drone_flight::arming::ARMED;
+use drone_flight::blackbox::{BlackboxStorage, SECTOR_SIZE, Snapshot};
+use drone_flight::protocol::{
+    self, Action, MAX_FRAME, MAX_PAYLOAD, Parser, Request, Response, SYNC, Status,
+};
+use drone_flight::{mavlink, msp};
+use embassy_futures::join::{join, join4};
 use embassy_rp::bind_interrupts;
 use embassy_rp::peripherals::USB;
 use embassy_rp::usb::{Driver, InterruptHandler};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, watch::Watch};
+use embassy_time::Timer;
 use embassy_usb::class::cdc_acm::{CdcAcmClass, Receiver, Sender, State};
 use embassy_usb::{Builder, Config};
 use static_cell::StaticCell;
@@ -17,56 +28,185 @@ bind_interrupts!(struct Irqs {
 type UsbDriver = Driver<'static, USB>;
 type UsbDevice = embassy_usb::UsbDevice<'static, UsbDriver>;
 
-fn handle_data(data: &[u8]) {
-    #[cfg(feature = "telemetry")]
-    {
-        match Category::try_from(data[0]) {
-            Ok(cat) => {
-                crate::telemetry::TELE_CATEGORY
-                    .store(cat as u8, portable_atomic::Ordering::Relaxed);
+const PACKET_SIZE: usize = 64;
+type Packet = ([u8; PACKET_SIZE], usize);
+
+static RESPONSES: Channel<CriticalSectionRawMutex, Packet, 4> = Channel::new();
+
+/// Last flight loop state, published every tick for the ground station protocols.
+pub static SNAPSHOT: Watch<CriticalSectionRawMutex, Snapshot, 1> = Watch::new();
+
+/// One decoder per protocol sharing the app port, each frame is told apart by its start byte.
+#[derive(Default)]
+struct Parsers {
+    protocol: Parser,
+    mavlink: mavlink::Parser,
+    msp: msp::Parser,
+}
+
+impl Parsers {
+    fn is_idle(&self) -> bool {
+        self.protocol.is_idle() && self.mavlink.is_idle() && self.msp.is_idle()
+    }
+}
+
+/// Queues one encoded frame for the app port, it has to fit a single packet.
+pub async fn send_frame(frame: &[u8]) {
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[..frame.len()].copy_from_slice(frame);
+    RESPONSES.send((packet, frame.len())).await;
+}
+
+async fn send_response(response: &Response) {
+    let mut frame = [0u8; MAX_FRAME];
+    let len = response.encode(&mut frame);
+    send_frame(&frame[..len]).await;
+}
+
+async fn handle_request(request: Request) {
+    let armed = ARMED.load(portable_atomic::Ordering::Relaxed);
+    let (mut response, action) = PARAMS.lock(|params| {
+        protocol::handle(
+            &request,
+            &mut params.borrow_mut(),
+            armed,
+            env!("CARGO_PKG_VERSION"),
+        )
+    });
+
+    match action {
+        Action::None | Action::Reboot => {}
+        Action::Apply => PARAMS_CHANGED.signal(()),
+        Action::Save => {
+            if let Err(e) = params::current().save(&mut FlashParamStorage) {
+                log::error!("Params save failed: {:?}", e);
+                response = Response::new(request.cmd, request.seq, e.into());
+            } else {
+                log::info!("Params saved");
             }
-            Err(_) => {
-                crate::telemetry::TELE_CATEGORY
-                    .store(Category::None as u8, portable_atomic::Ordering::Relaxed);
+        }
+        Action::BlackboxInfo => {
+            let recording = RECORDING.load(portable_atomic::Ordering::Relaxed);
+            response
+                .push(&(BLACKBOX_SECTORS as u16).to_le_bytes())
+                .push(&(SECTOR_SIZE as u32).to_le_bytes())
+                .push(&[recording as u8]);
+        }
+        Action::BlackboxRead { offset, len } => {
+            let mut buf = [0u8; MAX_PAYLOAD];
+            let end = offset as usize + len;
+            let status = if end > BLACKBOX_SECTORS * SECTOR_SIZE {
+                Err(Status::OutOfRange)
+            } else {
+                FlashBlackboxStorage
+                    .read(offset, &mut buf[..len])
+                    .map_err(|_| Status::Storage)
+            };
+            match status {
+                Ok(()) => {
+                    response.push(&buf[..len]);
+                }
+                Err(status) => response = Response::new(request.cmd, request.seq, status),
             }
         }
+        Action::BlackboxErase => ERASE.signal(()),
+    }
+
+    send_response(&response).await;
+
+    if action == Action::Reboot {
+        log::warn!("Rebooting on request");
+        Timer::after_millis(100).await;
+        cortex_m::peripheral::SCB::sys_reset();
     }
 }
 
-async fn usb_log_task(class: CdcAcmClass<'static, UsbDriver>) {
-    embassy_usb_logger::with_class!(1024, log::LevelFilter::Info, class).await
+async fn handle_data(data: &[u8], parsers: &mut Parsers) {
+    // A lone byte outside of a frame still selects a single telemetry category at full rate
+    if parsers.is_idle() && ![SYNC, mavlink::STX, msp::PREAMBLE].contains(&data[0]) {
+        select_category(data[0]);
+        return;
+    }
+
+    for &byte in data {
+        // Frames never interleave, a started frame keeps its parser until it completes
+        let idle = parsers.is_idle();
+        if !parsers.mavlink.is_idle() || (idle && byte == mavlink::STX) {
+            if let Some(message) = parsers.mavlink.push(byte) {
+                crate::mavlink::handle_message(message).await;
+            }
+        } else if !parsers.msp.is_idle() || (idle && byte == msp::PREAMBLE) {
+            match parsers.msp.push(byte) {
+                Some(Ok(request)) => crate::msp::handle_request(request).await,
+                Some(Err(response)) => crate::msp::send_response(&response).await,
+                None => {}
+            }
+        } else {
+            match parsers.protocol.push(byte) {
+                Some(Ok(request)) => handle_request(request).await,
+                Some(Err(response)) => send_response(&response).await,
+                None => {}
+            }
+        }
+    }
 }
 
-async fn usb_telemetry_task(mut sender: Sender<'static, UsbDriver>) {
+fn select_category(_byte: u8) {
     #[cfg(feature = "telemetry")]
     {
-        let receiver = crate::telemetry::TELE_CHANNEL.receiver();
-        loop {
-            sender.wait_connection().await;
-            loop {
-                let frame = receiver.receive().await;
-                let len = frame[1] as usize;
-                let frame_len = 2 + len * 4;
-                if sender.write_packet(&frame[..frame_len]).await.is_err() {
-                    break;
-                }
-            }
+        drone_flight::telemetry::unsubscribe_all();
+        if let Ok(cat) = Category::try_from(_byte) {
+            drone_flight::telemetry::subscribe(cat as u8, 1);
         }
     }
-    #[cfg(not(feature = "telemetry"))]
+}
+
+async fn usb_log_task(class: CdcAcmClass<'static, UsbDriver>) {
+    embassy_usb_logger::with_class!(1024, log::LevelFilter::Info, class).await
+}
+
+#[cfg(feature = "telemetry")]
+async fn next_packet() -> Packet {
+    use embassy_futures::select::{Either, select};
+
+    let telemetry = drone_flight::telemetry::TELE_CHANNEL.receiver();
+    match select(RESPONSES.receive(), telemetry.receive()).await {
+        Either::First(packet) => packet,
+        Either::Second(frame) => {
+            let len = drone_flight::telemetry::frame_len(&frame);
+            let mut packet = [0u8; PACKET_SIZE];
+            packet[..len].copy_from_slice(&frame[..len]);
+            (packet, len)
+        }
+    }
+}
+
+#[cfg(not(feature = "telemetry"))]
+async fn next_packet() -> Packet {
+    RESPONSES.receive().await
+}
+
+async fn usb_write_task(mut sender: Sender<'static, UsbDriver>) {
     loop {
-        embassy_time::Timer::after_secs(3600).await;
+        sender.wait_connection().await;
+        loop {
+            let (packet, len) = next_packet().await;
+            if sender.write_packet(&packet[..len]).await.is_err() {
+                break;
+            }
+        }
     }
 }
 
 async fn usb_read_task(mut receiver: Receiver<'static, UsbDriver>) {
-    let mut buf = [0; 64];
+    let mut buf = [0; PACKET_SIZE];
+    let mut parsers = Parsers::default();
     loop {
         receiver.wait_connection().await;
 
         while let Ok(count) = receiver.read_packet(&mut buf).await {
             if count > 0 {
-                handle_data(&buf[..count]);
+                handle_data(&buf[..count], &mut parsers).await;
             }
         }
     }
@@ -116,6 +256,12 @@ pub async fn usb_setup(p: embassy_rp::Peri<'static, embassy_rp::peripherals::USB
     let usb = builder.build();
     let (app_sender, app_receiver) = app_class.split();
 
-    let app_task = join(usb_read_task(app_receiver), usb_telemetry_task(app_sender));
-    join3(usb_run_task(usb), usb_log_task(logger_class), app_task).await;
+    let app_task = join(usb_read_task(app_receiver), usb_write_task(app_sender));
+    join4(
+        usb_run_task(usb),
+        usb_log_task(logger_class),
+        app_task,
+        crate::mavlink::stream_task(),
+    )
+    .await;
 }